@@ -0,0 +1,329 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Computes `textDocument/foldingRange` results directly from the source
+//! text, so that folding is available before any build has finished.
+
+use crate::actions::run::LineIndex;
+use crate::lsp_data::{FoldingRange, FoldingRangeKind};
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Returns the folding ranges for blocks (items, `impl`s, `mod`s, function
+/// bodies etc.), block comments, runs of line comments and groups of imports
+/// in `text`, ordered by their start line.
+pub fn folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let mut ranges = block_ranges(text);
+    ranges.extend(line_ranges(text));
+    ranges.sort_by_key(|r| (r.start_line, r.end_line));
+    ranges
+}
+
+fn folding_range(start_line: u64, end_line: u64, kind: Option<FoldingRangeKind>) -> FoldingRange {
+    FoldingRange {
+        start_line,
+        start_character: None,
+        end_line,
+        end_character: None,
+        kind,
+    }
+}
+
+/// Finds ranges delimited by braces and block comments, skipping over string
+/// and character literals.
+fn block_ranges(text: &str) -> Vec<FoldingRange> {
+    let line_index = LineIndex::new(text);
+    let line_of = |offset: usize| u64::from(line_index.offset_to_position(offset).row.0);
+
+    let bytes = text.as_bytes();
+    let is_ident_byte = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    let mut ranges = vec![];
+    let mut open_braces = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = text[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let start = i;
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                let (start_line, end_line) = (line_of(start), line_of(i.saturating_sub(1)));
+                if end_line > start_line {
+                    ranges.push(folding_range(
+                        start_line,
+                        end_line,
+                        Some(FoldingRangeKind::Comment),
+                    ));
+                }
+                continue;
+            }
+            b'"' => {
+                i = skip_string(bytes, i + 1);
+                continue;
+            }
+            b'r' if i == 0
+                || !is_ident_byte(bytes[i - 1])
+                || (bytes[i - 1] == b'b' && (i == 1 || !is_ident_byte(bytes[i - 2]))) =>
+            {
+                if let Some(end) = skip_raw_string(text, i + 1) {
+                    i = end;
+                    continue;
+                }
+            }
+            b'\'' => {
+                if let Some(end) = skip_char(text, i + 1) {
+                    i = end;
+                    continue;
+                }
+            }
+            b'{' => open_braces.push(i),
+            b'}' => {
+                if let Some(open) = open_braces.pop() {
+                    let start_line = line_of(open);
+                    let end_pos = line_index.offset_to_position(i);
+                    let line_start = i - end_pos.col.0 as usize;
+                    // Keep a closing brace which starts its line visible
+                    let end_line = if text[line_start..i].trim().is_empty() {
+                        u64::from(end_pos.row.0).saturating_sub(1)
+                    } else {
+                        u64::from(end_pos.row.0)
+                    };
+                    if end_line > start_line {
+                        ranges.push(folding_range(start_line, end_line, None));
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    ranges
+}
+
+/// Returns the offset just past the closing quote of a string literal whose
+/// contents start at `start`.
+crate fn skip_string(bytes: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// If a raw string literal (`r"..."`, `r#"..."#`) begins at `start` (just
+/// after the `r`), returns the offset just past its end.
+crate fn skip_raw_string(text: &str, start: usize) -> Option<usize> {
+    let hashes = text[start..].bytes().take_while(|&b| b == b'#').count();
+    let body = start + hashes;
+    if text.as_bytes().get(body) != Some(&b'"') {
+        return None;
+    }
+
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    Some(
+        text[body + 1..]
+            .find(&terminator)
+            .map_or(text.len(), |n| body + 1 + n + terminator.len()),
+    )
+}
+
+/// If a character literal (but not a lifetime) begins at `start` (just after
+/// the opening quote), returns the offset just past its end.
+crate fn skip_char(text: &str, start: usize) -> Option<usize> {
+    match text[start..].chars().next() {
+        Some('\\') => Some(
+            text.get(start + 2..)
+                .and_then(|rest| rest.find('\''))
+                .map_or(text.len(), |n| start + 2 + n + 1),
+        ),
+        Some(c) if text.as_bytes().get(start + c.len_utf8()) == Some(&b'\'') => {
+            Some(start + c.len_utf8() + 1)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LineKind {
+    /// A line comment, with the kind of its prefix (`//`, `///` or `//!`)
+    Comment(&'static str),
+    Import,
+    Other,
+}
+
+/// Finds runs of consecutive line comments and imports.
+fn line_ranges(text: &str) -> Vec<FoldingRange> {
+    lazy_static! {
+        static ref IMPORT_RE: Regex =
+            Regex::new(r"^(pub(\([^)]*\))?\s+)?(use\s|extern\s+crate\s)").unwrap();
+    }
+
+    let mut in_import = false;
+    let kinds = text.lines().map(|line| {
+        let line = line.trim();
+        if in_import || IMPORT_RE.is_match(line) {
+            in_import = !line.ends_with(';');
+            LineKind::Import
+        } else if line.starts_with("//!") {
+            LineKind::Comment("//!")
+        } else if line.starts_with("///") && !line.starts_with("////") {
+            LineKind::Comment("///")
+        } else if line.starts_with("//") {
+            LineKind::Comment("//")
+        } else {
+            LineKind::Other
+        }
+    });
+
+    let mut ranges = vec![];
+    let mut row = 0;
+    for (kind, group) in &kinds.group_by(|kind| *kind) {
+        let start_line = row;
+        row += group.count() as u64;
+        let end_line = row - 1;
+
+        let kind = match kind {
+            LineKind::Comment(_) => FoldingRangeKind::Comment,
+            LineKind::Import => FoldingRangeKind::Imports,
+            LineKind::Other => continue,
+        };
+        if end_line > start_line {
+            ranges.push(folding_range(start_line, end_line, Some(kind)));
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ranges(text: &str) -> Vec<(u64, u64, Option<FoldingRangeKind>)> {
+        folding_ranges(text)
+            .into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_blocks() {
+        let text = "\
+fn foo() {
+    let x = 1;
+    if x > 0 {
+        bar();
+    }
+}
+
+struct Unit {}
+";
+        assert_eq!(ranges(text), vec![(0, 4, None), (2, 3, None)]);
+    }
+
+    #[test]
+    fn test_cfg_test_mod_and_impl() {
+        let text = "\
+impl Foo {
+    fn foo() {}
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_foo() {
+        assert!(true);
+    }
+}
+";
+        assert_eq!(
+            ranges(text),
+            vec![(0, 1, None), (5, 9, None), (7, 8, None)]
+        );
+    }
+
+    #[test]
+    fn test_literals_are_skipped() {
+        let text = r##"fn foo() {
+    let a = "{";
+    let b = '{';
+    let c = r#"}"#;
+    let d = b'}';
+    let e: &'static str = "\"}";
+}
+"##;
+        assert_eq!(ranges(text), vec![(0, 5, None)]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let text = "\
+//! Crate docs
+//! continued
+
+/// Item docs
+/// continued
+// plain comment
+fn foo() {}
+
+/* block
+   comment */
+";
+        assert_eq!(
+            ranges(text),
+            vec![
+                (0, 1, Some(FoldingRangeKind::Comment)),
+                (3, 4, Some(FoldingRangeKind::Comment)),
+                (8, 9, Some(FoldingRangeKind::Comment)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_imports() {
+        let text = "\
+use std::fmt;
+use std::io::{
+    self,
+    Read,
+};
+pub(crate) use foo::bar;
+
+use single::Import;
+";
+        assert_eq!(
+            ranges(text),
+            vec![(0, 5, Some(FoldingRangeKind::Imports)), (1, 3, None)]
+        );
+    }
+}
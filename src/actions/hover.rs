@@ -375,7 +375,7 @@ fn tooltip_mod(ctx: &InitActionContext, def: &Def, doc_url: Option<String>) -> V
     let the_type = the_type.replace("\\\\", "/");
     let the_type = the_type.replace("\\", "/");
 
-    let mod_path = if let Some(dir) = ctx.current_project.file_name() {
+    let mod_path = if let Some(dir) = ctx.project.root.file_name() {
         if Path::new(&the_type).starts_with(dir) {
             the_type.chars().skip(dir.len() + 1).collect()
         } else {
@@ -399,6 +399,17 @@ fn tooltip_function_method(
     debug!("tooltip_function_method: {}", def.name);
 
     let vfs = ctx.vfs.clone();
+
+    let the_type = function_signature(ctx, def);
+    let docs = def_docs(def, &vfs);
+    let context = None;
+
+    create_tooltip(the_type, doc_url, context, docs)
+}
+
+/// Returns the formatted declaration of a function or method `def`, as shown
+/// in hover tooltips.
+pub fn function_signature(ctx: &InitActionContext, def: &Def) -> String {
     let fmt_config = ctx.fmt_config();
     // We hover often so use the in-process one to speed things up
     let fmt = Rustfmt::Internal;
@@ -411,13 +422,9 @@ fn tooltip_function_method(
             .replace("->(", "-> (")
     };
 
-    let decl = def_decl(def, &vfs, the_type);
-
-    let the_type = format_method(fmt, &fmt_config, decl);
-    let docs = def_docs(def, &vfs);
-    let context = None;
+    let decl = def_decl(def, &ctx.vfs, the_type);
 
-    create_tooltip(the_type, doc_url, context, docs)
+    format_method(fmt, &fmt_config, decl)
 }
 
 fn tooltip_local_variable_decl(
@@ -489,7 +496,7 @@ fn empty_to_none(s: String) -> Option<String> {
 }
 
 /// Extract and process source documentation for the give `def`.
-fn def_docs(def: &Def, vfs: &Vfs) -> Option<String> {
+pub fn def_docs(def: &Def, vfs: &Vfs) -> Option<String> {
     let save_analysis_docs = || empty_to_none(def.docs.trim().into());
     extract_and_process_docs(&vfs, def.span.file.as_ref(), def.span.range.row_start)
         .or_else(save_analysis_docs)
@@ -657,7 +664,7 @@ fn racer_match_to_def(ctx: &InitActionContext, m: &racer::Match) -> Option<Def>
         // Tidy up the module path
         contextstr_path
             // Strip current project dir prefix
-            .strip_prefix(&ctx.current_project)
+            .strip_prefix(&ctx.project.root)
             // Strip home directory prefix
             .or_else(|_| contextstr_path.strip_prefix(&home))
             .ok()
@@ -713,7 +720,7 @@ fn racer_match_to_def(ctx: &InitActionContext, m: &racer::Match) -> Option<Def>
 
 /// Use racer to synthesize a `Def` for the given `span`. If no appropriate
 /// match is found with coordinates, `None` is returned.
-fn racer_def(ctx: &InitActionContext, span: &Span<ZeroIndexed>) -> Option<Def> {
+pub fn racer_def(ctx: &InitActionContext, span: &Span<ZeroIndexed>) -> Option<Def> {
     let vfs = ctx.vfs.clone();
     let file_path = &span.file;
 
@@ -830,7 +837,7 @@ fn format_object(rustfmt: Rustfmt, fmt_config: &FmtConfig, the_type: String) ->
 
 /// Formats a method or function. The original type is returned
 /// in the event of an error.
-fn format_method(rustfmt: Rustfmt, fmt_config: &FmtConfig, the_type: String) -> String {
+pub fn format_method(rustfmt: Rustfmt, fmt_config: &FmtConfig, the_type: String) -> String {
     trace!("format_method: {}", the_type);
     let the_type = the_type.trim().trim_right_matches(';').to_string();
 
@@ -877,7 +884,7 @@ pub fn tooltip(
     ctx: &InitActionContext,
     params: &TextDocumentPositionParams,
 ) -> Result<Vec<MarkedString>, ResponseError> {
-    let analysis = &ctx.analysis;
+    let analysis = &ctx.project.analysis;
 
     let hover_file_path = parse_file_path!(&params.text_document.uri, "hover")?;
     let hover_span = ctx.convert_pos_to_span(hover_file_path, params.position);
@@ -1161,6 +1168,10 @@ pub mod test {
             let client_caps = ClientCapabilities {
                 code_completion_has_snippet_support: true,
                 related_information_support: true,
+                hierarchical_document_symbol_support: true,
+                rename_file_support: true,
+                rename_prepare_support: true,
+                code_action_literal_support: true,
             };
             let mut config = config::Config::default();
             let cur_dir = env::current_dir().unwrap();
@@ -1181,14 +1192,16 @@ pub mod test {
                 vfs,
                 config,
                 client_caps,
-                project_dir.clone(),
+                vec![project_dir.clone()],
                 pid,
                 true,
+                false,
+                false,
             );
 
             let init_options = InitializationOptions::default();
             ctx.init(&init_options, output);
-            ctx.build(&project_dir, BuildPriority::Immediate, output);
+            ctx.build(&ctx.project, BuildPriority::Immediate, output);
 
             TooltipTestHarness {
                 ctx,
@@ -0,0 +1,161 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Code lenses showing how many references (and implementations) a definition
+//! has. Counting them is comparatively expensive, so `textDocument/codeLens`
+//! only says where the lenses go, and the counts are filled in by
+//! `codeLens/resolve` for the lenses the client actually displays.
+
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+
+use rls_analysis::DefKind;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use url::Url;
+
+use std::path::Path;
+
+/// The command a resolved lens runs when clicked, with the document URI, the
+/// lens position and the locations found as its arguments. Clients should open
+/// their peek or locations view for it (e.g., with VSCode's
+/// `editor.action.showReferences`). Only used if the client says it handles
+/// it with the `cmdShowReferences` initialization option.
+pub const SHOW_REFERENCES_COMMAND: &str = "rls.showReferences";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum LensKind {
+    References,
+    Implementations,
+}
+
+/// What a lens counts, stored in the lens' `data` until it's resolved.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct LensData {
+    kind: LensKind,
+    uri: Url,
+    position: Position,
+}
+
+/// Returns unresolved lenses for the structs, enums, traits and functions
+/// defined in `file`.
+pub fn reference_lenses(ctx: &InitActionContext, file: &Path, uri: &Url) -> Vec<CodeLens> {
+    let symbols = ctx
+        .project
+        .analysis
+        .symbols(file)
+        .unwrap_or_else(|_| vec![]);
+
+    let mut lenses = vec![];
+    for symbol in symbols {
+        let kinds: &[LensKind] = match symbol.kind {
+            DefKind::Struct | DefKind::Enum | DefKind::Union | DefKind::Trait => {
+                &[LensKind::References, LensKind::Implementations]
+            }
+            DefKind::Function | DefKind::Method => &[LensKind::References],
+            _ => continue,
+        };
+        let range = ls_util::rls_to_range(symbol.span.range);
+        if range.start == range.end {
+            continue;
+        }
+        for &kind in kinds {
+            let data = LensData {
+                kind,
+                uri: uri.clone(),
+                position: range.start,
+            };
+            lenses.push(CodeLens {
+                range,
+                command: None,
+                data: Some(serde_json::to_value(data).unwrap()),
+            });
+        }
+    }
+    lenses
+}
+
+fn lens_data(lens: &CodeLens) -> Option<LensData> {
+    lens.data
+        .clone()
+        .and_then(|data| serde_json::from_value(data).ok())
+}
+
+/// Returns the URI of the document a lens from `reference_lenses` is in.
+crate fn lens_uri(lens: &CodeLens) -> Option<Url> {
+    lens_data(lens).map(|data| data.uri)
+}
+
+/// Counts the references or implementations for a lens from
+/// `reference_lenses`.
+pub fn resolve(ctx: &InitActionContext, mut lens: CodeLens) -> Result<CodeLens, ResponseError> {
+    let data = lens_data(&lens).ok_or(ResponseError::Empty)?;
+    let file_path = parse_file_path!(&data.uri, "resolve_code_lens")?;
+    let span = ctx.convert_pos_to_span(file_path, data.position);
+    let analysis = &ctx.project.analysis;
+
+    let spans = match data.kind {
+        LensKind::References => analysis.find_all_refs(&span, false, false),
+        LensKind::Implementations => analysis.id(&span).and_then(|id| analysis.find_impls(id)),
+    }
+    .map_err(|_| ResponseError::Empty)?;
+    let locations: Vec<Location> = spans.iter().map(ls_util::rls_to_location).collect();
+
+    let title = match (data.kind, locations.len()) {
+        (LensKind::References, 1) => "1 reference".to_owned(),
+        (LensKind::References, n) => format!("{} references", n),
+        (LensKind::Implementations, 1) => "1 implementation".to_owned(),
+        (LensKind::Implementations, n) => format!("{} implementations", n),
+    };
+    // Without a command the client can run, the lens is just a label.
+    let (command, arguments) = if ctx.client_supports_cmd_show_references && !locations.is_empty() {
+        let arguments = vec![
+            serde_json::to_value(&data.uri).unwrap(),
+            serde_json::to_value(data.position).unwrap(),
+            serde_json::to_value(locations).unwrap(),
+        ];
+        (SHOW_REFERENCES_COMMAND.to_owned(), Some(arguments))
+    } else {
+        (String::new(), None)
+    };
+    lens.command = Some(Command {
+        title,
+        command,
+        arguments,
+    });
+    Ok(lens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lens_data_round_trips() {
+        let data = LensData {
+            kind: LensKind::Implementations,
+            uri: Url::parse("file:///src/lib.rs").unwrap(),
+            position: Position::new(3, 11),
+        };
+        let value = serde_json::to_value(&data).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "kind": "implementations",
+                "uri": "file:///src/lib.rs",
+                "position": { "line": 3, "character": 11 },
+            })
+        );
+        assert_eq!(serde_json::from_value::<LensData>(value).unwrap(), data);
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `rls lsif`, which builds a project and exports its index in the Language
+//! Server Index Format (LSIF), so that tools can offer goto definition, find
+//! references and hover without running the RLS.
+//!
+//! Every definition in the project's files gets a result set, linking its
+//! definition, references and hover results. References to definitions outside
+//! the project (e.g., in `std`) are not exported.
+
+use crate::actions::hover;
+use crate::actions::InitActionContext;
+use crate::config::Config;
+use crate::lsp_data::{ls_util, TextDocumentIdentifier, TextDocumentPositionParams};
+use crate::version;
+use crate::Span;
+
+use serde_json::{json, Value};
+use url::Url;
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const LSIF_VERSION: &str = "0.4.0";
+
+/// Run `rls lsif` with the arguments following `lsif`.
+pub fn run(args: &[String]) -> i32 {
+    let mut project_dir = PathBuf::from(".");
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => match args.next() {
+                Some(file) => output = Some(PathBuf::from(file)),
+                None => {
+                    println!("Expected a file after '--output'\n{}", help());
+                    return 101;
+                }
+            },
+            "--help" | "-h" => {
+                println!("{}", help());
+                return 0;
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unknown option '{}'\n{}", arg, help());
+                return 101;
+            }
+            _ => project_dir = PathBuf::from(arg),
+        }
+    }
+
+    let root = match env::current_dir().and_then(|cwd| cwd.join(project_dir).canonicalize()) {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("Could not find the project: {}", e);
+            return 101;
+        }
+    };
+    let ctx = match InitActionContext::build_standalone(root, Config::default()) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 101;
+        }
+    };
+
+    let written = match output {
+        Some(path) => File::create(path).and_then(|f| dump(&ctx, BufWriter::new(f))),
+        None => dump(&ctx, BufWriter::new(io::stdout())),
+    };
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Could not write the index: {}", e);
+            101
+        }
+    }
+}
+
+fn file_url(path: &Path) -> String {
+    Url::from_file_path(path)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| path.display().to_string())
+}
+
+/// Writes vertices and edges, one JSON object per line.
+struct Emitter<W: Write> {
+    out: W,
+    next_id: u64,
+}
+
+impl<W: Write> Emitter<W> {
+    fn emit(&mut self, kind: &str, label: &str, mut fields: Value) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        fields["id"] = id.into();
+        fields["type"] = kind.into();
+        fields["label"] = label.into();
+        writeln!(self.out, "{}", fields)?;
+        Ok(id)
+    }
+
+    fn vertex(&mut self, label: &str, fields: Value) -> io::Result<u64> {
+        self.emit("vertex", label, fields)
+    }
+
+    fn edge(&mut self, label: &str, out_v: u64, in_v: u64) -> io::Result<u64> {
+        self.emit("edge", label, json!({ "outV": out_v, "inV": in_v }))
+    }
+
+    fn edge_many(
+        &mut self,
+        label: &str,
+        out_v: u64,
+        in_vs: &[u64],
+        mut fields: Value,
+    ) -> io::Result<u64> {
+        fields["outV"] = out_v.into();
+        fields["inVs"] = in_vs.into();
+        self.emit("edge", label, fields)
+    }
+}
+
+/// A document in the dump, and the ranges it contains.
+struct Document {
+    id: u64,
+    ranges: Vec<u64>,
+}
+
+struct Dump<W: Write> {
+    emitter: Emitter<W>,
+    documents: BTreeMap<PathBuf, Document>,
+    // Range vertices, by file and (zero-indexed) start and end positions.
+    ranges: HashMap<(PathBuf, (u64, u64, u64, u64)), u64>,
+}
+
+impl<W: Write> Dump<W> {
+    /// Returns the vertex for `span`, and whether it was just created, or
+    /// `None` if the span is not in one of the dumped documents.
+    fn range(&mut self, span: &Span) -> io::Result<Option<(u64, bool)>> {
+        let document = match self.documents.get_mut(&span.file) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let range = ls_util::rls_to_range(span.range);
+        let key = (
+            span.file.clone(),
+            (
+                range.start.line,
+                range.start.character,
+                range.end.line,
+                range.end.character,
+            ),
+        );
+        if let Some(id) = self.ranges.get(&key) {
+            return Ok(Some((*id, false)));
+        }
+
+        let id = self
+            .emitter
+            .vertex("range", json!({ "start": range.start, "end": range.end }))?;
+        document.ranges.push(id);
+        self.ranges.insert(key, id);
+        Ok(Some((id, true)))
+    }
+
+    fn document_id(&self, file: &Path) -> u64 {
+        self.documents[file].id
+    }
+
+    // Emits the `item` edges from `result` to `ranges`, grouped by document.
+    fn items(
+        &mut self,
+        result: u64,
+        ranges: &[(PathBuf, u64)],
+        property: Option<&str>,
+    ) -> io::Result<()> {
+        let mut by_document: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (file, range) in ranges {
+            by_document
+                .entry(self.document_id(file))
+                .or_default()
+                .push(*range);
+        }
+        for (document, ranges) in by_document {
+            let mut fields = json!({ "document": document });
+            if let Some(property) = property {
+                fields["property"] = property.into();
+            }
+            self.emitter.edge_many("item", result, &ranges, fields)?;
+        }
+        Ok(())
+    }
+}
+
+fn dump<W: Write>(ctx: &InitActionContext, out: W) -> io::Result<()> {
+    let analysis = &ctx.project.analysis;
+    let mut files: Vec<PathBuf> = ctx
+        .project
+        .file_to_crates
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    files.sort();
+
+    let mut dump = Dump {
+        emitter: Emitter { out, next_id: 1 },
+        documents: BTreeMap::new(),
+        ranges: HashMap::new(),
+    };
+
+    dump.emitter.vertex(
+        "metaData",
+        json!({
+            "version": LSIF_VERSION,
+            "projectRoot": file_url(ctx.project.root()),
+            "positionEncoding": "utf-16",
+            "toolInfo": { "name": "rls", "version": version() },
+        }),
+    )?;
+    let project = dump.emitter.vertex("project", json!({ "kind": "rust" }))?;
+    for file in &files {
+        let id = dump.emitter.vertex(
+            "document",
+            json!({ "uri": file_url(file), "languageId": "rust" }),
+        )?;
+        dump.documents
+            .insert(file.clone(), Document { id, ranges: vec![] });
+    }
+
+    for file in &files {
+        for symbol in analysis.symbols(file).unwrap_or_else(|_| vec![]) {
+            let def = match dump.range(&symbol.span)? {
+                Some((id, true)) => id,
+                // Already part of another result set.
+                Some((_, false)) | None => continue,
+            };
+            let result_set = dump.emitter.vertex("resultSet", json!({}))?;
+            dump.emitter.edge("next", def, result_set)?;
+
+            let definitions = [(symbol.span.file.clone(), def)];
+            let definition_result = dump.emitter.vertex("definitionResult", json!({}))?;
+            dump.emitter
+                .edge("textDocument/definition", result_set, definition_result)?;
+            dump.items(definition_result, &definitions, None)?;
+
+            let mut references = vec![];
+            for span in analysis
+                .find_all_refs_by_id(symbol.id)
+                .unwrap_or_else(|_| vec![])
+            {
+                if let Some((range, created)) = dump.range(&span)? {
+                    if created {
+                        dump.emitter.edge("next", range, result_set)?;
+                    }
+                    references.push((span.file.clone(), range));
+                }
+            }
+            let reference_result = dump.emitter.vertex("referenceResult", json!({}))?;
+            dump.emitter
+                .edge("textDocument/references", result_set, reference_result)?;
+            dump.items(reference_result, &definitions, Some("definitions"))?;
+            if !references.is_empty() {
+                dump.items(reference_result, &references, Some("references"))?;
+            }
+
+            let range = ls_util::rls_to_range(symbol.span.range);
+            let params = TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier::new(Url::from_file_path(file).unwrap()),
+                position: range.start,
+            };
+            match hover::tooltip(ctx, &params) {
+                Ok(ref contents) if !contents.is_empty() => {
+                    let hover_result = dump
+                        .emitter
+                        .vertex("hoverResult", json!({ "result": { "contents": contents } }))?;
+                    dump.emitter
+                        .edge("textDocument/hover", result_set, hover_result)?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut documents = vec![];
+    for document in dump.documents.values() {
+        documents.push(document.id);
+        if !document.ranges.is_empty() {
+            dump.emitter
+                .edge_many("contains", document.id, &document.ranges, json!({}))?;
+        }
+    }
+    dump.emitter
+        .edge_many("contains", project, &documents, json!({}))?;
+    dump.emitter.out.flush()
+}
+
+fn help() -> &'static str {
+    r#"
+rls lsif [PROJECT_DIR] [--output FILE]
+
+Builds the project (the current directory by default) and writes its index in
+the Language Server Index Format to stdout, or to FILE.
+"#
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_data::Range;
+
+    #[test]
+    fn emitter_numbers_vertices_and_edges() {
+        let mut emitter = Emitter {
+            out: vec![],
+            next_id: 1,
+        };
+        let document = emitter
+            .vertex("document", json!({ "uri": "file:///lib.rs" }))
+            .unwrap();
+        let range = emitter
+            .vertex("range", json!({ "start": Range::default().start }))
+            .unwrap();
+        emitter
+            .edge_many("contains", document, &[range], json!({}))
+            .unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(emitter.out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["id"], 1);
+        assert_eq!(lines[0]["type"], "vertex");
+        assert_eq!(lines[0]["label"], "document");
+        assert_eq!(lines[2]["type"], "edge");
+        assert_eq!(lines[2]["outV"], 1);
+        assert_eq!(lines[2]["inVs"], json!([2]));
+    }
+}
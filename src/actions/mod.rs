@@ -26,11 +26,12 @@ use crate::actions::format::Rustfmt;
 use crate::actions::post_build::{AnalysisQueue, BuildResults, PostBuildHandler};
 use crate::actions::progress::{BuildDiagnosticsNotifier, BuildProgressNotifier};
 use crate::build::*;
-use crate::concurrency::{ConcurrentJob, Jobs};
+use crate::concurrency::{CancellationToken, ConcurrentJob, InFlightRequests, Jobs};
 use crate::lsp_data;
 use crate::lsp_data::*;
 use crate::project_model::{ProjectModel, RacerFallbackModel, RacerProjectModel};
-use crate::server::Output;
+use crate::server::{Notification, Output, RequestId};
+use languageserver_types::notification::PublishDiagnostics;
 
 use std::collections::{HashMap, HashSet};
 use std::io;
@@ -58,13 +59,20 @@ macro_rules! parse_file_path {
 }
 
 pub mod diagnostics;
+pub mod folding;
 pub mod format;
 pub mod hover;
+pub mod lens;
+pub mod lsif;
 pub mod notifications;
 pub mod post_build;
 pub mod progress;
+pub mod rename;
 pub mod requests;
 pub mod run;
+pub mod signature_help;
+pub mod symbols;
+pub mod tags;
 pub mod work_pool;
 
 /// Persistent context shared across all requests and notifications.
@@ -85,10 +93,11 @@ impl ActionContext {
         ActionContext::Uninit(UninitActionContext::new(analysis, vfs, config))
     }
 
-    /// Initialize this context, returns `Err(())` if it has already been initialized.
+    /// Initialize this context with the projects rooted in `project_roots`,
+    /// returns `Err(())` if it has already been initialized.
     pub fn init<O: Output>(
         &mut self,
-        current_project: PathBuf,
+        project_roots: Vec<PathBuf>,
         init_options: &InitializationOptions,
         client_capabilities: lsp_data::ClientCapabilities,
         out: &O,
@@ -100,9 +109,11 @@ impl ActionContext {
                     uninit.vfs.clone(),
                     uninit.config.clone(),
                     client_capabilities,
-                    current_project,
+                    project_roots,
                     uninit.pid,
                     init_options.cmd_run,
+                    init_options.cmd_debug,
+                    init_options.cmd_show_references,
                 );
                 ctx.init(init_options, out);
                 ctx
@@ -129,21 +140,109 @@ impl ActionContext {
     }
 }
 
+/// A project opened by the client, rooted in one of its workspace folders.
+/// Every project is configured, built and analyzed separately.
+#[derive(Clone)]
+pub struct Project {
+    root: PathBuf,
+    // Client configuration, with the unspecified options inferred for this
+    // project.
+    config: Arc<Mutex<Config>>,
+    analysis: Arc<AnalysisHost>,
+    model: Arc<Mutex<Option<Arc<ProjectModel>>>>,
+
+    previous_build_results: Arc<Mutex<BuildResults>>,
+    build_queue: BuildQueue,
+    file_to_crates: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
+}
+
+impl Project {
+    fn new(root: PathBuf, analysis: Arc<AnalysisHost>, vfs: Arc<Vfs>, config: Config) -> Project {
+        let config = Arc::new(Mutex::new(config));
+        let build_queue = BuildQueue::new(vfs, config.clone());
+        Project {
+            root,
+            config,
+            analysis,
+            model: Arc::default(),
+            previous_build_results: Arc::default(),
+            build_queue,
+            file_to_crates: Arc::default(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Joins the project configuration with the new client configuration.
+    fn update_config(&self, new_config: Config) {
+        let mut config = self.config.lock().unwrap();
+
+        // User may specify null (to be inferred) options, in which case
+        // we schedule further inference on a separate thread not to block
+        // the main thread
+        let needs_inference = new_config.needs_inference();
+        // In case of null options, we provide default values for now
+        config.update(new_config);
+        trace!("Updated config for {:?}: {:?}", self.root, *config);
+
+        if needs_inference {
+            self.infer_config();
+        }
+    }
+
+    /// Infers the unspecified configuration options for this project.
+    fn infer_config(&self) {
+        let root = self.root.clone();
+        let config = self.config.clone();
+        // Spawn another thread since we're shelling out to Cargo and this can
+        // cause a non-trivial amount of time due to disk access
+        thread::spawn(move || {
+            let mut config = config.lock().unwrap();
+            if let Err(e) = config.infer_defaults(&root) {
+                debug!(
+                    "Encountered an error while trying to infer config defaults: {:?}",
+                    e
+                );
+            }
+        });
+    }
+
+    fn invalidate_model(&self) {
+        *self.model.lock().unwrap() = None;
+    }
+
+    fn model(&self, vfs: &Vfs) -> Result<Arc<ProjectModel>, failure::Error> {
+        let cached: Option<Arc<ProjectModel>> = self.model.lock().unwrap().clone();
+        match cached {
+            Some(pm) => Ok(pm),
+            None => {
+                info!("loading cargo project model for {:?}", self.root);
+                let pm = ProjectModel::load(&self.root.join("Cargo.toml"), vfs)?;
+                let pm = Arc::new(pm);
+                *self.model.lock().unwrap() = Some(pm.clone());
+                Ok(pm)
+            }
+        }
+    }
+}
+
 /// Persistent context shared across all requests and actions after the RLS has
 /// been initialized.
 #[derive(Clone)]
 pub struct InitActionContext {
-    analysis: Arc<AnalysisHost>,
     vfs: Arc<Vfs>,
     // Queues analysis jobs so that we don't over-use the CPU.
     analysis_queue: Arc<AnalysisQueue>,
 
-    current_project: PathBuf,
-    project_model: Arc<Mutex<Option<Arc<ProjectModel>>>>,
+    // Projects rooted in the workspace folders opened by the client. There is
+    // always at least one.
+    projects: Arc<Mutex<Vec<Project>>>,
+    // The project owning the document of the request this (cloned) context
+    // was dispatched with.
+    project: Project,
 
-    previous_build_results: Arc<Mutex<BuildResults>>,
-    build_queue: BuildQueue,
-    file_to_crates: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     // Keep a record of builds/post-build tasks currently in flight so that
     // mutating actions can block until the data is ready.
     active_build_count: Arc<AtomicUsize>,
@@ -157,10 +256,17 @@ pub struct InitActionContext {
 
     prev_changes: Arc<Mutex<HashMap<PathBuf, u64>>>,
 
+    // Configuration as sent by the client, shared by all projects.
     config: Arc<Mutex<Config>>,
     jobs: Arc<Mutex<Jobs>>,
+    in_flight_requests: Arc<Mutex<InFlightRequests>>,
+    // Signalled if the client cancels the request this (cloned) context was
+    // dispatched with. Never signalled outside of request handling.
+    cancel_token: CancellationToken,
     client_capabilities: Arc<lsp_data::ClientCapabilities>,
     client_supports_cmd_run: bool,
+    client_supports_cmd_debug: bool,
+    client_supports_cmd_show_references: bool,
     /// Whether the server is performing cleanup (after having received
     /// 'shutdown' request), just before final 'exit' request.
     pub shut_down: Arc<AtomicBool>,
@@ -197,52 +303,162 @@ impl InitActionContext {
         vfs: Arc<Vfs>,
         config: Arc<Mutex<Config>>,
         client_capabilities: lsp_data::ClientCapabilities,
-        current_project: PathBuf,
+        project_roots: Vec<PathBuf>,
         pid: u32,
         client_supports_cmd_run: bool,
+        client_supports_cmd_debug: bool,
+        client_supports_cmd_show_references: bool,
     ) -> InitActionContext {
-        let build_queue = BuildQueue::new(vfs.clone(), config.clone());
+        assert!(!project_roots.is_empty(), "No project to initialize");
+        let user_config = config.lock().unwrap().clone();
+        let projects: Vec<_> = project_roots
+            .into_iter()
+            .enumerate()
+            .map(|(i, root)| {
+                // Every project needs its own analysis host, since it's reset
+                // when reloaded for a different project.
+                let analysis = if i == 0 {
+                    analysis.clone()
+                } else {
+                    Arc::new(AnalysisHost::new(rls_analysis::Target::Debug))
+                };
+                Project::new(root, analysis, vfs.clone(), user_config.clone())
+            })
+            .collect();
         let analysis_queue = Arc::new(AnalysisQueue::init());
         InitActionContext {
-            analysis,
             analysis_queue,
             vfs,
             config,
             jobs: Arc::default(),
-            current_project,
-            project_model: Arc::default(),
-            previous_build_results: Arc::default(),
-            build_queue,
-            file_to_crates: Arc::default(),
+            in_flight_requests: Arc::default(),
+            cancel_token: CancellationToken::new(),
+            project: projects[0].clone(),
+            projects: Arc::new(Mutex::new(projects)),
             active_build_count: Arc::new(AtomicUsize::new(0)),
             shown_cargo_error: Arc::new(AtomicBool::new(false)),
             quiescent: Arc::new(AtomicBool::new(false)),
             prev_changes: Arc::default(),
             client_capabilities: Arc::new(client_capabilities),
             client_supports_cmd_run,
+            client_supports_cmd_debug,
+            client_supports_cmd_show_references,
             shut_down: Arc::new(AtomicBool::new(false)),
             pid,
         }
     }
 
-    pub fn invalidate_project_model(&self) {
-        *self.project_model.lock().unwrap() = None;
+    /// Creates a context for the project in `root` outside of a language server
+    /// session (e.g., for `rls lsif`), builds it, and waits for its analysis
+    /// data to be loaded.
+    fn build_standalone(root: PathBuf, mut config: Config) -> Result<InitActionContext, String> {
+        config
+            .infer_defaults(&root)
+            .map_err(|e| format!("Could not load the project at {}: {}", root.display(), e))?;
+
+        let ctx = InitActionContext::new(
+            Arc::new(AnalysisHost::new(rls_analysis::Target::Debug)),
+            Arc::new(Vfs::new()),
+            Arc::new(Mutex::new(config)),
+            lsp_data::ClientCapabilities {
+                code_completion_has_snippet_support: false,
+                related_information_support: false,
+                hierarchical_document_symbol_support: false,
+                rename_file_support: false,
+                rename_prepare_support: false,
+                code_action_literal_support: false,
+            },
+            vec![root],
+            ::std::process::id(),
+            false,
+            false,
+            false,
+        );
+        ctx.init(&InitializationOptions::default(), &StderrOutput);
+        ctx.block_on_build();
+
+        if ctx.project.file_to_crates.lock().unwrap().is_empty() {
+            return Err("The project could not be built".to_owned());
+        }
+        Ok(ctx)
     }
 
-    pub fn project_model(&self) -> Result<Arc<ProjectModel>, failure::Error> {
-        let cached: Option<Arc<ProjectModel>> = self.project_model.lock().unwrap().clone();
-        match cached {
-            Some(pm) => Ok(pm),
-            None => {
-                info!("loading cargo project model");
-                let pm = ProjectModel::load(&self.current_project.join("Cargo.toml"), &self.vfs)?;
-                let pm = Arc::new(pm);
-                *self.project_model.lock().unwrap() = Some(pm.clone());
-                Ok(pm)
+    /// Returns all the projects opened by the client.
+    pub fn projects(&self) -> Vec<Project> {
+        self.projects.lock().unwrap().clone()
+    }
+
+    /// Returns the project owning `file`, i.e. the one with the innermost root
+    /// containing it. Files outside of every project (e.g. sources of
+    /// dependencies) are attributed to the first one.
+    pub fn project_for(&self, file: &Path) -> Project {
+        let projects = self.projects.lock().unwrap();
+        projects
+            .iter()
+            .filter(|project| file.starts_with(&project.root))
+            .max_by_key(|project| project.root.components().count())
+            .unwrap_or(&projects[0])
+            .clone()
+    }
+
+    /// Routes the request handled with this context to the project owning
+    /// `file`, or to the first project if there's no document involved.
+    pub fn select_project(&mut self, file: Option<&Path>) {
+        self.project = match file {
+            Some(file) => self.project_for(file),
+            None => self.projects.lock().unwrap()[0].clone(),
+        };
+    }
+
+    /// Opens and builds a project rooted in `root`, unless it's open already.
+    fn add_project<O: Output>(&self, root: PathBuf, out: &O) {
+        let project = {
+            let mut projects = self.projects.lock().unwrap();
+            if projects.iter().any(|project| project.root == root) {
+                return;
             }
+
+            info!("adding project {:?}", root);
+            let config = self.config.lock().unwrap().clone();
+            let analysis = Arc::new(AnalysisHost::new(rls_analysis::Target::Debug));
+            let project = Project::new(root, analysis, self.vfs.clone(), config);
+            projects.push(project.clone());
+            project
+        };
+
+        project.infer_config();
+        self.build(&project, BuildPriority::Cargo, out);
+    }
+
+    /// Closes the project rooted in `root` and clears its diagnostics. The
+    /// last remaining project is never closed.
+    fn remove_project<O: Output>(&self, root: &Path, out: &O) {
+        let project = {
+            let mut projects = self.projects.lock().unwrap();
+            match projects.iter().position(|project| project.root == root) {
+                Some(_) if projects.len() == 1 => {
+                    debug!("not removing the last project {:?}", root);
+                    return;
+                }
+                Some(idx) => projects.remove(idx),
+                None => return,
+            }
+        };
+
+        info!("removing project {:?}", root);
+        for file in project.previous_build_results.lock().unwrap().keys() {
+            let params = PublishDiagnosticsParams {
+                uri: Url::from_file_path(file).unwrap(),
+                diagnostics: vec![],
+            };
+            out.notify(Notification::<PublishDiagnostics>::new(params));
         }
     }
 
+    pub fn project_model(&self) -> Result<Arc<ProjectModel>, failure::Error> {
+        self.project.model(&self.vfs)
+    }
+
     pub fn racer_cache(&self) -> racer::FileCache {
         struct RacerVfs(Arc<Vfs>);
         impl racer::FileLoader for RacerVfs {
@@ -281,17 +497,17 @@ impl InitActionContext {
             .unwrap()
             .rustfmt_path
             .clone()
-            .map(|path| (path, self.current_project.clone()));
+            .map(|path| (path, self.project.root.clone()));
 
         Rustfmt::from(rustfmt)
     }
 
     fn fmt_config(&self) -> FmtConfig {
-        FmtConfig::from(&self.current_project)
+        FmtConfig::from(&self.project.root)
     }
 
     fn file_edition(&self, file: PathBuf) -> Option<Edition> {
-        let files_to_crates = self.file_to_crates.lock().unwrap();
+        let files_to_crates = self.project.file_to_crates.lock().unwrap();
 
         let editions: HashSet<_> = files_to_crates
             .get(&file)?
@@ -307,37 +523,27 @@ impl InitActionContext {
     }
 
     fn init<O: Output>(&self, init_options: &InitializationOptions, out: &O) {
-        let current_project = self.current_project.clone();
-        let config = self.config.clone();
-        // Spawn another thread since we're shelling out to Cargo and this can
-        // cause a non-trivial amount of time due to disk access
-        thread::spawn(move || {
-            let mut config = config.lock().unwrap();
-            if let Err(e) = config.infer_defaults(&current_project) {
-                debug!(
-                    "Encountered an error while trying to infer config defaults: {:?}",
-                    e
-                );
-            }
-        });
+        for project in self.projects() {
+            project.infer_config();
+        }
 
         if !init_options.omit_init_build {
-            self.build_current_project(BuildPriority::Cargo, out);
+            self.build_all_projects(BuildPriority::Cargo, out);
         }
     }
 
-    fn build<O: Output>(&self, project_path: &Path, priority: BuildPriority, out: &O) {
+    fn build<O: Output>(&self, project: &Project, priority: BuildPriority, out: &O) {
         let (job, token) = ConcurrentJob::new();
         self.add_job(job);
 
         let pbh = {
-            let config = self.config.lock().unwrap();
+            let config = project.config.lock().unwrap();
             PostBuildHandler {
-                analysis: self.analysis.clone(),
+                analysis: project.analysis.clone(),
                 analysis_queue: self.analysis_queue.clone(),
-                previous_build_results: self.previous_build_results.clone(),
-                file_to_crates: self.file_to_crates.clone(),
-                project_path: project_path.to_owned(),
+                previous_build_results: project.previous_build_results.clone(),
+                file_to_crates: project.file_to_crates.clone(),
+                project_path: project.root.clone(),
                 show_warnings: config.show_warnings,
                 related_information_support: self.client_capabilities.related_information_support,
                 shown_cargo_error: self.shown_cargo_error.clone(),
@@ -352,12 +558,15 @@ impl InitActionContext {
         let notifier = Box::new(BuildProgressNotifier::new(out.clone()));
 
         self.active_build_count.fetch_add(1, Ordering::SeqCst);
-        self.build_queue
-            .request_build(project_path, priority, notifier, pbh);
+        project
+            .build_queue
+            .request_build(&project.root, priority, notifier, pbh);
     }
 
-    fn build_current_project<O: Output>(&self, priority: BuildPriority, out: &O) {
-        self.build(&self.current_project, priority, out);
+    fn build_all_projects<O: Output>(&self, priority: BuildPriority, out: &O) {
+        for project in self.projects() {
+            self.build(&project, priority, out);
+        }
     }
 
     pub fn add_job(&self, job: ConcurrentJob) {
@@ -368,14 +577,42 @@ impl InitActionContext {
         self.jobs.lock().unwrap().wait_for_all();
     }
 
+    /// Registers the request `id` as in flight and associates this context
+    /// with it, so that its handler can poll `request_cancelled`.
+    pub fn add_request(&mut self, id: RequestId) {
+        self.cancel_token = self.in_flight_requests.lock().unwrap().add(id);
+    }
+
+    /// Marks the request `id` as responded to.
+    pub fn finish_request(&self, id: &RequestId) {
+        self.in_flight_requests.lock().unwrap().finish(id);
+    }
+
+    /// Signals cancellation to the handler of the in-flight request `id`.
+    pub fn cancel_request(&self, id: &RequestId) {
+        self.in_flight_requests.lock().unwrap().cancel(id);
+    }
+
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel_token.clone()
+    }
+
+    /// Returns true if the client has cancelled the request being handled
+    /// with this context.
+    pub fn request_cancelled(&self) -> bool {
+        self.cancel_token.is_cancelled()
+    }
+
     /// Block until any builds and analysis tasks are complete.
     fn block_on_build(&self) {
-        self.build_queue.block_on_build();
+        for project in self.projects() {
+            project.build_queue.block_on_build();
+        }
     }
 
     /// Returns true if there are no builds pending or in progress.
     fn build_ready(&self) -> bool {
-        self.build_queue.build_ready()
+        self.projects().iter().all(|project| project.build_queue.build_ready())
     }
 
     /// Returns true if there are no builds or post-build (analysis) tasks pending
@@ -486,6 +723,26 @@ fn find_word_at_pos(line: &str, pos: Column) -> (Column, Column) {
     )
 }
 
+/// Output for a context without a client, which reports the errors (e.g., from
+/// Cargo) the server would show the user and ignores everything else.
+#[derive(Clone)]
+struct StderrOutput;
+
+impl Output for StderrOutput {
+    fn response(&self, output: String) {
+        let msg: serde_json::Value = serde_json::from_str(&output).unwrap_or_default();
+        if msg["method"] == "window/showMessage" {
+            if let Some(message) = msg["params"]["message"].as_str() {
+                eprintln!("{}", message);
+            }
+        }
+    }
+
+    fn provide_id(&self) -> RequestId {
+        RequestId::Num(0)
+    }
+}
+
 /// Client file-watching request / filtering logic
 /// We want to watch workspace 'Cargo.toml', root 'Cargo.lock' & the root 'target' dir
 pub struct FileWatch {
@@ -495,8 +752,8 @@ pub struct FileWatch {
 
 impl FileWatch {
     /// Construct a new `FileWatch`.
-    pub fn new(ctx: &InitActionContext) -> Self {
-        Self::from_project_root(ctx.current_project.clone())
+    pub fn new(project: &Project) -> Self {
+        Self::from_project_root(project.root.clone())
     }
 
     pub fn from_project_root(root: PathBuf) -> Self {
@@ -508,6 +765,11 @@ impl FileWatch {
 
     /// Returns json config for desired file watches
     pub fn watchers_config(&self) -> serde_json::Value {
+        json!({ "watchers": self.watchers() })
+    }
+
+    /// Returns the desired file watches
+    pub fn watchers(&self) -> Vec<FileSystemWatcher> {
         fn watcher(pat: String) -> FileSystemWatcher {
             FileSystemWatcher {
                 glob_pattern: pat,
@@ -538,7 +800,7 @@ impl FileWatch {
             watchers.push(watcher(entry.path().display().to_string()));
         }
 
-        json!({ "watchers": watchers })
+        watchers
     }
 
     /// Returns if a file change is relevant to the files we actually wanted to watch
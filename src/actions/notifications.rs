@@ -13,7 +13,7 @@
 use crate::actions::{FileWatch, InitActionContext, VersionOrdering};
 use crate::config::Config;
 use crate::Span;
-use log::{debug, trace, warn};
+use log::{trace, warn};
 use rls_vfs::{Change, VfsSpan};
 use serde::de::Error;
 use serde::Deserialize;
@@ -23,18 +23,42 @@ use std::sync::atomic::Ordering;
 use crate::build::*;
 use crate::lsp_data::request::{RangeFormatting, RegisterCapability, UnregisterCapability};
 use crate::lsp_data::*;
-use crate::server::Request;
+use crate::server::{Request, RequestId};
 use languageserver_types::notification::ShowMessage;
 
 pub use crate::lsp_data::notification::{
     Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
-    DidOpenTextDocument, DidSaveTextDocument, Initialized,
+    DidChangeWorkspaceFolders, DidOpenTextDocument, DidSaveTextDocument, Initialized,
 };
 
 use crate::server::{BlockingNotificationAction, Notification, Output};
 
+use serde_json::json;
 use std::thread;
 
+const WATCH_ID: &str = "rls-watch";
+
+/// Registers the file watches of every project.
+fn register_file_watches<O: Output>(ctx: &InitActionContext, out: &O) {
+    let watchers: Vec<_> = ctx
+        .projects()
+        .iter()
+        .flat_map(|project| FileWatch::new(project).watchers())
+        .collect();
+
+    let id = out.provide_id();
+    let params = RegistrationParams {
+        registrations: vec![Registration {
+            id: WATCH_ID.to_owned(),
+            method: <DidChangeWatchedFiles as LSPNotification>::METHOD.to_owned(),
+            register_options: Some(json!({ "watchers": watchers })),
+        }],
+    };
+
+    let request = Request::<RegisterCapability>::new(id, params);
+    out.request(request);
+}
+
 impl BlockingNotificationAction for Initialized {
     // Respond to the `initialized` notification. We take this opportunity to
     // dynamically register some options.
@@ -43,19 +67,7 @@ impl BlockingNotificationAction for Initialized {
         ctx: &mut InitActionContext,
         out: O,
     ) -> Result<(), ()> {
-        const WATCH_ID: &str = "rls-watch";
-
-        let id = out.provide_id();
-        let params = RegistrationParams {
-            registrations: vec![Registration {
-                id: WATCH_ID.to_owned(),
-                method: <DidChangeWatchedFiles as LSPNotification>::METHOD.to_owned(),
-                register_options: Some(FileWatch::new(&ctx).watchers_config()),
-            }],
-        };
-
-        let request = Request::<RegisterCapability>::new(id, params);
-        out.request(request);
+        register_file_watches(ctx, &out);
         Ok(())
     }
 }
@@ -131,10 +143,11 @@ impl BlockingNotificationAction for DidChangeTextDocument {
             .on_changes(&changes)
             .expect("error committing to VFS");
 
-        ctx.build_queue.mark_file_dirty(file_path, version_num);
+        let project = ctx.project_for(&file_path);
+        project.build_queue.mark_file_dirty(file_path, version_num);
 
         if !ctx.config.lock().unwrap().build_on_save {
-            ctx.build_current_project(BuildPriority::Normal, &out);
+            ctx.build(&project, BuildPriority::Normal, &out);
         }
         Ok(())
     }
@@ -142,11 +155,17 @@ impl BlockingNotificationAction for DidChangeTextDocument {
 
 impl BlockingNotificationAction for Cancel {
     fn handle<O: Output>(
-        _params: CancelParams,
-        _ctx: &mut InitActionContext,
+        params: CancelParams,
+        ctx: &mut InitActionContext,
         _out: O,
     ) -> Result<(), ()> {
-        // Nothing to do.
+        let id = match params.id {
+            NumberOrString::Number(n) => RequestId::Num(n),
+            NumberOrString::String(s) => RequestId::Str(s),
+        };
+        trace!("cancel: {}", id);
+        // The dispatcher responds to the cancelled request itself.
+        ctx.cancel_request(&id);
         Ok(())
     }
 }
@@ -182,36 +201,17 @@ impl BlockingNotificationAction for DidChangeConfiguration {
 
         {
             let mut config = ctx.config.lock().unwrap();
-
-            // User may specify null (to be inferred) options, in which case
-            // we schedule further inference on a separate thread not to block
-            // the main thread
-            let needs_inference = new_config.needs_inference();
-            // In case of null options, we provide default values for now
-            config.update(new_config);
+            config.update(new_config.clone());
             trace!("Updated config: {:?}", *config);
-
-            if needs_inference {
-                let project_dir = ctx.current_project.clone();
-                let config = ctx.config.clone();
-                // Will lock and access Config just outside the current scope
-                thread::spawn(move || {
-                    let mut config = config.lock().unwrap();
-                    if let Err(e) = config.infer_defaults(&project_dir) {
-                        debug!(
-                            "Encountered an error while trying to infer config \
-                             defaults: {:?}",
-                            e
-                        );
-                    }
-                });
-            }
+        }
+        for project in ctx.projects() {
+            project.update_config(new_config.clone());
         }
         // We do a clean build so that if we've changed any relevant options
         // for Cargo, we'll notice them. But if nothing relevant changes
         // then we don't do unnecessary building (i.e., we don't delete
         // artifacts on disk).
-        ctx.build_current_project(BuildPriority::Cargo, &out);
+        ctx.build_all_projects(BuildPriority::Cargo, &out);
 
         const RANGE_FORMATTING_ID: &str = "rls-range-formatting";
         // FIXME should handle the response
@@ -253,7 +253,7 @@ impl BlockingNotificationAction for DidSaveTextDocument {
         ctx.vfs.file_saved(&file_path).unwrap();
 
         if ctx.config.lock().unwrap().build_on_save {
-            ctx.build_current_project(BuildPriority::Normal, &out);
+            ctx.build(&ctx.project_for(&file_path), BuildPriority::Normal, &out);
         }
 
         Ok(())
@@ -268,13 +268,51 @@ impl BlockingNotificationAction for DidChangeWatchedFiles {
     ) -> Result<(), ()> {
         trace!("on_cargo_change: thread: {:?}", thread::current().id());
 
-        let file_watch = FileWatch::new(&ctx);
+        for project in ctx.projects() {
+            let file_watch = FileWatch::new(&project);
 
-        if params.changes.iter().any(|c| file_watch.is_relevant(c)) {
-            ctx.build_current_project(BuildPriority::Cargo, &out);
-            ctx.invalidate_project_model();
+            if params.changes.iter().any(|c| file_watch.is_relevant(c)) {
+                ctx.build(&project, BuildPriority::Cargo, &out);
+                project.invalidate_model();
+            }
         }
 
         Ok(())
     }
 }
+
+impl BlockingNotificationAction for DidChangeWorkspaceFolders {
+    fn handle<O: Output>(
+        params: DidChangeWorkspaceFoldersParams,
+        ctx: &mut InitActionContext,
+        out: O,
+    ) -> Result<(), ()> {
+        trace!("workspace folders change: {:?}", params.event);
+        let event = params.event;
+
+        // Add new folders first, so that we never run out of projects
+        for folder in &event.added {
+            if let Ok(root) = parse_file_path!(&folder.uri, "workspace_folders") {
+                ctx.add_project(root, &out);
+            }
+        }
+        for folder in &event.removed {
+            if let Ok(root) = parse_file_path!(&folder.uri, "workspace_folders") {
+                ctx.remove_project(&root, &out);
+            }
+        }
+
+        // Watch the files of the current set of projects instead
+        let id = out.provide_id();
+        let params = UnregistrationParams {
+            unregisterations: vec![Unregistration {
+                id: WATCH_ID.to_owned(),
+                method: <DidChangeWatchedFiles as LSPNotification>::METHOD.to_owned(),
+            }],
+        };
+        out.request(Request::<UnregisterCapability>::new(id, params));
+        register_file_watches(ctx, &out);
+
+        Ok(())
+    }
+}
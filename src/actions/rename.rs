@@ -0,0 +1,189 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Checks that the symbol at some position can be renamed, and that a new name
+//! is valid for it, before any edits are made (by `textDocument/rename`) or
+//! the user is even asked for a new name (by `textDocument/prepareRename`).
+//!
+//! Errors are descriptions of why the rename is not possible, to be shown to
+//! the user.
+
+use crate::actions::InitActionContext;
+use crate::build::Edition;
+use crate::lsp_data::ls_util;
+use crate::Span;
+
+use rls_analysis::{Def, DefKind, Id};
+
+/// The symbol a rename would change.
+pub struct RenameTarget {
+    pub id: Id,
+    pub def: Def,
+    /// The definition and all the references to rename.
+    pub refs: Vec<Span>,
+    /// The occurrence of the symbol the rename was requested at.
+    pub ident: Span,
+}
+
+/// Finds the symbol named at `span` and everything to rename with it.
+pub fn rename_target(ctx: &InitActionContext, span: &Span) -> Result<RenameTarget, String> {
+    let analysis = &ctx.project.analysis;
+
+    // Only symbols defined in the crates being built are local.
+    let id = analysis
+        .crate_local_id(span)
+        .map_err(|_| "no information for symbol".to_owned())?;
+    let def = analysis
+        .get_def(id)
+        .map_err(|_| "no definition for symbol".to_owned())?;
+    if def.name.is_empty() {
+        return Err("cannot rename crates".to_owned());
+    }
+    if def.name == "self" || def.name == "Self" {
+        return Err(format!("cannot rename {}", def.name));
+    }
+    if !def.span.file.starts_with(ctx.project.root()) {
+        return Err(format!(
+            "cannot rename `{}`, which is defined outside the project",
+            def.name
+        ));
+    }
+
+    let refs = analysis
+        .find_all_refs(span, true, true)
+        .map_err(|_| "error finding references".to_owned())?;
+    if refs.is_empty() {
+        return Err("RLS found nothing to rename - possibly due to multiple defs".to_owned());
+    }
+
+    let range = ls_util::rls_to_range(span.range);
+    let ident = refs
+        .iter()
+        .find(|r| {
+            let r_range = ls_util::rls_to_range(r.range);
+            r.file == span.file && r_range.start <= range.start && range.end <= r_range.end
+        })
+        .cloned()
+        .unwrap_or_else(|| span.clone());
+    Ok(RenameTarget {
+        id,
+        def,
+        refs,
+        ident,
+    })
+}
+
+/// Checks that `new_name` is a valid name for `target`, and doesn't clash with
+/// another definition in the same scope.
+pub fn check_new_name(
+    ctx: &InitActionContext,
+    target: &RenameTarget,
+    new_name: &str,
+) -> Result<(), String> {
+    // If the edition is unknown, check for the keywords of both.
+    let edition = ctx
+        .file_edition(target.def.span.file.clone())
+        .unwrap_or(Edition::Edition2018);
+    check_identifier(new_name, edition)?;
+
+    // Locals can shadow each other, and aren't always nested under the scope
+    // they're declared in.
+    if target.def.kind == DefKind::Local {
+        return Ok(());
+    }
+    let name = new_name.trim_start_matches("r#");
+    let symbols = ctx
+        .project
+        .analysis
+        .symbols(&target.def.span.file)
+        .unwrap_or_else(|_| vec![]);
+    match symbols
+        .iter()
+        .find(|s| s.id != target.id && s.parent == target.def.parent && s.name == name)
+    {
+        Some(existing) => Err(format!(
+            "`{}` is already defined in this scope, at line {}",
+            name,
+            existing.span.range.row_start.one_indexed().0
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks that `name` is an identifier (possibly a raw one) which isn't a
+/// keyword in `edition`.
+fn check_identifier(name: &str, edition: Edition) -> Result<(), String> {
+    let is_raw = name.starts_with("r#");
+    let ident = if is_raw { &name[2..] } else { name };
+
+    let mut chars = ident.chars();
+    let valid = match chars.next() {
+        Some(c) if c == '_' || c.is_alphabetic() => chars.all(|c| c == '_' || c.is_alphanumeric()),
+        _ => false,
+    };
+    if !valid || ident == "_" {
+        return Err(format!("`{}` is not a valid identifier", name));
+    }
+
+    // These are path segments rather than ordinary keywords, and can't be
+    // used as raw identifiers either.
+    if ["crate", "self", "Self", "super"].contains(&ident) {
+        return Err(format!("`{}` is a reserved name", ident));
+    }
+    if !is_raw && is_keyword(ident, edition) {
+        return Err(format!(
+            "`{0}` is a keyword, use the raw identifier `r#{0}` instead",
+            ident
+        ));
+    }
+    Ok(())
+}
+
+fn is_keyword(ident: &str, edition: Edition) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "as", "break", "const", "continue", "else", "enum", "extern", "false", "fn", "for", "if",
+        "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+        // Reserved for future use
+        "abstract", "become", "box", "do", "final", "macro", "override", "priv", "typeof",
+        "unsized", "virtual", "yield",
+    ];
+    const KEYWORDS_2018: &[&str] = &["async", "await", "dyn", "try"];
+
+    KEYWORDS.contains(&ident) || (edition == Edition::Edition2018 && KEYWORDS_2018.contains(&ident))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers() {
+        assert!(check_identifier("foo", Edition::Edition2018).is_ok());
+        assert!(check_identifier("_foo_1", Edition::Edition2018).is_ok());
+        assert!(check_identifier("r#match", Edition::Edition2018).is_ok());
+        assert!(check_identifier("async", Edition::Edition2015).is_ok());
+
+        assert!(check_identifier("", Edition::Edition2018).is_err());
+        assert!(check_identifier("_", Edition::Edition2018).is_err());
+        assert!(check_identifier("1foo", Edition::Edition2018).is_err());
+        assert!(check_identifier("foo-bar", Edition::Edition2018).is_err());
+        assert!(check_identifier("foo::bar", Edition::Edition2018).is_err());
+        assert!(check_identifier("r#self", Edition::Edition2018).is_err());
+        assert_eq!(
+            check_identifier("async", Edition::Edition2018),
+            Err("`async` is a keyword, use the raw identifier `r#async` instead".to_owned())
+        );
+        assert_eq!(
+            check_identifier("super", Edition::Edition2015),
+            Err("`super` is a reserved name".to_owned())
+        );
+    }
+}
@@ -12,6 +12,7 @@
 
 use crate::actions::InitActionContext;
 use itertools::Itertools;
+use lazy_static::lazy_static;
 use log::{debug, trace, warn};
 use racer;
 use rls_data as data;
@@ -22,8 +23,14 @@ use serde_derive::{Deserialize, Serialize};
 use serde_json;
 use url::Url;
 
+use crate::actions::diagnostics::Suggestion;
+use crate::actions::folding;
 use crate::actions::hover;
-use crate::actions::run::collect_run_actions;
+use crate::actions::lens;
+use crate::actions::rename;
+use crate::actions::run::{collect_run_actions, CmdKind};
+use crate::actions::signature_help;
+use crate::actions::symbols::{document_symbols, workspace_symbols};
 use crate::actions::work_pool;
 use crate::actions::work_pool::WorkDescription;
 use crate::build::Edition;
@@ -32,19 +39,24 @@ use crate::lsp_data::*;
 use crate::server;
 use crate::server::{Ack, Output, Request, RequestAction, ResponseError, ResponseWithMessage};
 use jsonrpc_core::types::ErrorCode;
-use rls_analysis::SymbolQuery;
+use regex::Regex;
+use rls_analysis::{AnalysisHost, SymbolQuery};
 
 use crate::lsp_data::request::ApplyWorkspaceEdit;
+pub use crate::lsp_data::PrepareRename;
 pub use crate::lsp_data::request::{
-    CodeActionRequest as CodeAction, CodeLensRequest, Completion,
-    DocumentHighlightRequest as DocumentHighlight, DocumentSymbolRequest as Symbols,
-    ExecuteCommand, Formatting, GotoDefinition as Definition, GotoImplementation as Implementation,
+    CodeActionRequest as CodeAction, CodeLensRequest, CodeLensResolve, Completion,
+    DocumentHighlightRequest as DocumentHighlight, DocumentSymbolRequest as Symbols, ExecuteCommand,
+    FoldingRangeRequest, Formatting, GotoDefinition as Definition,
+    GotoImplementation as Implementation, GotoTypeDefinition as TypeDefinition,
     HoverRequest as Hover, RangeFormatting, References, Rename,
-    ResolveCompletionItem as ResolveCompletion, WorkspaceSymbol,
+    ResolveCompletionItem as ResolveCompletion, SignatureHelpRequest as SignatureHelp,
+    WorkspaceSymbol,
 };
 
 use std::collections::HashMap;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 
 /// Represent the result of a deglob action for a single wildcard import.
@@ -70,47 +82,39 @@ impl RequestAction for WorkspaceSymbol {
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
-        let analysis = ctx.analysis;
-        let query = SymbolQuery::subsequence(&params.query).limit(512);
-        let defs = analysis.query_defs(query).unwrap_or_else(|_| vec![]);
-
-        Ok(defs
-            .into_iter()
-            // Sometimes analysis will return duplicate symbols
-            // for the same location, fix that up.
-            .unique_by(|d| (d.span.clone(), d.name.clone()))
-            .map(|d| SymbolInformation {
-                name: d.name,
-                kind: source_kind_from_def_kind(d.kind),
-                location: ls_util::rls_to_location(&d.span),
-                container_name: d
-                    .parent
-                    .and_then(|id| analysis.get_def(id).ok())
-                    .map(|parent| parent.name),
-                deprecated: None,
-            })
-            .collect())
+        Ok(workspace_symbols(&ctx, &params.query))
     }
 }
 
 impl RequestAction for Symbols {
-    type Response = Vec<SymbolInformation>;
+    type Response = DocumentSymbolResponse;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
 
     fn fallback_response() -> Result<Self::Response, ResponseError> {
-        Ok(vec![])
+        Ok(DocumentSymbolResponse::Flat(vec![]))
     }
 
     fn handle(
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
-        let analysis = ctx.analysis;
+        let analysis = ctx.project.analysis;
 
         let file_path = parse_file_path!(&params.text_document.uri, "symbols")?;
 
+        if ctx.client_capabilities.hierarchical_document_symbol_support {
+            if let Ok(FileContents::Text(text)) = ctx.vfs.load_file(&file_path) {
+                let symbols = document_symbols(&analysis, &file_path, &text);
+                return Ok(DocumentSymbolResponse::Nested(symbols));
+            }
+        }
+
         let symbols = analysis.symbols(&file_path).unwrap_or_else(|_| vec![]);
 
-        Ok(symbols
+        let symbols = symbols
             .into_iter()
             .filter(|s| {
                 let range = ls_util::rls_to_range(s.span.range);
@@ -126,13 +130,19 @@ impl RequestAction for Symbols {
                     .map(|parent| parent.name),
                 deprecated: None,
             })
-            .collect())
+            .collect();
+
+        Ok(DocumentSymbolResponse::Flat(symbols))
     }
 }
 
 impl RequestAction for Hover {
     type Response = lsp_data::Hover;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(lsp_data::Hover {
             contents: HoverContents::Array(vec![]),
@@ -153,9 +163,32 @@ impl RequestAction for Hover {
     }
 }
 
+impl RequestAction for SignatureHelp {
+    type Response = lsp_data::SignatureHelp;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(signature_help::empty_signature_help())
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        signature_help::signature_help(&ctx, &params)
+    }
+}
+
 impl RequestAction for Implementation {
     type Response = Vec<Location>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
     }
@@ -166,7 +199,7 @@ impl RequestAction for Implementation {
     ) -> Result<Self::Response, ResponseError> {
         let file_path = parse_file_path!(&params.text_document.uri, "find_impls")?;
         let span = ctx.convert_pos_to_span(file_path, params.position);
-        let analysis = ctx.analysis;
+        let analysis = ctx.project.analysis;
 
         let type_id = analysis.id(&span).map_err(|_| ResponseError::Empty)?;
         let result = analysis.find_impls(type_id).map(|spans| {
@@ -190,6 +223,10 @@ impl RequestAction for Implementation {
 impl RequestAction for Definition {
     type Response = Vec<Location>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
     }
@@ -201,7 +238,7 @@ impl RequestAction for Definition {
         // Save-analysis thread.
         let file_path = parse_file_path!(&params.text_document.uri, "goto_def")?;
         let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
-        let analysis = ctx.analysis.clone();
+        let analysis = ctx.project.analysis.clone();
 
         // If configured start racer concurrently and fallback to racer result
         let racer_receiver = {
@@ -246,9 +283,203 @@ impl RequestAction for Definition {
     }
 }
 
+impl RequestAction for TypeDefinition {
+    type Response = Vec<Location>;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "goto_type_def")?;
+        let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
+        let racer_fallback = ctx.config.lock().unwrap().goto_def_racer_fallback;
+
+        let ty = match ctx.project.analysis.show_type(&span) {
+            Ok(ty) => ty,
+            // Analysis data may be stale, so try to find a type annotation
+            // on the declaration instead
+            Err(_) if racer_fallback => {
+                let ctx = ctx.clone();
+                let file_path = file_path.clone();
+                let receiver = work_pool::receive_from_thread(
+                    move || {
+                        let cache = ctx.racer_cache();
+                        let session = ctx.racer_session(&cache);
+                        let location = pos_to_racer_location(params.position);
+
+                        racer::find_definition(file_path, location, &session)
+                            .and_then(|rm| type_annotation(&rm.contextstr))
+                    },
+                    WorkDescription("textDocument/typeDefinition-racer"),
+                );
+                match receiver.recv() {
+                    Ok(Some(ty)) => ty,
+                    _ => return Self::fallback_response(),
+                }
+            }
+            Err(_) => return Self::fallback_response(),
+        };
+        trace!("goto_type_def: type: {:?}", ty);
+
+        let mut result = vec![];
+        let mut unresolved = vec![];
+        for path in type_paths(&ty) {
+            let locations = find_type_defs(&ctx.project.analysis, &path);
+            if locations.is_empty() {
+                unresolved.push(path);
+            }
+            result.extend(locations);
+        }
+
+        if !unresolved.is_empty() && racer_fallback {
+            let receiver = work_pool::receive_from_thread(
+                move || {
+                    let cache = ctx.racer_cache();
+                    let session = ctx.racer_session(&cache);
+
+                    unresolved
+                        .iter()
+                        .filter_map(|path| {
+                            racer::complete_fully_qualified_name(path.as_str(), &file_path, &session)
+                                .find(|m| m.matchstr == path.rsplit("::").next().unwrap())
+                                .and_then(|m| location_from_racer_match(&m))
+                        })
+                        .collect::<Vec<_>>()
+                },
+                WorkDescription("textDocument/typeDefinition-racer"),
+            );
+            if let Ok(locations) = receiver.recv() {
+                trace!("goto_type_def (Racer): {:?}", locations);
+                result.extend(locations);
+            }
+        }
+
+        Ok(result
+            .into_iter()
+            .unique_by(|loc| (loc.uri.clone(), loc.range.start))
+            .collect())
+    }
+}
+
+/// Type constructors which usually wrap the type the user is interested in.
+/// Their definitions are offered after those of the types they wrap.
+const WRAPPER_TYPES: &[&str] = &[
+    "Box", "Rc", "Arc", "Vec", "VecDeque", "Option", "Result", "Cell", "RefCell", "Mutex",
+    "RwLock", "HashMap", "HashSet", "BTreeMap", "BTreeSet",
+];
+
+/// Names appearing in types which cannot be jumped to.
+const NON_NAVIGABLE_TYPES: &[&str] = &[
+    "mut", "dyn", "impl", "const", "fn", "for", "unsafe", "extern", "Self", "bool", "char", "str",
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32",
+    "f64",
+];
+
+/// Extracts the paths of all named types in a type, e.g. `&Vec<foo::Bar>`
+/// yields `foo::Bar` and `Vec` (wrapper types are ordered last).
+fn type_paths(ty: &str) -> Vec<String> {
+    lazy_static! {
+        static ref TYPE_PATH_RE: Regex = Regex::new(r"'\w+|(?P<path>(?:\w+::)*\w+)").unwrap();
+    }
+
+    let paths: Vec<String> = TYPE_PATH_RE
+        .captures_iter(ty)
+        .filter_map(|caps| caps.name("path"))
+        .map(|path| path.as_str())
+        .filter(|path| !path.starts_with(|c: char| c.is_numeric()))
+        .filter(|path| !NON_NAVIGABLE_TYPES.contains(path))
+        .unique()
+        .map(|path| path.to_owned())
+        .collect();
+
+    let (wrappers, inner): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .partition(|path| WRAPPER_TYPES.contains(&path.rsplit("::").next().unwrap()));
+    inner.into_iter().chain(wrappers).collect()
+}
+
+/// Extracts the type annotation from a declaration such as `let x: Foo = ..`
+/// or `pub field: Foo`.
+fn type_annotation(decl: &str) -> Option<String> {
+    let bytes = decl.as_bytes();
+    let colon = (0..bytes.len()).find(|&i| {
+        bytes[i] == b':'
+            && (i == 0 || bytes[i - 1] != b':')
+            && bytes.get(i + 1).map_or(true, |&b| b != b':')
+    })?;
+
+    let mut depth = 0i32;
+    let mut prev = ' ';
+    let mut ty = String::new();
+    for c in decl[colon + 1..].chars() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' if prev == '-' => {}
+            '>' | ')' | ']' => depth -= 1,
+            '=' | ';' | ',' | '{' if depth <= 0 => break,
+            _ => {}
+        }
+        ty.push(c);
+        prev = c;
+    }
+
+    let ty = ty.trim();
+    if ty.is_empty() {
+        None
+    } else {
+        Some(ty.to_owned())
+    }
+}
+
+/// Finds the definitions of the type named by `path` in the analysis data.
+fn find_type_defs(analysis: &AnalysisHost, path: &str) -> Vec<Location> {
+    let name = path.rsplit("::").next().unwrap();
+    let defs: Vec<_> = analysis
+        .query_defs(SymbolQuery::exact(name))
+        .unwrap_or_else(|_| vec![])
+        .into_iter()
+        .filter(|d| match d.kind {
+            data::DefKind::Struct
+            | data::DefKind::Enum
+            | data::DefKind::Union
+            | data::DefKind::Trait
+            | data::DefKind::Type
+            | data::DefKind::ExternType => true,
+            _ => false,
+        })
+        .collect();
+
+    // Prefer the defs matching the full path, if the path is qualified
+    let qualified: Vec<_> = if path.contains("::") {
+        defs.iter()
+            .filter(|d| d.qualname.trim_left_matches("::").ends_with(path))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    if qualified.is_empty() {
+        defs.iter().map(|d| ls_util::rls_to_location(&d.span)).collect()
+    } else {
+        qualified.into_iter().map(|d| ls_util::rls_to_location(&d.span)).collect()
+    }
+}
+
 impl RequestAction for References {
     type Response = Vec<Location>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
     }
@@ -262,12 +493,16 @@ impl RequestAction for References {
 
         let result =
             match ctx
+                .project
                 .analysis
                 .find_all_refs(&span, params.context.include_declaration, false)
             {
                 Ok(t) => t,
                 _ => vec![],
             };
+        if ctx.request_cancelled() {
+            return Self::fallback_response();
+        }
 
         Ok(result
             .iter()
@@ -279,6 +514,10 @@ impl RequestAction for References {
 impl RequestAction for Completion {
     type Response = Vec<CompletionItem>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
     }
@@ -324,6 +563,10 @@ impl RequestAction for Completion {
 impl RequestAction for DocumentHighlight {
     type Response = Vec<lsp_data::DocumentHighlight>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
     }
@@ -336,6 +579,7 @@ impl RequestAction for DocumentHighlight {
         let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
 
         let result = ctx
+            .project
             .analysis
             .find_all_refs(&span, true, false)
             .unwrap_or_else(|_| vec![]);
@@ -359,6 +603,10 @@ impl RequestAction for DocumentHighlight {
 impl RequestAction for Rename {
     type Response = ResponseWithMessage<WorkspaceEdit>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(ResponseWithMessage::Response(WorkspaceEdit {
             changes: None,
@@ -374,60 +622,51 @@ impl RequestAction for Rename {
         // We're going to mutate based on our data so we should block until the
         // data is ready.
         ctx.block_on_build();
+        if ctx.request_cancelled() {
+            return Self::fallback_response();
+        }
 
         let file_path = parse_file_path!(&params.text_document.uri, "rename")?;
         let span = ctx.convert_pos_to_span(file_path, params.position);
 
-        let analysis = ctx.analysis;
-
-        macro_rules! unwrap_or_fallback {
-            ($e: expr, $msg: expr) => {
-                match $e {
-                    Ok(e) => e,
-                    Err(_) => {
-                        return Ok(ResponseWithMessage::Warn($msg.to_owned()));
-                    }
-                }
-            };
-        }
-
-        let id = unwrap_or_fallback!(
-            analysis.crate_local_id(&span),
-            "Rename failed: no information for symbol"
-        );
-        let def = unwrap_or_fallback!(
-            analysis.get_def(id),
-            "Rename failed: no definition for symbol"
-        );
-        if def.name == "self" || def.name == "Self"
-            // FIXME(#578)
-            || def.kind == data::DefKind::Mod
-        {
-            return Ok(ResponseWithMessage::Warn(format!(
-                "Rename failed: cannot rename {}",
-                if def.kind == data::DefKind::Mod {
-                    "modules"
-                } else {
-                    &def.name
-                }
-            )));
+        let target = match rename::rename_target(&ctx, &span).and_then(|target| {
+            rename::check_new_name(&ctx, &target, &params.new_name).map(|_| target)
+        }) {
+            Ok(target) => target,
+            Err(e) => return Ok(ResponseWithMessage::Warn(format!("Rename failed: {}", e))),
+        };
+        if ctx.request_cancelled() {
+            return Self::fallback_response();
         }
-
-        let result = unwrap_or_fallback!(
-            analysis.find_all_refs(&span, true, true),
-            "Rename failed: error finding references"
-        );
-
-        if result.is_empty() {
+        let def = &target.def;
+
+        // Renaming a module which lives in its own file also means moving
+        // that file (and its submodules' directory).
+        let moves = if def.kind == data::DefKind::Mod && !def.value.is_empty() {
+            // The save-analysis data records the file defining a module as
+            // its value.
+            let module_file = ctx.project.root().join(&def.value);
+            let file_name = params.new_name.trim_start_matches("r#");
+            module_moves(&def.name, &def.span.file, &module_file, file_name)
+        } else {
+            vec![]
+        };
+        if !moves.is_empty() && !ctx.client_capabilities.rename_file_support {
             return Ok(ResponseWithMessage::Warn(
-                "Rename failed: RLS found nothing to rename - possibly due to multiple defs"
+                "Rename failed: renaming this module means moving files, which the editor doesn't support"
                     .to_owned(),
             ));
         }
+        if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+            return Ok(ResponseWithMessage::Warn(format!(
+                "Rename failed: {} already exists",
+                to.display()
+            )));
+        }
 
         let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
 
-        for item in &result {
+        for item in &target.refs {
             let loc = ls_util::rls_to_location(item);
             edits
                 .entry(loc.uri)
@@ -444,13 +683,115 @@ impl RequestAction for Rename {
             ));
         }
 
+        if moves.is_empty() {
+            return Ok(ResponseWithMessage::Response(WorkspaceEdit {
+                changes: Some(edits),
+                document_changes: None,
+            }));
+        }
+
+        // Edit the files before moving them, since the edits refer to them by
+        // their current names.
+        let mut operations: Vec<_> = edits
+            .into_iter()
+            .map(|(uri, edits)| {
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: VersionedTextDocumentIdentifier { uri, version: None },
+                    edits,
+                })
+            })
+            .collect();
+        for (old_path, new_path) in moves {
+            let (old_uri, new_uri) = match (
+                Url::from_file_path(&old_path),
+                Url::from_file_path(&new_path),
+            ) {
+                (Ok(old_uri), Ok(new_uri)) => (old_uri, new_uri),
+                _ => {
+                    return Ok(ResponseWithMessage::Warn(format!(
+                        "Rename failed: cannot move {}",
+                        old_path.display()
+                    )))
+                }
+            };
+            operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(
+                RenameFile {
+                    old_uri,
+                    new_uri,
+                    options: None,
+                },
+            )));
+        }
+
         Ok(ResponseWithMessage::Response(WorkspaceEdit {
-            changes: Some(edits),
-            document_changes: None,
+            changes: None,
+            document_changes: Some(DocumentChanges::Operations(operations)),
         }))
     }
 }
 
+/// Returns the files and directories (as `(from, to)` pairs) to move when
+/// renaming the module `name`, declared in `declared_in` and defined in `file`,
+/// to `new_name`, i.e., `foo.rs` (and a `foo` directory of submodules) or
+/// `foo/mod.rs`.
+///
+/// Modules defined inline, and modules whose file is named with a `#[path]`
+/// attribute, don't need any moves.
+fn module_moves(
+    name: &str,
+    declared_in: &Path,
+    file: &Path,
+    new_name: &str,
+) -> Vec<(PathBuf, PathBuf)> {
+    if file == declared_in || !file.is_file() {
+        return vec![];
+    }
+    let dir = match file.parent() {
+        Some(dir) => dir,
+        None => return vec![],
+    };
+    let old_name = OsStr::new(name);
+
+    if file.file_name() == Some(OsStr::new("mod.rs")) && dir.file_name() == Some(old_name) {
+        vec![(dir.to_owned(), dir.with_file_name(new_name))]
+    } else if file.file_stem() == Some(old_name) && file.extension() == Some(OsStr::new("rs")) {
+        let mut moves = vec![(file.to_owned(), dir.join(format!("{}.rs", new_name)))];
+        let submodules = dir.join(old_name);
+        if submodules.is_dir() {
+            moves.push((submodules, dir.join(new_name)));
+        }
+        moves
+    } else {
+        vec![]
+    }
+}
+
+impl RequestAction for PrepareRename {
+    type Response = Range;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "prepare_rename")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        rename::rename_target(&ctx, &span)
+            .map(|target| ls_util::rls_to_range(target.ident.range))
+            .map_err(|e| {
+                ResponseError::Message(ErrorCode::InvalidParams, format!("Rename failed: {}", e))
+            })
+    }
+}
+
 #[derive(Debug)]
 pub enum ExecuteCommandResponse {
     /// Response/client request containing workspace edits.
@@ -558,15 +899,21 @@ fn make_suggestion_fix_actions(
     ctx: &InitActionContext,
     code_actions_result: &mut <CodeAction as RequestAction>::Response,
 ) {
+    let uri = &params.text_document.uri;
     // search for compiler suggestions
-    if let Some(results) = ctx.previous_build_results.lock().unwrap().get(file_path) {
-        let suggestions = results
+    if let Some(results) = ctx.project.previous_build_results.lock().unwrap().get(file_path) {
+        let fixes = results
             .iter()
             .filter(|(diag, _)| diag.range.overlaps(&params.range))
-            .flat_map(|(_, suggestions)| suggestions);
-        for s in suggestions {
+            .flat_map(|(diag, suggestions)| suggestions.iter().map(move |s| (diag, s)));
+        for (diag, s) in fixes {
+            if ctx.client_capabilities.code_action_literal_support {
+                code_actions_result.push(quickfix(uri, diag, s));
+                continue;
+            }
+
             let span = Location {
-                uri: params.text_document.uri.clone(),
+                uri: uri.clone(),
                 range: s.range,
             };
             let span = serde_json::to_value(&span).unwrap();
@@ -576,11 +923,81 @@ fn make_suggestion_fix_actions(
                 command: format!("rls.applySuggestion-{}", ctx.pid),
                 arguments: Some(vec![span, new_text]),
             };
-            code_actions_result.push(cmd);
+            code_actions_result.push(CodeActionOrCommand::Command(cmd));
         }
     }
 }
 
+/// A quick fix `CodeAction` applying the compiler's `suggestion` for `diag`
+/// to the file at `uri`.
+fn quickfix(uri: &Url, diag: &Diagnostic, suggestion: &Suggestion) -> CodeActionOrCommand {
+    let edit = TextEdit::new(suggestion.range, suggestion.new_text.clone());
+    code_action(
+        suggestion.label.clone(),
+        code_action_kind::QUICKFIX,
+        uri,
+        vec![edit],
+        Some(vec![diag.clone()]),
+    )
+}
+
+/// Create a `CodeAction` removing every import in the file which the compiler
+/// reported as unused, the result is appended to `code_actions_result`
+///
+/// This is only offered to clients supporting `CodeAction` literals, older
+/// clients can still apply the fix for each unused import on its own.
+fn make_organize_imports_action(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    if !ctx.client_capabilities.code_action_literal_support {
+        return;
+    }
+    let results = ctx.project.previous_build_results.lock().unwrap();
+    if let Some(results) = results.get(file_path) {
+        code_actions_result.extend(organize_imports(&params.text_document.uri, results));
+    }
+}
+
+/// A `CodeAction` applying the compiler's suggestions for every unused import
+/// among `results` (the diagnostics of the file at `uri`), if there are any.
+fn organize_imports(
+    uri: &Url,
+    results: &[(Diagnostic, Vec<Suggestion>)],
+) -> Option<CodeActionOrCommand> {
+    let unused_imports = NumberOrString::String("unused_imports".to_owned());
+    let unused: Vec<_> = results
+        .iter()
+        .filter(|(diag, suggestions)| {
+            diag.code.as_ref() == Some(&unused_imports) && !suggestions.is_empty()
+        })
+        .collect();
+    if unused.is_empty() {
+        return None;
+    }
+
+    let mut edits: Vec<_> = unused
+        .iter()
+        .flat_map(|(_, suggestions)| suggestions)
+        .map(|s| TextEdit::new(s.range, s.new_text.clone()))
+        .collect();
+    // The same import can be reported more than once (e.g., for each target it
+    // is built for), and clients reject overlapping edits
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+    edits.dedup_by(|edit, prev| edit.range.start < prev.range.end);
+
+    let diagnostics = unused.into_iter().map(|(diag, _)| diag.clone()).collect();
+    Some(code_action(
+        "Remove unused imports".to_owned(),
+        code_action_kind::SOURCE_ORGANIZE_IMPORTS,
+        uri,
+        edits,
+        Some(diagnostics),
+    ))
+}
+
 /// Create `CodeActions` for performing deglobbing when a wildcard import is found
 /// the results are appended to `code_actions_result`
 fn make_deglob_actions(
@@ -609,7 +1026,7 @@ fn make_deglob_actions(
                 span.range.col_end = span::Column::new_zero_indexed(index as u32 + 1);
 
                 // load the deglob type information
-                ctx.analysis.show_type(&span).ok().map(|ty| (ty, span))
+                ctx.project.analysis.show_type(&span).ok().map(|ty| (ty, span))
             })
             .map(|(mut deglob_str, span)| {
                 // Handle multiple imports from one *
@@ -618,31 +1035,70 @@ fn make_deglob_actions(
                 }
 
                 // Build result
-                let deglob_result = DeglobResult {
+                DeglobResult {
                     location: ls_util::rls_to_location(&span),
                     new_text: deglob_str,
-                };
-
-                // Convert to json
-                serde_json::to_value(&deglob_result).unwrap()
+                }
             })
             .collect();
 
         if !deglob_results.is_empty() {
+            let title = format!(
+                "Deglob import{}",
+                if deglob_results.len() > 1 { "s" } else { "" }
+            );
+
             // extend result list
-            let cmd = Command {
-                title: format!(
-                    "Deglob import{}",
-                    if deglob_results.len() > 1 { "s" } else { "" }
-                ),
-                command: format!("rls.deglobImports-{}", ctx.pid),
-                arguments: Some(deglob_results),
-            };
-            code_actions_result.push(cmd);
+            if ctx.client_capabilities.code_action_literal_support {
+                let edits = deglob_results
+                    .into_iter()
+                    .map(|res| TextEdit::new(res.location.range, res.new_text))
+                    .collect();
+                code_actions_result.push(code_action(
+                    title,
+                    code_action_kind::REFACTOR_REWRITE,
+                    &params.text_document.uri,
+                    edits,
+                    None,
+                ));
+            } else {
+                let deglob_results = deglob_results
+                    .iter()
+                    .map(|res| serde_json::to_value(res).unwrap())
+                    .collect();
+                let cmd = Command {
+                    title,
+                    command: format!("rls.deglobImports-{}", ctx.pid),
+                    arguments: Some(deglob_results),
+                };
+                code_actions_result.push(CodeActionOrCommand::Command(cmd));
+            }
         }
     };
 }
 
+/// A `CodeAction` literal of the given kind, which the client applies by
+/// making `edits` to the file at `uri`.
+fn code_action(
+    title: String,
+    kind: &str,
+    uri: &Url,
+    edits: Vec<TextEdit>,
+    diagnostics: Option<Vec<Diagnostic>>,
+) -> CodeActionOrCommand {
+    let changes: HashMap<_, _> = vec![(uri.clone(), edits)].into_iter().collect();
+    CodeActionOrCommand::CodeAction(lsp_data::CodeAction {
+        title,
+        kind: Some(kind.to_owned()),
+        diagnostics,
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+        }),
+        command: None,
+    })
+}
+
 // Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
 fn sort_deglob_str(s: &str) -> String {
     let mut substrings = s.split(',').map(|s| s.trim()).collect::<Vec<_>>();
@@ -675,7 +1131,11 @@ fn sort_deglob_str(s: &str) -> String {
 }
 
 impl RequestAction for CodeAction {
-    type Response = Vec<Command>;
+    type Response = Vec<CodeActionOrCommand>;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
 
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
@@ -692,17 +1152,41 @@ impl RequestAction for CodeAction {
         let mut cmds = vec![];
         if ctx.build_ready() {
             make_suggestion_fix_actions(&params, &file_path, &ctx, &mut cmds);
+            make_organize_imports_action(&params, &file_path, &ctx, &mut cmds);
         }
         if ctx.analysis_ready() {
             make_deglob_actions(&params, &file_path, &ctx, &mut cmds);
         }
+
+        // Clients only asking for some kinds of action (e.g., to organize
+        // imports on save) don't want the others.
+        if let Some(ref only) = params.context.only {
+            retain_kinds(&mut cmds, only);
+        }
         Ok(cmds)
     }
 }
 
+/// Keeps the code actions whose kind is one of `only`, or a sub-kind of one
+/// (e.g., `refactor.rewrite` for `refactor`). Commands have no kind, so none
+/// are kept.
+fn retain_kinds(actions: &mut Vec<CodeActionOrCommand>, only: &[String]) {
+    actions.retain(|action| match action {
+        CodeActionOrCommand::CodeAction(action) => action.kind.as_ref().map_or(false, |kind| {
+            only.iter()
+                .any(|only| kind == only || kind.starts_with(&format!("{}.", only)))
+        }),
+        CodeActionOrCommand::Command(_) => false,
+    });
+}
+
 impl RequestAction for Formatting {
     type Response = [TextEdit; 1];
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Err(ResponseError::Message(
             ErrorCode::InternalError,
@@ -721,6 +1205,10 @@ impl RequestAction for Formatting {
 impl RequestAction for RangeFormatting {
     type Response = [TextEdit; 1];
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Err(ResponseError::Message(
             ErrorCode::InternalError,
@@ -846,6 +1334,34 @@ fn reformat(
     }])
 }
 
+impl RequestAction for FoldingRangeRequest {
+    type Response = Vec<FoldingRange>;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "folding_range")?;
+
+        match ctx.vfs.load_file(&file_path) {
+            Ok(FileContents::Text(text)) => Ok(folding::folding_ranges(&text)),
+            Ok(FileContents::Binary(_)) => Self::fallback_response(),
+            Err(e) => {
+                debug!("folding_range: failed to load file: {:?}", e);
+                Self::fallback_response()
+            }
+        }
+    }
+}
+
 impl RequestAction for ResolveCompletion {
     type Response = CompletionItem;
 
@@ -892,6 +1408,10 @@ fn location_from_racer_match(a_match: &racer::Match) -> Option<Location> {
 impl RequestAction for CodeLensRequest {
     type Response = Vec<CodeLens>;
 
+    fn document(params: &Self::Params) -> Option<Url> {
+        Some(params.text_document.uri.clone())
+    }
+
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Err(ResponseError::Empty)
     }
@@ -900,13 +1420,17 @@ impl RequestAction for CodeLensRequest {
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "code_lens")?;
         let mut ret = Vec::new();
         if ctx.client_supports_cmd_run {
-            let file_path = parse_file_path!(&params.text_document.uri, "code_lens")?;
             for action in collect_run_actions(&ctx, &file_path) {
+                let command = match action.cmd.kind {
+                    CmdKind::Run => "rls.run",
+                    CmdKind::Debug => "rls.debug",
+                };
                 let command = Command {
                     title: action.label,
-                    command: "rls.run".to_string(),
+                    command: command.to_string(),
                     arguments: Some(vec![serde_json::to_value(&action.cmd).unwrap()]),
                 };
                 let range = ls_util::rls_to_range(action.target_element);
@@ -918,13 +1442,69 @@ impl RequestAction for CodeLensRequest {
                 ret.push(lens);
             }
         }
+        ret.extend(lens::reference_lenses(
+            &ctx,
+            &file_path,
+            &params.text_document.uri,
+        ));
         Ok(ret)
     }
 }
 
+impl RequestAction for CodeLensResolve {
+    type Response = CodeLens;
+
+    fn document(params: &Self::Params) -> Option<Url> {
+        lens::lens_uri(params)
+    }
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        lens::resolve(&ctx, params)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_type_paths() {
+        assert_eq!(type_paths("u32"), Vec::<String>::new());
+        assert_eq!(type_paths("&'a mut Foo"), vec!["Foo"]);
+        assert_eq!(
+            type_paths("std::boxed::Box<foo::Bar>"),
+            vec!["foo::Bar", "std::boxed::Box"]
+        );
+        assert_eq!(type_paths("Vec<(Foo, [Bar; 4])>"), vec!["Foo", "Bar", "Vec"]);
+        assert_eq!(
+            type_paths("HashMap<Foo, Vec<Foo>>"),
+            vec!["Foo", "HashMap", "Vec"]
+        );
+    }
+
+    #[test]
+    fn test_type_annotation() {
+        assert_eq!(type_annotation("let x: Foo = Foo::new();"), Some("Foo".to_owned()));
+        assert_eq!(
+            type_annotation("let map: HashMap<u32, Bar> = HashMap::new()"),
+            Some("HashMap<u32, Bar>".to_owned())
+        );
+        assert_eq!(
+            type_annotation("pub field: Box<dyn Fn() -> u32>,"),
+            Some("Box<dyn Fn() -> u32>".to_owned())
+        );
+        assert_eq!(type_annotation("let x = foo::bar();"), None);
+        assert_eq!(type_annotation("let x = std::f32::MAX;"), None);
+    }
 
     #[test]
     fn test_sort_deglob_str() {
@@ -938,4 +1518,179 @@ mod test {
             "arc, bow, curve, Arc, Bow, Curve, ARC",
         );
     }
+
+    #[test]
+    fn test_module_moves() {
+        let root = env::temp_dir().join("rls-test-module-moves");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/foo")).unwrap();
+        fs::create_dir_all(root.join("src/bar")).unwrap();
+        for file in &[
+            "src/lib.rs",
+            "src/foo.rs",
+            "src/foo/baz.rs",
+            "src/bar/mod.rs",
+            "src/qux.rs",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let lib = root.join("src/lib.rs");
+        let moves = |name: &str, file: &str| module_moves(name, &lib, &root.join(file), "new");
+
+        assert_eq!(
+            moves("foo", "src/foo.rs"),
+            vec![
+                (root.join("src/foo.rs"), root.join("src/new.rs")),
+                (root.join("src/foo"), root.join("src/new")),
+            ]
+        );
+        assert_eq!(
+            moves("bar", "src/bar/mod.rs"),
+            vec![(root.join("src/bar"), root.join("src/new"))]
+        );
+        // Inline, or with a `#[path]` attribute
+        assert_eq!(moves("inline", "src/lib.rs"), vec![]);
+        assert_eq!(moves("quux", "src/qux.rs"), vec![]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn diagnostic(code: &str, line: u64) -> Diagnostic {
+        let range = Range::new(Position::new(line, 4), Position::new(line, 12));
+        Diagnostic {
+            code: Some(NumberOrString::String(code.to_owned())),
+            ..Diagnostic::new_simple(range, format!("{} on line {}", code, line))
+        }
+    }
+
+    fn suggestion(start: (u64, u64), end: (u64, u64), new_text: &str) -> Suggestion {
+        Suggestion {
+            range: Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            new_text: new_text.to_owned(),
+            label: format!("replace with `{}`", new_text),
+        }
+    }
+
+    fn unwrap_action(action: CodeActionOrCommand) -> lsp_data::CodeAction {
+        match action {
+            CodeActionOrCommand::CodeAction(action) => action,
+            CodeActionOrCommand::Command(cmd) => panic!("expected a code action: {:?}", cmd),
+        }
+    }
+
+    // The edits a code action makes to the file at `uri`.
+    fn edits(action: &lsp_data::CodeAction, uri: &Url) -> Vec<TextEdit> {
+        let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        assert_eq!(changes.len(), 1);
+        changes[uri].clone()
+    }
+
+    #[test]
+    fn test_quickfix() {
+        let uri = Url::parse("file:///src/lib.rs").unwrap();
+        let diag = diagnostic("unused_variables", 3);
+        let fix = suggestion((3, 8), (3, 9), "_x");
+
+        let action = unwrap_action(quickfix(&uri, &diag, &fix));
+        assert_eq!(action.title, "replace with `_x`");
+        assert_eq!(action.kind.as_ref().map(String::as_str), Some("quickfix"));
+        assert_eq!(action.diagnostics, Some(vec![diag]));
+        assert_eq!(
+            edits(&action, &uri),
+            vec![TextEdit::new(fix.range, "_x".to_owned())]
+        );
+        assert!(action.command.is_none());
+    }
+
+    #[test]
+    fn test_organize_imports() {
+        let uri = Url::parse("file:///src/lib.rs").unwrap();
+        let results = vec![
+            (
+                diagnostic("unused_imports", 1),
+                vec![suggestion((1, 0), (2, 0), "")],
+            ),
+            // Reported again (e.g., for another target), overlapping the first
+            (
+                diagnostic("unused_imports", 1),
+                vec![suggestion((1, 4), (1, 12), "")],
+            ),
+            (
+                diagnostic("unused_imports", 5),
+                vec![suggestion((5, 0), (6, 0), "")],
+            ),
+            (
+                diagnostic("unused_variables", 8),
+                vec![suggestion((8, 8), (8, 9), "_x")],
+            ),
+            // Nothing to apply
+            (diagnostic("unused_imports", 10), vec![]),
+        ];
+
+        let action = unwrap_action(organize_imports(&uri, &results).unwrap());
+        assert_eq!(action.title, "Remove unused imports");
+        assert_eq!(
+            action.kind.as_ref().map(String::as_str),
+            Some("source.organizeImports")
+        );
+        let diagnostics: Vec<_> = results[..3].iter().map(|(diag, _)| diag.clone()).collect();
+        assert_eq!(action.diagnostics, Some(diagnostics));
+        assert_eq!(
+            edits(&action, &uri),
+            vec![
+                TextEdit::new(results[0].1[0].range, String::new()),
+                TextEdit::new(results[2].1[0].range, String::new()),
+            ]
+        );
+
+        assert!(organize_imports(&uri, &results[3..]).is_none());
+    }
+
+    #[test]
+    fn test_retain_kinds() {
+        let uri = Url::parse("file:///src/lib.rs").unwrap();
+        let kinds = |only: &[&str]| {
+            let mut actions = vec![
+                quickfix(
+                    &uri,
+                    &diagnostic("unused_variables", 3),
+                    &suggestion((3, 8), (3, 9), "_x"),
+                ),
+                code_action(
+                    "Deglob import".to_owned(),
+                    code_action_kind::REFACTOR_REWRITE,
+                    &uri,
+                    vec![],
+                    None,
+                ),
+                code_action(
+                    "Remove unused imports".to_owned(),
+                    code_action_kind::SOURCE_ORGANIZE_IMPORTS,
+                    &uri,
+                    vec![],
+                    None,
+                ),
+                CodeActionOrCommand::Command(Command {
+                    title: "Deglob import".to_owned(),
+                    command: "rls.deglobImports-0".to_owned(),
+                    arguments: None,
+                }),
+            ];
+            let only: Vec<_> = only.iter().map(|kind| kind.to_string()).collect();
+            retain_kinds(&mut actions, &only);
+            actions
+                .into_iter()
+                .map(|action| unwrap_action(action).kind.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kinds(&["quickfix"]), vec!["quickfix"]);
+        assert_eq!(kinds(&["source"]), vec!["source.organizeImports"]);
+        assert_eq!(
+            kinds(&["refactor", "quickfix"]),
+            vec!["quickfix", "refactor.rewrite"]
+        );
+        assert_eq!(kinds(&["quick"]), Vec::<String>::new());
+    }
 }
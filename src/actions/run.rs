@@ -1,13 +1,19 @@
+use crate::actions::folding::{skip_char, skip_raw_string, skip_string};
 use crate::actions::InitActionContext;
-use lazy_static::lazy_static;
+use crate::build::{command_options, Crate};
+use crate::config::Inferrable;
+use crate::project_model::TargetKind;
 use log::error;
 use ordslice::Ext;
-use regex::Regex;
 use rls_span::{Column, Position, Range, Row, ZeroIndexed};
 use rls_vfs::FileContents;
 use serde_derive::Serialize;
 
-use std::{collections::HashMap, iter, path::Path};
+use std::{
+    collections::HashMap,
+    env, iter,
+    path::{Path, PathBuf},
+};
 
 pub fn collect_run_actions(ctx: &InitActionContext, file: &Path) -> Vec<RunAction> {
     let text = match ctx.vfs.load_file(file) {
@@ -18,54 +24,724 @@ pub fn collect_run_actions(ctx: &InitActionContext, file: &Path) -> Vec<RunActio
             return Vec::new();
         }
     };
-    if !text.contains("#[test]") {
+    let runnables = find_runnables(&text);
+    if runnables.is_empty() {
         return Vec::new();
     }
 
-    lazy_static! {
-        static ref TEST_FN_RE: Regex = Regex::new(r"#\[test\]\s*\n\s*fn\s*(?P<name>\w+)").unwrap();
-    }
+    // Without the target, we know neither how to build it nor where the file
+    // is in its crate, so test names are only partial paths.
+    let target = file_target(ctx, file);
+    let target_kind = target.as_ref().map(|target| target.kind);
+    let (options, rustflags) = command_options(&ctx.project.config.lock().unwrap());
+    // `-p PACKAGE`, and the same build options as the RLS, for commands which
+    // select their own targets. `target_args` also selects the file's target.
+    let mut package_args = target.as_ref().map_or_else(Vec::new, |target| {
+        vec!["-p".to_string(), target.package.clone()]
+    });
+    let mut target_args = package_args.clone();
+    target_args.extend(target.as_ref().map_or_else(Vec::new, FileTarget::args));
+    target_args.extend(options.iter().cloned());
+    package_args.extend(options);
+    let debug_artifact = if ctx.client_supports_cmd_debug {
+        target
+            .as_ref()
+            .and_then(|target| artifact_path(ctx, target))
+    } else {
+        None
+    };
+    let file_module = target.and_then(|target| target.module);
+    let exact: &[&str] = if file_module.is_some() {
+        &["--exact"]
+    } else {
+        &[]
+    };
 
     let line_index = LineIndex::new(&text);
 
     let mut ret = Vec::new();
-    for caps in TEST_FN_RE.captures_iter(&text) {
-        let group = caps.name("name").unwrap();
+    for runnable in runnables {
         let target_element = Range::from_positions(
-            line_index.offset_to_position(group.start()),
-            line_index.offset_to_position(group.end()),
+            line_index.offset_to_position(runnable.range.0),
+            line_index.offset_to_position(runnable.range.1),
         );
-        let test_name = group.as_str();
-        let run_action = RunAction {
-            label: "Run test".to_string(),
-            target_element,
-            cmd: Cmd {
-                binary: "cargo".to_string(),
-                args: vec![
-                    "test".to_string(),
-                    "--".to_string(),
-                    "--nocapture".to_string(),
-                    test_name.to_string(),
-                ],
-                env: iter::once(("RUST_BACKTRACE".to_string(), "short".to_string())).collect(),
-            },
+        let path = file_module
+            .iter()
+            .flatten()
+            .chain(&runnable.path)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("::");
+        let path = path.as_str();
+
+        let mut action = |label: &str, kind: RunKind, mut cmd: Cmd| {
+            if let Some(rustflags) = &rustflags {
+                // Debug commands run the executable directly, and only their
+                // build runs Cargo.
+                let env = match &mut cmd.build {
+                    Some(build) => &mut build.env,
+                    None => &mut cmd.env,
+                };
+                env.insert("RUSTFLAGS".to_string(), rustflags.clone());
+            }
+            ret.push(RunAction {
+                label: label.to_string(),
+                kind,
+                target_element,
+                cmd,
+            })
         };
-        ret.push(run_action);
+        match runnable.kind {
+            RunnableKind::Test => {
+                let test_args: [&[&str]; 3] = [&["--nocapture"], exact, &[path]];
+                action(
+                    "Run test",
+                    RunKind::Test,
+                    Cmd::run(&["test"], &target_args, &test_args.concat()),
+                );
+            }
+            RunnableKind::TestModule => {
+                // Test filters match anywhere in a test's path, so skip the
+                // tests of modules with the same path nested in other ones.
+                let filter = format!("{}::", path);
+                let nested = format!("::{}::", path);
+                action(
+                    "Run all tests in module",
+                    RunKind::TestModule,
+                    Cmd::run(
+                        &["test"],
+                        &target_args,
+                        &["--nocapture", filter.as_str(), "--skip", nested.as_str()],
+                    ),
+                );
+            }
+            RunnableKind::Bench => {
+                let bench_args: [&[&str]; 2] = [exact, &[path]];
+                action(
+                    "Run bench",
+                    RunKind::Bench,
+                    Cmd::run(&["bench"], &target_args, &bench_args.concat()),
+                );
+            }
+            // Only the `main` of a binary or example is run.
+            RunnableKind::Main => {
+                let is_bin = target_kind == Some(TargetKind::Bin)
+                    || target_kind == Some(TargetKind::Example);
+                if !is_bin
+                    || file_module
+                        .as_ref()
+                        .map_or(true, |module| !module.is_empty())
+                {
+                    continue;
+                }
+                action("Run", RunKind::Bin, Cmd::run(&["run"], &target_args, &[]));
+                if let Some(artifact) = &debug_artifact {
+                    action(
+                        "Debug",
+                        RunKind::Bin,
+                        Cmd::debug(artifact, Cmd::run(&["build"], &target_args, &[])),
+                    );
+                }
+            }
+            // Only libraries have doctests. The crate's own documentation
+            // has no name to filter by.
+            RunnableKind::Doctest => {
+                if target_kind.map_or(false, |kind| kind != TargetKind::Lib) || path.is_empty() {
+                    continue;
+                }
+                action(
+                    "Run doctests",
+                    RunKind::Doctest,
+                    Cmd::run(&["test", "--doc"], &package_args, &[path]),
+                );
+            }
+        }
     }
     ret
 }
 
 pub struct RunAction {
     pub label: String,
+    pub kind: RunKind,
     pub target_element: Range<ZeroIndexed>,
     pub cmd: Cmd,
 }
 
+/// What a run action runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunKind {
+    Test,
+    /// All the tests in a module.
+    TestModule,
+    Bench,
+    /// A binary or example.
+    Bin,
+    Doctest,
+}
+
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Cmd {
+    pub kind: CmdKind,
     pub binary: String,
     pub args: Vec<String>,
     pub env: HashMap<String, String>,
+    /// For `CmdKind::Debug`, the command building `binary`, to be run first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<Box<Cmd>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CmdKind {
+    /// The command runs the action.
+    Run,
+    /// The command is the built executable, to be run in a debugger once
+    /// the `build` command has built it.
+    Debug,
+}
+
+impl Cmd {
+    /// `cargo ARGS... TARGET_ARGS... -- ARGS_AFTER...`, or no `--` if
+    /// `args_after` is empty.
+    fn run(args: &[&str], target_args: &[String], args_after: &[&str]) -> Cmd {
+        let mut all_args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        all_args.extend(target_args.iter().cloned());
+        if !args_after.is_empty() {
+            all_args.push("--".to_string());
+            all_args.extend(args_after.iter().map(|arg| arg.to_string()));
+        }
+        Cmd::new(CmdKind::Run, "cargo".to_string(), all_args, None)
+    }
+
+    /// `artifact`, once `build` has built it.
+    fn debug(artifact: &Path, build: Cmd) -> Cmd {
+        Cmd::new(
+            CmdKind::Debug,
+            artifact.display().to_string(),
+            vec![],
+            Some(Box::new(build)),
+        )
+    }
+
+    fn new(kind: CmdKind, binary: String, args: Vec<String>, build: Option<Box<Cmd>>) -> Cmd {
+        Cmd {
+            kind,
+            binary,
+            args,
+            env: iter::once(("RUST_BACKTRACE".to_string(), "short".to_string())).collect(),
+            build,
+        }
+    }
+}
+
+/// The Cargo target a file is compiled in.
+struct FileTarget {
+    /// The name of the target's package.
+    package: String,
+    kind: TargetKind,
+    name: String,
+    /// The path of the module the file defines in the target's crate, if
+    /// known.
+    module: Option<Vec<String>>,
+}
+
+impl FileTarget {
+    /// The arguments selecting the target for Cargo.
+    fn args(&self) -> Vec<String> {
+        let flag = match self.kind {
+            TargetKind::Lib => return vec!["--lib".to_string()],
+            TargetKind::Bin => "--bin",
+            TargetKind::Test => "--test",
+            TargetKind::Bench => "--bench",
+            TargetKind::Example => "--example",
+            TargetKind::Other => return vec![],
+        };
+        vec![flag.to_string(), self.name.clone()]
+    }
+}
+
+/// Returns the path of the executable `cargo build` builds for `target`, if
+/// it's a binary or an example. (The executables of tests and benches have a
+/// hash in their names, which only Cargo knows.)
+fn artifact_path(ctx: &InitActionContext, target: &FileTarget) -> Option<PathBuf> {
+    if target.kind != TargetKind::Bin && target.kind != TargetKind::Example {
+        return None;
+    }
+    let (target_dir, triple) = {
+        let config = ctx.project.config.lock().unwrap();
+        let target_dir = match &config.target_dir {
+            Inferrable::Specified(Some(target_dir)) => Some(target_dir.clone()),
+            _ => None,
+        };
+        (target_dir, config.target.clone())
+    };
+    let mut path = match target_dir {
+        Some(target_dir) => target_dir,
+        None => ctx.project_model().ok()?.target_dir().to_owned(),
+    };
+    path.extend(triple);
+    path.push("debug");
+    if target.kind == TargetKind::Example {
+        path.push("examples");
+    }
+    path.push(format!("{}{}", target.name, env::consts::EXE_SUFFIX));
+    Some(path)
+}
+
+/// Returns the target `file` is compiled in. Libraries are preferred if the
+/// file is part of several crates.
+fn file_target(ctx: &InitActionContext, file: &Path) -> Option<FileTarget> {
+    let mut crates: Vec<Crate> = ctx
+        .project
+        .file_to_crates
+        .lock()
+        .unwrap()
+        .get(file)?
+        .iter()
+        .cloned()
+        .collect();
+    let model = ctx.project_model().ok()?;
+
+    crates.sort_by(|a, b| a.name.cmp(&b.name));
+    crates
+        .into_iter()
+        .filter_map(|krate| {
+            let src_path = krate.src_path?;
+            let (package, target) = model.target_for_root(&src_path)?;
+            if target.kind == TargetKind::Other {
+                return None;
+            }
+            Some(FileTarget {
+                package: package.name(&model).to_owned(),
+                kind: target.kind,
+                name: target.name.clone(),
+                module: module_path(file, &src_path),
+            })
+        })
+        .min_by_key(|target| target.kind != TargetKind::Lib)
+}
+
+/// Returns the path of the module defined by `file` in the crate whose root
+/// is `crate_root`, assuming the module tree follows the file layout (i.e.,
+/// there are no `#[path]` attributes).
+fn module_path(file: &Path, crate_root: &Path) -> Option<Vec<String>> {
+    if file == crate_root {
+        return Some(vec![]);
+    }
+    let relative = file.strip_prefix(crate_root.parent()?).ok()?;
+    let mut path: Vec<String> = relative
+        .parent()?
+        .iter()
+        .map(|component| component.to_str().map(str::to_owned))
+        .collect::<Option<_>>()?;
+    match relative.file_stem()?.to_str()? {
+        "mod" => {}
+        stem => path.push(stem.to_owned()),
+    }
+    Some(path)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunnableKind {
+    Test,
+    /// An inline module containing tests.
+    TestModule,
+    Bench,
+    /// A `main` function at the root of the file.
+    Main,
+    /// An item with examples in its documentation.
+    Doctest,
+}
+
+/// Something runnable found in a file.
+#[derive(Debug, PartialEq)]
+struct Runnable {
+    kind: RunnableKind,
+    /// The path of the item, from the module the file defines.
+    path: Vec<String>,
+    /// The start and end offsets of the item's name, or for doctests, of the
+    /// opening fence of the first example.
+    range: (usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Ident(&'a str, usize),
+    Punct(u8),
+    /// Consecutive doc comments: whether they're inner (`//!`) ones, and
+    /// their start and end offsets.
+    Doc(bool, usize, usize),
+}
+
+/// Splits `text` into identifiers, punctuation and doc comments, skipping
+/// whitespace, other comments and literals.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let bytes = text.as_bytes();
+    let is_ident_byte = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80;
+
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                let start = i;
+                i = text[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                let comment = &text[start..i];
+                let inner = comment.starts_with("//!");
+                if inner || (comment.starts_with("///") && !comment.starts_with("////")) {
+                    push_doc(&mut tokens, inner, start, i);
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let start = i;
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                let comment = &text[start..i];
+                let inner = comment.starts_with("/*!");
+                if inner
+                    || (comment.starts_with("/**")
+                        && !comment.starts_with("/***")
+                        && comment != "/**/")
+                {
+                    push_doc(&mut tokens, inner, start, i);
+                }
+                continue;
+            }
+            b'"' => {
+                i = skip_string(bytes, i + 1);
+                continue;
+            }
+            b'\'' => {
+                // Lifetimes are left to be read as identifiers.
+                i = skip_char(text, i + 1).unwrap_or(i + 1);
+                continue;
+            }
+            b if b.is_ascii_digit() => {
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                continue;
+            }
+            b if is_ident_byte(b) => {
+                let start = i;
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                match (&text[start..i], bytes.get(i)) {
+                    ("b", Some(b'"')) => i = skip_string(bytes, i + 1),
+                    ("b", Some(b'\'')) => i = skip_char(text, i + 1).unwrap_or(i + 1),
+                    ("r", Some(b'#'))
+                    | ("r", Some(b'"'))
+                    | ("br", Some(b'#'))
+                    | ("br", Some(b'"')) => match skip_raw_string(text, i) {
+                        Some(end) => i = end,
+                        // A raw identifier.
+                        None if bytes.get(i + 1).map_or(false, |&b| is_ident_byte(b)) => {
+                            let start = i + 1;
+                            i = start;
+                            while i < bytes.len() && is_ident_byte(bytes[i]) {
+                                i += 1;
+                            }
+                            tokens.push(Token::Ident(&text[start..i], start));
+                        }
+                        None => tokens.push(Token::Ident(&text[start..i], start)),
+                    },
+                    (ident, _) => tokens.push(Token::Ident(ident, start)),
+                }
+                continue;
+            }
+            b if b.is_ascii_whitespace() => {}
+            b => tokens.push(Token::Punct(b)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Adds a doc comment to `tokens`, merging it with the previous one if that's
+/// a doc comment of the same kind.
+fn push_doc(tokens: &mut Vec<Token<'_>>, inner: bool, start: usize, end: usize) {
+    if let Some(Token::Doc(prev_inner, _, prev_end)) = tokens.last_mut() {
+        if *prev_inner == inner {
+            *prev_end = end;
+            return;
+        }
+    }
+    tokens.push(Token::Doc(inner, start, end));
+}
+
+/// Returns the start and end offsets of the opening fence of the first Rust
+/// code block in the doc comments between `start` and `end`.
+fn doc_example(text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let mut in_block = false;
+    let mut line_start = start;
+    for line in text[start..end].split('\n') {
+        let offset = line_start;
+        line_start += line.len() + 1;
+
+        let content = line.trim_start();
+        let content = ["///", "//!", "/**", "/*!", "*"]
+            .iter()
+            .find(|prefix| content.starts_with(*prefix))
+            .map_or(content, |prefix| &content[prefix.len()..])
+            .trim();
+        if !content.starts_with("```") {
+            continue;
+        }
+        if in_block {
+            in_block = false;
+            continue;
+        }
+        in_block = true;
+        if is_rust_example(&content[3..]) {
+            let fence = offset + line.find("```")?;
+            return Some((fence, offset + line.trim_end().len()));
+        }
+    }
+    None
+}
+
+/// Whether a code block with the info string `info` is a Rust example which
+/// rustdoc tests.
+fn is_rust_example(info: &str) -> bool {
+    const ATTRIBUTES: &[&str] = &[
+        "rust",
+        "should_panic",
+        "no_run",
+        "compile_fail",
+        "test_harness",
+        "allow_fail",
+        "edition2015",
+        "edition2018",
+    ];
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|attr| !attr.is_empty())
+        .all(|attr| ATTRIBUTES.contains(&attr))
+}
+
+/// A block enclosing an item.
+enum Block {
+    Module(String),
+    /// An impl or a trait, with the name of its type.
+    Impl(String),
+    Other,
+}
+
+impl Block {
+    fn name(&self) -> Option<&String> {
+        match self {
+            Block::Module(name) | Block::Impl(name) => Some(name),
+            Block::Other => None,
+        }
+    }
+}
+
+/// The path of the item `name` in `blocks`.
+fn item_path(blocks: &[Block], name: Option<&str>) -> Vec<String> {
+    blocks
+        .iter()
+        .filter_map(Block::name)
+        .cloned()
+        .chain(name.map(str::to_owned))
+        .collect()
+}
+
+/// Returns the name of the type an impl is for, given the tokens following
+/// `impl`.
+fn impl_self_type(tokens: &[Token<'_>]) -> String {
+    let mut depth = 0;
+    let mut name = "";
+    let mut prev = None;
+    for &token in tokens {
+        match token {
+            Token::Punct(b'<') => depth += 1,
+            // Not the arrow of an `Fn` bound.
+            Token::Punct(b'>') if prev != Some(Token::Punct(b'-')) => depth -= 1,
+            Token::Punct(b'{') | Token::Punct(b';') => break,
+            Token::Ident("where", _) if depth == 0 => break,
+            Token::Ident("for", _) if depth == 0 => name = "",
+            Token::Ident(ident, _) if depth == 0 => name = ident,
+            _ => {}
+        }
+        prev = Some(token);
+    }
+    name.to_owned()
+}
+
+/// Finds the tests, benches, `main` function and documented examples in
+/// `text`, and the inline modules containing tests. Tests, benches and `main`
+/// are only looked for directly in modules, since they can't be nested in
+/// functions or impls.
+fn find_runnables(text: &str) -> Vec<Runnable> {
+    const ITEM_KEYWORDS: &[&str] = &[
+        "fn", "mod", "struct", "enum", "union", "trait", "type", "const", "static",
+    ];
+    let tokens = tokenize(text);
+
+    let mut runnables = vec![];
+    let mut modules = vec![];
+    // The blocks enclosing the current token.
+    let mut blocks: Vec<Block> = vec![];
+    // The kind of block the next `{` opens, if it's not `Block::Other`.
+    let mut next_block = None;
+    // The paths of the attributes on the current item.
+    let mut attrs: Vec<String> = vec![];
+    // The first example in the current item's documentation.
+    let mut example = None;
+    let mut i = 0;
+    while i < tokens.len() {
+        let (in_module, in_items) = match blocks.last() {
+            None | Some(Block::Module(_)) => (true, true),
+            Some(Block::Impl(_)) => (false, true),
+            Some(Block::Other) => (false, false),
+        };
+        match (tokens[i], tokens.get(i + 1), tokens.get(i + 2)) {
+            (Token::Doc(true, start, end), ..) if in_module => {
+                if let Some(range) = doc_example(text, start, end) {
+                    runnables.push(Runnable {
+                        kind: RunnableKind::Doctest,
+                        path: item_path(&blocks, None),
+                        range,
+                    });
+                }
+            }
+            (Token::Doc(false, start, end), ..) if in_items => {
+                example = doc_example(text, start, end);
+            }
+            (Token::Punct(b'#'), Some(Token::Punct(b'[')), _) if in_items => {
+                let mut path = vec![];
+                i += 2;
+                while let Some(&Token::Ident(segment, _)) = tokens.get(i) {
+                    path.push(segment);
+                    i += 1;
+                    if tokens.get(i) == Some(&Token::Punct(b':'))
+                        && tokens.get(i + 1) == Some(&Token::Punct(b':'))
+                    {
+                        i += 2;
+                    } else {
+                        break;
+                    }
+                }
+                attrs.push(path.join("::"));
+
+                let mut depth = 1;
+                while i < tokens.len() && depth > 0 {
+                    match tokens[i] {
+                        Token::Punct(b'[') => depth += 1,
+                        Token::Punct(b']') => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                continue;
+            }
+            // Not an `impl Trait` in a function's signature.
+            (Token::Ident("impl", _), ..) if in_module && next_block.is_none() => {
+                next_block = Some(Block::Impl(impl_self_type(&tokens[i + 1..])));
+                attrs.clear();
+                example = None;
+            }
+            (Token::Ident(keyword, _), Some(&Token::Ident(name, start)), next)
+                if in_items
+                    && ITEM_KEYWORDS.contains(&keyword)
+                    && name != "fn"
+                    && name != "mut" =>
+            {
+                let path = item_path(&blocks, Some(name));
+                let range = (start, start + name.len());
+                if let Some(example) = example.take() {
+                    runnables.push(Runnable {
+                        kind: RunnableKind::Doctest,
+                        path: path.clone(),
+                        range: example,
+                    });
+                }
+                match keyword {
+                    "fn" => {
+                        // `#[test]`, or a runtime's replacement such as
+                        // `#[tokio::test]`.
+                        let is_test = attrs
+                            .iter()
+                            .any(|attr| attr == "test" || attr.ends_with("::test"));
+                        let kind = if is_test {
+                            Some(RunnableKind::Test)
+                        } else if attrs.iter().any(|attr| attr == "bench") {
+                            Some(RunnableKind::Bench)
+                        } else if name == "main" && blocks.is_empty() {
+                            Some(RunnableKind::Main)
+                        } else {
+                            None
+                        };
+                        match kind {
+                            Some(kind) if in_module => {
+                                runnables.push(Runnable { kind, path, range })
+                            }
+                            _ => {}
+                        }
+                        next_block = Some(Block::Other);
+                    }
+                    "mod" if next == Some(&Token::Punct(b'{')) => {
+                        modules.push(Runnable {
+                            kind: RunnableKind::TestModule,
+                            path,
+                            range,
+                        });
+                        next_block = Some(Block::Module(name.to_owned()));
+                    }
+                    "trait" => next_block = Some(Block::Impl(name.to_owned())),
+                    _ => {}
+                }
+                attrs.clear();
+                i += 2;
+                continue;
+            }
+            (Token::Punct(b'{'), ..) => {
+                blocks.push(next_block.take().unwrap_or(Block::Other));
+                attrs.clear();
+                example = None;
+            }
+            (Token::Punct(b'}'), ..) => {
+                blocks.pop();
+                next_block = None;
+                attrs.clear();
+                example = None;
+            }
+            (Token::Punct(b';'), ..) => {
+                next_block = None;
+                attrs.clear();
+                example = None;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let tests: Vec<&Runnable> = runnables
+        .iter()
+        .filter(|runnable| runnable.kind == RunnableKind::Test)
+        .collect();
+    let modules: Vec<Runnable> = modules
+        .into_iter()
+        .filter(|module| tests.iter().any(|test| test.path.starts_with(&module.path)))
+        .collect();
+    runnables.extend(modules);
+    runnables.sort_by_key(|runnable| runnable.range);
+    runnables
 }
 
 pub struct LineIndex {
@@ -93,3 +769,151 @@ impl LineIndex {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runnables_in(text: &str) -> Vec<(RunnableKind, String, &str)> {
+        find_runnables(text)
+            .into_iter()
+            .map(|runnable| {
+                (
+                    runnable.kind,
+                    runnable.path.join("::"),
+                    &text[runnable.range.0..runnable.range.1],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_tests() {
+        let text = r##"
+#[test]
+fn plain() {}
+
+#[test]
+#[should_panic(expected = "}")]
+pub async fn with_attributes() {
+    let s = "#[test] fn in_string() {}";
+}
+
+#[tokio::test]
+async fn tokio() {}
+
+/// #[test]
+fn not_a_test() {
+    #[test]
+    fn nested() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Foo { f: fn() }
+
+    impl Foo {
+        #[test]
+        fn in_impl() {}
+    }
+
+    mod r#inner {
+        #[test] fn r#raw() {}
+    }
+}
+
+mod no_tests {
+    fn helper() {}
+}
+"##;
+        assert_eq!(
+            runnables_in(text),
+            vec![
+                (RunnableKind::Test, "plain".to_owned(), "plain"),
+                (
+                    RunnableKind::Test,
+                    "with_attributes".to_owned(),
+                    "with_attributes"
+                ),
+                (RunnableKind::Test, "tokio".to_owned(), "tokio"),
+                (RunnableKind::TestModule, "tests".to_owned(), "tests"),
+                (RunnableKind::TestModule, "tests::inner".to_owned(), "inner"),
+                (RunnableKind::Test, "tests::inner::raw".to_owned(), "raw"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_main_benches_and_examples() {
+        let text = r##"
+//! ```
+//! assert!(true);
+//! ```
+
+/// Adds one.
+///
+/// ```text
+/// not rust
+/// ```
+///
+/// ```rust,should_panic
+/// add_one(u32::max_value());
+/// ```
+pub fn add_one(x: u32) -> u32 { x + 1 }
+
+/// ```ignore
+/// ignored();
+/// ```
+pub struct Ignored;
+
+impl<T: Fn() -> u8> fmt::Debug for Wrapper<T> {
+    /**
+     * ```
+     * Wrapper::new();
+     * ```
+     */
+    fn new(f: impl Fn() -> u8) -> Self {
+        fn main() {}
+    }
+}
+
+#[bench]
+fn bench_add(b: &mut Bencher) {}
+
+fn main() {}
+"##;
+        assert_eq!(
+            runnables_in(text),
+            vec![
+                (RunnableKind::Doctest, "".to_owned(), "```"),
+                (
+                    RunnableKind::Doctest,
+                    "add_one".to_owned(),
+                    "```rust,should_panic"
+                ),
+                (RunnableKind::Doctest, "Wrapper::new".to_owned(), "```"),
+                (RunnableKind::Bench, "bench_add".to_owned(), "bench_add"),
+                (RunnableKind::Main, "main".to_owned(), "main"),
+            ]
+        );
+    }
+
+    #[test]
+    fn module_paths() {
+        let lib = Path::new("/project/src/lib.rs");
+        let path = |file: &str| module_path(Path::new(file), lib);
+        assert_eq!(path("/project/src/lib.rs"), Some(vec![]));
+        assert_eq!(path("/project/src/foo.rs"), Some(vec!["foo".to_owned()]));
+        assert_eq!(
+            path("/project/src/foo/bar.rs"),
+            Some(vec!["foo".to_owned(), "bar".to_owned()])
+        );
+        assert_eq!(
+            path("/project/src/foo/mod.rs"),
+            Some(vec!["foo".to_owned()])
+        );
+        assert_eq!(path("/project/tests/foo.rs"), None);
+    }
+}
@@ -0,0 +1,441 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Support for `textDocument/signatureHelp`: finds the call surrounding the
+//! cursor in the VFS buffer and renders the signature of its callee.
+
+use crate::actions::hover;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+
+use log::{debug, trace};
+use rls_analysis::DefKind;
+use rls_vfs::FileContents;
+
+/// Keywords which may directly precede a parenthesized expression, and so
+/// can't be the callee of a call.
+const NON_CALLEE_KEYWORDS: &[&str] = &["if", "while", "match", "return", "in", "for", "as"];
+
+/// The call expression surrounding a cursor.
+#[derive(Debug, PartialEq)]
+struct CallSite {
+    /// Byte offset of the start of the callee identifier.
+    callee: usize,
+    /// Number of top-level arguments preceding the cursor.
+    active_parameter: usize,
+    /// Whether the callee is invoked with method-call syntax, i.e. `x.foo(..)`.
+    method_call: bool,
+}
+
+/// Builds a signature help response for the call surrounding the requested
+/// position. Returns an empty response if the cursor is not inside the
+/// argument list of a function or method call.
+pub fn signature_help(
+    ctx: &InitActionContext,
+    params: &TextDocumentPositionParams,
+) -> Result<SignatureHelp, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "signature_help")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Ok(empty_signature_help()),
+    };
+
+    let offset = match position_to_offset(&text, params.position) {
+        Some(offset) => offset,
+        None => return Ok(empty_signature_help()),
+    };
+
+    let call_site = match find_call_site(&text[..offset]) {
+        Some(call_site) => call_site,
+        None => {
+            trace!("signature_help: no call site at {:?}", params.position);
+            return Ok(empty_signature_help());
+        }
+    };
+    trace!("signature_help: call site: {:?}", call_site);
+
+    let callee_pos = offset_to_position(&text, call_site.callee);
+    let callee_span = ctx.convert_pos_to_span(file_path, callee_pos);
+
+    let analysis = &ctx.project.analysis;
+    let def = analysis
+        .id(&callee_span)
+        .and_then(|id| analysis.get_def(id))
+        .ok()
+        .or_else(|| {
+            if ctx.config.lock().unwrap().goto_def_racer_fallback {
+                debug!("signature_help: no def for callee, attempting with racer");
+                hover::racer_def(ctx, &callee_span)
+            } else {
+                None
+            }
+        });
+
+    let def = match def {
+        Some(def) => def,
+        None => return Ok(empty_signature_help()),
+    };
+    match def.kind {
+        DefKind::Function | DefKind::Method | DefKind::ForeignFunction => {}
+        _ => return Ok(empty_signature_help()),
+    }
+
+    let label = hover::function_signature(ctx, &def);
+    let mut parameters = signature_parameters(&label);
+    if call_site.method_call && parameters.first().map_or(false, |p| is_self_param(p)) {
+        parameters.remove(0);
+    }
+    let active_parameter = call_site.active_parameter.min(parameters.len().saturating_sub(1));
+
+    let documentation = hover::def_docs(&def, &ctx.vfs).map(|docs| {
+        Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: docs,
+        })
+    });
+
+    Ok(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation,
+            parameters: Some(
+                parameters
+                    .into_iter()
+                    .map(|label| ParameterInformation {
+                        label,
+                        documentation: None,
+                    })
+                    .collect(),
+            ),
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(active_parameter as u64),
+    })
+}
+
+pub fn empty_signature_help() -> SignatureHelp {
+    SignatureHelp {
+        signatures: vec![],
+        active_signature: None,
+        active_parameter: None,
+    }
+}
+
+/// Converts an LSP position into a byte offset into `text`. Like the rest of
+/// the RLS, this counts `Position::character` in chars.
+fn position_to_offset(text: &str, pos: Position) -> Option<usize> {
+    let mut offset = 0;
+    for (row, line) in text.split('\n').enumerate() {
+        if row as u64 == pos.line {
+            let col = line
+                .char_indices()
+                .nth(pos.character as usize)
+                .map(|(i, _)| i)
+                .unwrap_or_else(|| line.len());
+            return Some(offset + col);
+        }
+        offset += line.len() + 1;
+    }
+    None
+}
+
+/// Converts a byte offset into `text` into an LSP position, the inverse of
+/// `position_to_offset`.
+fn offset_to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u64,
+        before[line_start..].chars().count() as u64,
+    )
+}
+
+/// Finds the innermost unclosed call in `text`, which is expected to be the
+/// buffer contents preceding the cursor.
+fn find_call_site(text: &str) -> Option<CallSite> {
+    // Open delimiters: (delimiter, byte offset, top-level comma count)
+    let mut open: Vec<(char, usize, usize)> = vec![];
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let mut escaped = false;
+                for (_, c) in &mut chars {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            // Skip char literals such as `'('` or `'\''`, but not lifetimes.
+            '\'' => {
+                let mut rest = text[i + 1..].chars();
+                match (rest.next(), rest.next()) {
+                    (Some('\\'), _) => {
+                        // Skip the backslash and the escaped character
+                        chars.next();
+                        chars.next();
+                        for (_, c) in &mut chars {
+                            if c == '\'' {
+                                break;
+                            }
+                        }
+                    }
+                    (Some(_), Some('\'')) => {
+                        chars.next();
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            }
+            '/' if chars.peek().map(|&(_, c)| c) == Some('/') => {
+                for (_, c) in &mut chars {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek().map(|&(_, c)| c) == Some('*') => {
+                chars.next();
+                // Block comments nest
+                let mut depth = 1;
+                while let Some((_, c)) = chars.next() {
+                    match (c, chars.peek().map(|&(_, c)| c)) {
+                        ('*', Some('/')) => {
+                            chars.next();
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        ('/', Some('*')) => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            '(' | '[' | '{' => open.push((c, i, 0)),
+            ')' | ']' | '}' => {
+                open.pop();
+            }
+            ',' => {
+                if let Some(innermost) = open.last_mut() {
+                    innermost.2 += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (delim, paren, commas) = *open.last()?;
+    if delim != '(' {
+        return None;
+    }
+
+    let before = skip_turbofish(text[..paren].trim_end())?;
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let callee = before
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_ident_char(c))
+        .last()
+        .map(|(i, _)| i)?;
+
+    let ident = &before[callee..];
+    if ident.starts_with(|c: char| c.is_numeric()) || NON_CALLEE_KEYWORDS.contains(&ident) {
+        return None;
+    }
+
+    Some(CallSite {
+        callee,
+        active_parameter: commas,
+        method_call: before[..callee].trim_end().ends_with('.'),
+    })
+}
+
+/// Strips a trailing turbofish (`::<T>`) from `text`. Returns `None` if the
+/// generic arguments are unbalanced.
+fn skip_turbofish(text: &str) -> Option<&str> {
+    if !text.ends_with('>') {
+        return Some(text);
+    }
+
+    let mut depth = 0;
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            '>' => depth += 1,
+            '<' => {
+                depth -= 1;
+                if depth == 0 {
+                    let path = text[..i].trim_end();
+                    return if path.ends_with("::") {
+                        Some(path[..path.len() - 2].trim_end())
+                    } else {
+                        None
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits the argument list of a rendered function signature into the
+/// individual parameter declarations.
+fn signature_parameters(signature: &str) -> Vec<String> {
+    let mut params = vec![];
+    let mut current = String::new();
+    // Depth of `<>`, `()` and `[]` nesting, with the argument list at depth 1
+    let mut depth = 0;
+    let mut in_args = false;
+    let mut prev = ' ';
+
+    for c in signature.chars() {
+        match c {
+            '(' if depth == 0 && !in_args => {
+                in_args = true;
+                depth = 1;
+                prev = c;
+                continue;
+            }
+            '<' | '(' | '[' => depth += 1,
+            // `->` inside generic bounds, e.g. `F: Fn() -> u32`
+            '>' if prev == '-' => {}
+            '>' | ')' | ']' => {
+                depth -= 1;
+                if in_args && depth == 0 {
+                    break;
+                }
+            }
+            ',' if in_args && depth == 1 => {
+                params.push(current.trim().to_owned());
+                current.clear();
+                prev = c;
+                continue;
+            }
+            _ => {}
+        }
+        if in_args {
+            current.push(c);
+        }
+        prev = c;
+    }
+
+    if in_args {
+        params.push(current.trim().to_owned());
+    }
+    params.retain(|p| !p.is_empty());
+    params
+}
+
+fn is_self_param(param: &str) -> bool {
+    let param = param.trim_start_matches('&').trim();
+    let param = if param.starts_with('\'') {
+        param.splitn(2, ' ').nth(1).unwrap_or("").trim()
+    } else {
+        param
+    };
+    let param = param.trim_start_matches("mut ").trim();
+    param == "self" || param.starts_with("self:")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call_site(text: &str) -> Option<(&str, usize, bool)> {
+        find_call_site(text).map(|site| {
+            let ident_len = text[site.callee..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap();
+            (
+                &text[site.callee..site.callee + ident_len],
+                site.active_parameter,
+                site.method_call,
+            )
+        })
+    }
+
+    #[test]
+    fn test_find_call_site() {
+        assert_eq!(call_site("foo("), Some(("foo", 0, false)));
+        assert_eq!(call_site("foo(a, b"), Some(("foo", 1, false)));
+        assert_eq!(call_site("x.bar(a, "), Some(("bar", 1, true)));
+        assert_eq!(call_site("foo(bar(1, 2), "), Some(("foo", 1, false)));
+        assert_eq!(call_site("foo(bar(1, "), Some(("bar", 1, false)));
+        assert_eq!(call_site("foo::<Vec<u8>>(a, "), Some(("foo", 1, false)));
+        assert_eq!(call_site("foo(\"a, (b\", "), Some(("foo", 1, false)));
+        assert_eq!(call_site("foo(',', '(', "), Some(("foo", 2, false)));
+        assert_eq!(call_site("foo('\\'', ')', "), Some(("foo", 2, false)));
+        assert_eq!(call_site("foo(&[1, 2], "), Some(("foo", 1, false)));
+        assert_eq!(call_site("foo(/* a, (b */ c, "), Some(("foo", 1, false)));
+        assert_eq!(
+            call_site("foo(/* /* ) */ , */ a, "),
+            Some(("foo", 1, false))
+        );
+        assert_eq!(call_site("foo(a) /* bar( */"), None);
+        assert_eq!(call_site("foo(x)"), None);
+        assert_eq!(call_site("foo(&[1, "), None);
+        assert_eq!(call_site("if (a"), None);
+        assert_eq!(call_site("(a, "), None);
+    }
+
+    #[test]
+    fn test_signature_parameters() {
+        assert_eq!(signature_parameters("fn foo()"), Vec::<String>::new());
+        assert_eq!(
+            signature_parameters("fn foo(a: u32, b: &str) -> bool"),
+            vec!["a: u32", "b: &str"]
+        );
+        assert_eq!(
+            signature_parameters("pub fn foo<F: Fn(u32) -> u32>(f: F, x: HashMap<u32, u32>)"),
+            vec!["f: F", "x: HashMap<u32, u32>"]
+        );
+        assert_eq!(
+            signature_parameters("fn foo(\n    &self,\n    a: (u32, u32),\n) -> u32"),
+            vec!["&self", "a: (u32, u32)"]
+        );
+    }
+
+    #[test]
+    fn test_is_self_param() {
+        assert!(is_self_param("self"));
+        assert!(is_self_param("&self"));
+        assert!(is_self_param("&mut self"));
+        assert!(is_self_param("&'a mut self"));
+        assert!(is_self_param("self: Box<Self>"));
+        assert!(!is_self_param("selfish: u32"));
+    }
+
+    #[test]
+    fn test_position_to_offset() {
+        let text = "fn main() {\n    foo(1, 2);\n}\n";
+        assert_eq!(position_to_offset(text, Position::new(0, 0)), Some(0));
+        assert_eq!(position_to_offset(text, Position::new(1, 8)), Some(20));
+        assert_eq!(position_to_offset(text, Position::new(5, 0)), None);
+    }
+
+    #[test]
+    fn test_non_ascii_positions() {
+        let text = "fn main() {\n    let é = \"ü\"; foo(1, 2);\n}\n";
+        let foo = text.find("foo").unwrap();
+        assert_eq!(offset_to_position(text, foo), Position::new(1, 17));
+        assert_eq!(position_to_offset(text, Position::new(1, 17)), Some(foo));
+        assert_eq!(offset_to_position(text, 0), Position::new(0, 0));
+    }
+}
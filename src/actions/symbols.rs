@@ -0,0 +1,806 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Symbol search: hierarchical `textDocument/documentSymbol` results, nesting
+//! each definition under its parent (methods under `impl`s, items under `mod`s
+//! etc.), and ranked `workspace/symbol` results.
+
+use crate::actions::folding::{skip_char, skip_raw_string, skip_string};
+use crate::actions::InitActionContext;
+use crate::lsp_data::{
+    ls_util, source_kind_from_def_kind, DocumentSymbol, Position, Range, SymbolInformation,
+};
+use crate::project_model::ProjectModel;
+use crate::Span;
+
+use log::debug;
+use rls_analysis::{AnalysisHost, DefKind, SymbolQuery};
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The maximum number of `workspace/symbol` results.
+const MAX_WORKSPACE_SYMBOLS: usize = 512;
+
+/// Returns the symbols defined in `file` (whose contents are `text`) as a tree.
+pub fn document_symbols(analysis: &AnalysisHost, file: &Path, text: &str) -> Vec<DocumentSymbol> {
+    let symbols = analysis.symbols(file).unwrap_or_else(|_| vec![]);
+    let line_starts = line_starts(text);
+    let nodes = symbols
+        .into_iter()
+        .filter(|s| {
+            let range = ls_util::rls_to_range(s.span.range);
+            range.start != range.end
+        })
+        .map(|s| {
+            let symbol = DocumentSymbol {
+                range: item_range(text, &line_starts, &s.span, s.kind),
+                selection_range: ls_util::rls_to_range(s.span.range),
+                name: s.name,
+                detail: None,
+                kind: source_kind_from_def_kind(s.kind),
+                deprecated: None,
+                children: None,
+            };
+            (s.id, s.parent, symbol)
+        })
+        .collect();
+    build_tree(nodes)
+}
+
+/// Nests `nodes`, which are `(id, parent id, symbol)`, under their parents.
+/// Nodes whose parent is not among them are roots. Siblings are ordered by
+/// their position in the file.
+fn build_tree<K: Hash + Eq + Copy>(
+    nodes: Vec<(K, Option<K>, DocumentSymbol)>,
+) -> Vec<DocumentSymbol> {
+    let index: HashMap<K, usize> = nodes.iter().enumerate().map(|(i, n)| (n.0, i)).collect();
+    let mut children: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+    let mut roots = vec![];
+    for (i, (_, parent, _)) in nodes.iter().enumerate() {
+        match parent.and_then(|p| index.get(&p)) {
+            Some(&p) if p != i => children[p].push(i),
+            _ => roots.push(i),
+        }
+    }
+
+    let mut symbols: Vec<Option<DocumentSymbol>> = nodes.into_iter().map(|n| Some(n.2)).collect();
+    fn assemble(
+        i: usize,
+        symbols: &mut Vec<Option<DocumentSymbol>>,
+        children: &[Vec<usize>],
+    ) -> Option<DocumentSymbol> {
+        // Taking the symbol guards against cycles in the parent links.
+        let mut symbol = symbols[i].take()?;
+        let mut nested: Vec<_> = children[i]
+            .iter()
+            .filter_map(|&c| assemble(c, symbols, children))
+            .collect();
+        if !nested.is_empty() {
+            nested.sort_by_key(|s| s.range.start);
+            symbol.children = Some(nested);
+        }
+        Some(symbol)
+    }
+
+    let mut tree: Vec<_> = roots
+        .into_iter()
+        .filter_map(|i| assemble(i, &mut symbols, &children))
+        .collect();
+    tree.sort_by_key(|s| s.range.start);
+    tree
+}
+
+/// Returns the offset of the start of each line in `text`.
+fn line_starts(text: &str) -> Vec<usize> {
+    Some(0)
+        .into_iter()
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// Returns the range of the whole definition whose name is at `ident`, i.e.
+/// including its attributes, doc comments, signature and body. `line_starts`
+/// are those of `text` (see `line_starts`).
+///
+/// This works on the source text, since the save-analysis data only records
+/// where definitions are named.
+fn item_range(text: &str, line_starts: &[usize], ident: &Span, kind: DefKind) -> Range {
+    let ident_range = ls_util::rls_to_range(ident.range);
+    let offset = |pos: Position| -> Option<usize> {
+        let start = *line_starts.get(pos.line as usize)?;
+        let line = text[start..].lines().next().unwrap_or("");
+        let col = line
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(line.len()))
+            .nth(pos.character as usize)?;
+        Some(start + col)
+    };
+    let (ident_start, ident_end) = match (offset(ident_range.start), offset(ident_range.end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return ident_range,
+    };
+
+    let end = match kind {
+        // Bindings, e.g., in patterns or arguments, can't be told apart from
+        // `let` statements, so stick to their names.
+        DefKind::Local => return ident_range,
+        DefKind::Field | DefKind::TupleVariant | DefKind::StructVariant => End::List,
+        DefKind::Const
+        | DefKind::Static
+        | DefKind::Type
+        | DefKind::ExternType
+        | DefKind::ForeignStatic => End::Statement,
+        _ => End::Block,
+    };
+    let (start, end) = (
+        item_start(text, ident_start),
+        item_end(text, ident_end, end),
+    );
+
+    let position = |offset: usize| {
+        let line = match line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let character = text[line_starts[line]..offset].chars().count();
+        Position::new(line as u64, character as u64)
+    };
+    Range::new(position(start), position(end))
+}
+
+/// Returns the start of the line containing `ident`, or of the attributes and
+/// doc comments (`///` or `/** */`) above it.
+fn item_start(text: &str, ident: usize) -> usize {
+    let line_start = text[..ident].rfind('\n').map_or(0, |i| i + 1);
+    let indent = text[line_start..ident].len() - text[line_start..ident].trim_start().len();
+    let mut start = line_start + indent;
+
+    loop {
+        let before = text[..start].trim_end();
+        let prev_line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let prev_line = &before[prev_line_start..];
+        let prev_start = if prev_line.trim_start().starts_with("///") {
+            Some(prev_line_start + (prev_line.len() - prev_line.trim_start().len()))
+        } else if before.ends_with("*/") {
+            before
+                .rfind("/*")
+                .filter(|&i| is_block_doc_comment(&before[i..]))
+        } else if before.ends_with(']') {
+            // The attribute may span several lines, so look for the `#[`
+            // whose `]` this is.
+            let close = before.len() - 1;
+            before
+                .rmatch_indices("#[")
+                .map(|(i, _)| i)
+                .find(|&i| matching_bracket(text, i + 1) == Some(close))
+        } else {
+            None
+        };
+        match prev_start {
+            Some(prev_start) => start = prev_start,
+            None => return start,
+        }
+    }
+}
+
+/// Whether `comment` (starting with `/*`) is an outer block doc comment.
+fn is_block_doc_comment(comment: &str) -> bool {
+    comment.starts_with("/**") && !comment.starts_with("/***") && !comment.starts_with("/**/")
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum End {
+    /// Items ending with their `{ ... }` body, or a `;`.
+    Block,
+    /// Items ending with a `;`.
+    Statement,
+    /// Fields and variants, ending before the `,` separating them from the
+    /// next one.
+    List,
+}
+
+/// Returns the end of the definition whose name ends at `from`, skipping over
+/// comments and literals.
+fn item_end(text: &str, from: usize, end: End) -> usize {
+    let bytes = text.as_bytes();
+    let is_ident_byte = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    let mut depth = 0;
+    let mut angle_depth = 0;
+    let mut i = from;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = text[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = text[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |n| i + 2 + n + 2);
+                continue;
+            }
+            b'"' => {
+                i = skip_string(bytes, i + 1);
+                continue;
+            }
+            b'r' if !is_ident_byte(bytes[i - 1]) => {
+                if let Some(end) = skip_raw_string(text, i + 1) {
+                    i = end;
+                    continue;
+                }
+            }
+            b'\'' => {
+                if let Some(end) = skip_char(text, i + 1) {
+                    i = end;
+                    continue;
+                }
+            }
+            b'<' => angle_depth += 1,
+            b'>' if bytes[i - 1] != b'-' && bytes[i - 1] != b'=' && angle_depth > 0 => {
+                angle_depth -= 1
+            }
+            b'{' if depth == 0 && end == End::Block => {
+                return matching_bracket(text, i).map_or(bytes.len(), |close| close + 1);
+            }
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' if depth == 0 => return text[..i].trim_end().len(),
+            b')' | b']' | b'}' => depth -= 1,
+            b';' if depth == 0 => return i + 1,
+            b',' if depth == 0 && angle_depth == 0 && end == End::List => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Returns the offset of the `}` (or `]`) closing the `{` (or `[`) at `open`.
+fn matching_bracket(text: &str, open: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let (open_byte, close_byte) = match bytes[open] {
+        b'[' => (b'[', b']'),
+        _ => (b'{', b'}'),
+    };
+    let mut depth = 0;
+    let mut i = open;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = text[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = text[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |n| i + 2 + n + 2);
+                continue;
+            }
+            b'"' => {
+                i = skip_string(bytes, i + 1);
+                continue;
+            }
+            b'\'' => {
+                if let Some(end) = skip_char(text, i + 1) {
+                    i = end;
+                    continue;
+                }
+            }
+            b if b == open_byte => depth += 1,
+            b if b == close_byte => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Returns the definitions matching `query` in every project, best matches
+/// first. See `SymbolSearch` for the query syntax.
+pub fn workspace_symbols(ctx: &InitActionContext, query: &str) -> Vec<SymbolInformation> {
+    let search = SymbolSearch::parse(query);
+
+    let mut candidates = vec![];
+    let mut seen = HashSet::new();
+    for project in ctx.projects() {
+        let model = project
+            .model(&ctx.vfs)
+            .map_err(|e| debug!("workspace_symbols: no project model: {}", e))
+            .ok();
+        let analysis = &project.analysis;
+        let roots = analysis.def_roots().unwrap_or_else(|_| vec![]);
+        let mut packages = PackageCache::new(model, roots);
+
+        // The analysis truncates results before we get to rank them, so ask
+        // for prefix matches separately rather than risk losing them among
+        // the subsequence matches.
+        let query = |query: SymbolQuery| {
+            analysis
+                .query_defs(query.limit(MAX_WORKSPACE_SYMBOLS))
+                .unwrap_or_else(|_| vec![])
+        };
+        let mut defs = query(SymbolQuery::prefix(&search.name));
+        defs.extend(query(SymbolQuery::subsequence(&search.name)));
+
+        for def in defs {
+            // Definitions can be reported more than once, e.g., by crates
+            // re-exporting them, or by every project depending on the same crate.
+            if !seen.insert((def.span.clone(), def.name.clone())) || !search.accepts_kind(def.kind)
+            {
+                continue;
+            }
+            let quality = match match_quality(&search.name, &def.name) {
+                Some(quality) => quality,
+                None => continue,
+            };
+            let (crate_name, member) = packages.crate_of(&def.span.file);
+            if !search.accepts_path(
+                &def.qualname,
+                crate_name.as_ref().map(String::as_str),
+                member,
+            ) {
+                continue;
+            }
+            let container_name = def
+                .parent
+                .and_then(|id| analysis.get_def(id).ok())
+                .map(|parent| parent.name);
+            candidates.push((quality, member, def, container_name));
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        (Reverse(a.0), Reverse(a.1), a.2.name.len(), &a.2.name).cmp(&(
+            Reverse(b.0),
+            Reverse(b.1),
+            b.2.name.len(),
+            &b.2.name,
+        ))
+    });
+    candidates
+        .into_iter()
+        .take(MAX_WORKSPACE_SYMBOLS)
+        .map(|(_, _, def, container_name)| SymbolInformation {
+            name: def.name,
+            kind: source_kind_from_def_kind(def.kind),
+            location: ls_util::rls_to_location(&def.span),
+            container_name,
+            deprecated: None,
+        })
+        .collect()
+}
+
+/// A parsed `workspace/symbol` query.
+///
+/// Besides the (fuzzily matched) name, a query can contain a kind filter such
+/// as `#fn` or `#struct`, and qualify the name with a path, e.g.,
+/// `serde::de::Deserialize` or `crate::config::Config` (where `crate` stands
+/// for any crate of the workspace). Path segments can be abbreviated to a
+/// prefix, and need not be complete (`std::HashMap` finds
+/// `std::collections::HashMap`).
+#[derive(Debug, PartialEq)]
+struct SymbolSearch {
+    name: String,
+    kinds: Option<&'static [DefKind]>,
+    path: Vec<String>,
+}
+
+impl SymbolSearch {
+    fn parse(query: &str) -> SymbolSearch {
+        let mut search = SymbolSearch {
+            name: String::new(),
+            kinds: None,
+            path: vec![],
+        };
+        for word in query.split_whitespace() {
+            if word.starts_with('#') {
+                search.kinds = def_kinds(&word[1..]);
+                continue;
+            }
+            let mut segments: Vec<_> = word.split("::").map(str::to_owned).collect();
+            search.name = segments.pop().unwrap_or_default();
+            search.path = segments.into_iter().filter(|s| !s.is_empty()).collect();
+        }
+        search
+    }
+
+    fn accepts_kind(&self, kind: DefKind) -> bool {
+        self.kinds.map_or(true, |kinds| kinds.contains(&kind))
+    }
+
+    /// Whether the definition called `qualname` (which doesn't include its
+    /// crate), in the crate `crate_name`, lies under the query's path.
+    fn accepts_path(&self, qualname: &str, crate_name: Option<&str>, member: bool) -> bool {
+        let mut query = self.path.iter().map(|s| s.to_lowercase()).peekable();
+        if query.peek().map_or(false, |s| s == "crate") {
+            if !member {
+                return false;
+            }
+            query.next();
+        }
+
+        let mut segments = crate_name
+            .into_iter()
+            .chain(qualname.split("::"))
+            .map(|s| s.trim_matches(|c| c == '<' || c == '>').to_lowercase());
+        query.all(|q| segments.any(|s| s.starts_with(&q)))
+    }
+}
+
+/// The definition kinds a `#kind` filter selects.
+fn def_kinds(filter: &str) -> Option<&'static [DefKind]> {
+    let kinds: &'static [DefKind] = match filter {
+        "fn" => &[DefKind::Function, DefKind::Method, DefKind::ForeignFunction],
+        "method" => &[DefKind::Method],
+        "struct" => &[DefKind::Struct],
+        "enum" => &[DefKind::Enum],
+        "union" => &[DefKind::Union],
+        "trait" => &[DefKind::Trait],
+        "mod" => &[DefKind::Mod],
+        "const" => &[DefKind::Const],
+        "static" => &[DefKind::Static, DefKind::ForeignStatic],
+        "type" => &[DefKind::Type, DefKind::ExternType],
+        "macro" => &[DefKind::Macro],
+        "field" => &[DefKind::Field],
+        "variant" => &[DefKind::TupleVariant, DefKind::StructVariant],
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// How well a name matches a query, from worst to best.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Match {
+    /// The query's characters appear in order in the name.
+    Subsequence,
+    Substring,
+    /// The query is made up of prefixes of the name's words, e.g., `HM` or
+    /// `hamap` for `HashMap`.
+    CamelHumps,
+    PrefixIgnoringCase,
+    Prefix,
+    ExactIgnoringCase,
+    Exact,
+}
+
+fn match_quality(query: &str, name: &str) -> Option<Match> {
+    let (lower_query, lower_name) = (query.to_lowercase(), name.to_lowercase());
+    if name == query {
+        Some(Match::Exact)
+    } else if lower_name == lower_query {
+        Some(Match::ExactIgnoringCase)
+    } else if name.starts_with(query) {
+        Some(Match::Prefix)
+    } else if lower_name.starts_with(&lower_query) {
+        Some(Match::PrefixIgnoringCase)
+    } else if camel_humps(&lower_query, name) {
+        Some(Match::CamelHumps)
+    } else if lower_name.contains(&lower_query) {
+        Some(Match::Substring)
+    } else {
+        let mut name_chars = lower_name.chars();
+        if lower_query.chars().all(|q| name_chars.any(|n| n == q)) {
+            Some(Match::Subsequence)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `query` (in lower case) is made up of prefixes of consecutive or
+/// later words of `name`, where words start after an `_` or at an upper case
+/// letter following a lower case one.
+fn camel_humps(query: &str, name: &str) -> bool {
+    let mut words: Vec<Vec<char>> = vec![];
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        let starts_word = match prev {
+            None | Some('_') => c != '_',
+            Some(p) => c.is_uppercase() && !p.is_uppercase(),
+        };
+        if starts_word {
+            words.push(vec![]);
+        }
+        if c != '_' {
+            if let Some(word) = words.last_mut() {
+                word.extend(c.to_lowercase());
+            }
+        }
+        prev = Some(c);
+    }
+
+    fn matches(query: &[char], words: &[Vec<char>]) -> bool {
+        if query.is_empty() {
+            return true;
+        }
+        (0..words.len()).any(|w| {
+            let common = query
+                .iter()
+                .zip(&words[w])
+                .take_while(|(q, c)| q == c)
+                .count();
+            (1..=common)
+                .rev()
+                .any(|n| matches(&query[n..], &words[w + 1..]))
+        })
+    }
+    let query: Vec<char> = query.chars().collect();
+    matches(&query, &words)
+}
+
+/// Looks up (and remembers) which crate a file belongs to.
+struct PackageCache {
+    model: Option<Arc<ProjectModel>>,
+    roots: CrateRoots,
+    files: HashMap<PathBuf, (Option<String>, bool)>,
+}
+
+impl PackageCache {
+    /// `roots` are the root files of the crates in the analysis, with their
+    /// names (see `AnalysisHost::def_roots`).
+    fn new(model: Option<Arc<ProjectModel>>, roots: Vec<(PathBuf, String)>) -> PackageCache {
+        PackageCache {
+            model,
+            roots: CrateRoots::new(roots),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Returns the name of the crate `file` belongs to, if known, and whether
+    /// it's a member of the workspace.
+    fn crate_of(&mut self, file: &Path) -> (Option<String>, bool) {
+        let (model, roots) = (&self.model, &self.roots);
+        self.files
+            .entry(file.to_owned())
+            .or_insert_with(|| {
+                // The name comes from the analysis rather than the project
+                // model, which doesn't know about the crates of the sysroot.
+                let name = roots.crate_of(file).map(str::to_owned);
+                let member = model.as_ref().map_or(false, |model| {
+                    model
+                        .package_for_file(file)
+                        .map_or(false, |pkg| pkg.is_workspace_member(model))
+                });
+                (name, member)
+            })
+            .clone()
+    }
+}
+
+/// The directories of crates' root files, deepest first, with the crates'
+/// names.
+struct CrateRoots(Vec<(PathBuf, String)>);
+
+impl CrateRoots {
+    fn new(roots: Vec<(PathBuf, String)>) -> CrateRoots {
+        let mut dirs: Vec<_> = roots
+            .into_iter()
+            .filter_map(|(file, name)| Some((file.parent()?.to_owned(), name)))
+            .collect();
+        dirs.sort_by_key(|(dir, _)| Reverse(dir.components().count()));
+        CrateRoots(dirs)
+    }
+
+    /// Returns the name of the crate whose root file is in the innermost
+    /// directory containing `file`.
+    fn crate_of(&self, file: &Path) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(dir, _)| file.starts_with(dir))
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_data::SymbolKind;
+    use rls_span::{Column, Row};
+    use std::path::PathBuf;
+
+    // The span of the first occurrence of `name` after `after` in `text`.
+    fn span_of(text: &str, after: &str, name: &str) -> Span {
+        let offset = text.find(after).unwrap() + after.len();
+        let offset = offset + text[offset..].find(name).unwrap();
+        let line = text[..offset].matches('\n').count() as u32;
+        let col = (offset - text[..offset].rfind('\n').map_or(0, |i| i + 1)) as u32;
+        Span::new(
+            Row::new_zero_indexed(line),
+            Row::new_zero_indexed(line),
+            Column::new_zero_indexed(col),
+            Column::new_zero_indexed(col + name.len() as u32),
+            PathBuf::from("lib.rs"),
+        )
+    }
+
+    fn range(start: (u64, u64), end: (u64, u64)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    const TEXT: &str = r#"/// A foo.
+#[derive(Debug)]
+pub struct Foo {
+    pub bar: HashMap<u8, String>,
+    baz: u8,
+}
+
+impl Foo {
+    pub fn new() -> Foo {
+        let s = "}";
+        Foo { bar: HashMap::new(), baz: b'}' }
+    }
+}
+
+const MAX: Foo = Foo { baz: 1 };
+"#;
+
+    #[test]
+    fn item_ranges() {
+        let line_starts = line_starts(TEXT);
+        let range_of = |ident: &Span, kind| item_range(TEXT, &line_starts, ident, kind);
+        assert_eq!(
+            range_of(&span_of(TEXT, "", "Foo"), DefKind::Struct),
+            range((0, 0), (5, 1))
+        );
+        assert_eq!(
+            range_of(&span_of(TEXT, "", "bar"), DefKind::Field),
+            range((3, 4), (3, 32))
+        );
+        assert_eq!(
+            range_of(&span_of(TEXT, "", "new"), DefKind::Method),
+            range((8, 4), (11, 5))
+        );
+        assert_eq!(
+            range_of(&span_of(TEXT, "", "MAX"), DefKind::Const),
+            range((14, 0), (14, 32))
+        );
+        let s = span_of(TEXT, "let ", "s");
+        assert_eq!(
+            range_of(&s, DefKind::Local),
+            ls_util::rls_to_range(s.range)
+        );
+    }
+
+    #[test]
+    fn item_starts() {
+        let text = r#"fn a() {}
+
+/** Block docs,
+ * over several lines. */
+#[cfg_attr(
+    test,
+    derive(Debug)
+)]
+#[doc = "]"]
+struct B;
+
+/* Not docs. */
+#[inline] #[must_use]
+fn c() {}
+"#;
+        let start = |name: &str| {
+            let offset = text.find(name).unwrap();
+            let start = item_start(text, offset);
+            let line = text[..start].matches('\n').count();
+            (line, start - text[..start].rfind('\n').map_or(0, |i| i + 1))
+        };
+        assert_eq!(start("a()"), (0, 0));
+        assert_eq!(start("B;"), (2, 0));
+        assert_eq!(start("c()"), (12, 0));
+    }
+
+    fn symbol(name: &str, line: u64) -> DocumentSymbol {
+        DocumentSymbol {
+            name: name.to_owned(),
+            detail: None,
+            kind: SymbolKind::Function,
+            deprecated: None,
+            range: range((line, 0), (line, 10)),
+            selection_range: range((line, 0), (line, 1)),
+            children: None,
+        }
+    }
+
+    #[test]
+    fn nests_symbols_under_parents() {
+        let tree = build_tree(vec![
+            (3, Some(1), symbol("b", 3)),
+            (1, None, symbol("a", 1)),
+            (2, Some(1), symbol("c", 2)),
+            (4, Some(9), symbol("d", 0)),
+        ]);
+
+        let names = |symbols: &[DocumentSymbol]| -> Vec<String> {
+            symbols.iter().map(|s| s.name.clone()).collect()
+        };
+        assert_eq!(names(&tree), vec!["d", "a"]);
+        assert_eq!(names(tree[1].children.as_ref().unwrap()), vec!["c", "b"]);
+        assert!(tree[0].children.is_none());
+    }
+
+    #[test]
+    fn parse_symbol_search() {
+        assert_eq!(
+            SymbolSearch::parse("#struct std::coll::HashMap"),
+            SymbolSearch {
+                name: "HashMap".to_owned(),
+                kinds: def_kinds("struct"),
+                path: vec!["std".to_owned(), "coll".to_owned()],
+            }
+        );
+        assert_eq!(
+            SymbolSearch::parse("foo #fn"),
+            SymbolSearch {
+                name: "foo".to_owned(),
+                kinds: def_kinds("fn"),
+                path: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn crate_roots() {
+        let roots = CrateRoots::new(vec![
+            (PathBuf::from("/rust/src/libstd/lib.rs"), "std".to_owned()),
+            (PathBuf::from("/rust/src/libcore/lib.rs"), "core".to_owned()),
+            (PathBuf::from("/foo/src/lib.rs"), "foo".to_owned()),
+            (PathBuf::from("/foo/src/bin/tool.rs"), "tool".to_owned()),
+        ]);
+        let crate_of = |file: &str| roots.crate_of(Path::new(file));
+        assert_eq!(crate_of("/rust/src/libstd/collections/hash/map.rs"), Some("std"));
+        assert_eq!(crate_of("/rust/src/libcore/lib.rs"), Some("core"));
+        assert_eq!(crate_of("/foo/src/config.rs"), Some("foo"));
+        assert_eq!(crate_of("/foo/src/bin/tool.rs"), Some("tool"));
+        assert_eq!(crate_of("/bar/src/lib.rs"), None);
+    }
+
+    #[test]
+    fn symbol_search_paths() {
+        let search = SymbolSearch::parse("std::coll::HashMap");
+        assert!(search.accepts_path("::collections::hash::map::HashMap", Some("std"), false));
+        assert!(!search.accepts_path("::hash::map::HashMap", Some("std"), false));
+        assert!(!search.accepts_path("::collections::HashMap", Some("hashbrown"), false));
+
+        let search = SymbolSearch::parse("crate::config::Config");
+        assert!(search.accepts_path("::config::Config", Some("rls"), true));
+        assert!(!search.accepts_path("::config::Config", Some("cargo"), false));
+    }
+
+    #[test]
+    fn match_qualities() {
+        assert_eq!(match_quality("HashMap", "HashMap"), Some(Match::Exact));
+        assert_eq!(
+            match_quality("hashmap", "HashMap"),
+            Some(Match::ExactIgnoringCase)
+        );
+        assert_eq!(match_quality("Hash", "HashMap"), Some(Match::Prefix));
+        assert_eq!(
+            match_quality("hash", "HashMap"),
+            Some(Match::PrefixIgnoringCase)
+        );
+        assert_eq!(match_quality("HM", "HashMap"), Some(Match::CamelHumps));
+        assert_eq!(match_quality("hamap", "HashMap"), Some(Match::CamelHumps));
+        assert_eq!(match_quality("fb", "foo_bar"), Some(Match::CamelHumps));
+        assert_eq!(match_quality("Map", "HashMap"), Some(Match::Substring));
+        assert_eq!(match_quality("hmp", "HashMap"), Some(Match::Subsequence));
+        assert_eq!(match_quality("mh", "HashMap"), None);
+        assert!(Match::Exact > Match::Prefix && Match::CamelHumps > Match::Substring);
+    }
+}
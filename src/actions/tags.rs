@@ -0,0 +1,323 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `rls tags`, which builds a project and writes a tags file for its
+//! definitions, for editors which prefer those to a language server. The
+//! default is a Universal Ctags compatible `tags` file, `--etags` writes an
+//! Emacs `TAGS` file instead.
+
+use crate::actions::InitActionContext;
+use crate::config::Config;
+use crate::lsp_data::{source_kind_from_def_kind, SymbolKind};
+use crate::version;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Run `rls tags` with the arguments following `tags`.
+pub fn run(args: &[String]) -> i32 {
+    let mut project_dir = PathBuf::from(".");
+    let mut output = None;
+    let mut etags = false;
+    let mut config = Config::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => match args.next() {
+                Some(file) => output = Some(PathBuf::from(file)),
+                None => {
+                    println!("Expected a file after '--output'\n{}", help());
+                    return 101;
+                }
+            },
+            "--etags" => etags = true,
+            "--no-crate-blacklist" => config.use_crate_blacklist = false,
+            "--help" | "-h" => {
+                println!("{}", help());
+                return 0;
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unknown option '{}'\n{}", arg, help());
+                return 101;
+            }
+            _ => project_dir = PathBuf::from(arg),
+        }
+    }
+
+    let root = match env::current_dir().and_then(|cwd| cwd.join(project_dir).canonicalize()) {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("Could not find the project: {}", e);
+            return 101;
+        }
+    };
+    let output = output.unwrap_or_else(|| root.join(if etags { "TAGS" } else { "tags" }));
+    let ctx = match InitActionContext::build_standalone(root, config) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 101;
+        }
+    };
+
+    let tags = collect(&ctx);
+    // Tags files refer to files relative to their own directory.
+    let base = env::current_dir()
+        .map(|cwd| cwd.join(&output))
+        .ok()
+        .and_then(|output| output.parent().map(Path::to_owned))
+        .unwrap_or_default();
+    let written = File::create(&output).and_then(|file| {
+        let out = BufWriter::new(file);
+        if etags {
+            write_etags(&tags, &base, out)
+        } else {
+            write_ctags(&tags, &base, out)
+        }
+    });
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Could not write {}: {}", output.display(), e);
+            101
+        }
+    }
+}
+
+/// A definition to be written to the tags file.
+#[derive(Debug, Clone, PartialEq)]
+struct Tag {
+    name: String,
+    file: PathBuf,
+    /// Zero-indexed.
+    line: usize,
+    /// Zero-indexed, in chars.
+    column: usize,
+    kind: SymbolKind,
+    /// The kind and name of the parent definition, if any.
+    scope: Option<(SymbolKind, String)>,
+}
+
+fn collect(ctx: &InitActionContext) -> Vec<Tag> {
+    let analysis = &ctx.project.analysis;
+    let use_crate_blacklist = ctx.project.config.lock().unwrap().use_crate_blacklist;
+    let mut files: Vec<PathBuf> = ctx
+        .project
+        .file_to_crates
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, crates)| {
+            !use_crate_blacklist
+                || crates
+                    .iter()
+                    .any(|krate| !rls_blacklist::CRATE_BLACKLIST.contains(&&*krate.name))
+        })
+        .map(|(file, _)| file.clone())
+        .collect();
+    files.sort();
+
+    let mut tags = vec![];
+    for file in files {
+        for symbol in analysis.symbols(&file).unwrap_or_else(|_| vec![]) {
+            let range = symbol.span.range;
+            if range.row_start == range.row_end && range.col_start == range.col_end {
+                continue;
+            }
+            tags.push(Tag {
+                name: symbol.name,
+                file: symbol.span.file,
+                line: range.row_start.0 as usize,
+                column: range.col_start.0 as usize,
+                kind: source_kind_from_def_kind(symbol.kind),
+                scope: symbol
+                    .parent
+                    .and_then(|id| analysis.get_def(id).ok())
+                    .map(|parent| (source_kind_from_def_kind(parent.kind), parent.name)),
+            });
+        }
+    }
+    tags
+}
+
+/// The letter and name Universal Ctags uses for the kind of a Rust definition.
+fn ctags_kind(kind: SymbolKind) -> (char, &'static str) {
+    match kind {
+        SymbolKind::Module => ('n', "module"),
+        SymbolKind::Struct | SymbolKind::Array => ('s', "struct"),
+        SymbolKind::Interface => ('i', "interface"),
+        SymbolKind::Enum => ('g', "enum"),
+        SymbolKind::EnumMember => ('e', "enumerator"),
+        SymbolKind::Method => ('P', "method"),
+        SymbolKind::Field => ('m', "field"),
+        SymbolKind::Constant => ('C', "constant"),
+        SymbolKind::TypeParameter => ('t', "typedef"),
+        SymbolKind::Variable => ('v', "variable"),
+        _ => ('f', "function"),
+    }
+}
+
+fn relative<'a>(file: &'a Path, base: &Path) -> &'a Path {
+    file.strip_prefix(base).unwrap_or(file)
+}
+
+fn write_ctags<W: Write>(tags: &[Tag], base: &Path, mut out: W) -> io::Result<()> {
+    writeln!(out, "!_TAG_FILE_FORMAT\t2\t/extended format/")?;
+    writeln!(
+        out,
+        "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/"
+    )?;
+    writeln!(out, "!_TAG_PROGRAM_NAME\trls\t//")?;
+    writeln!(out, "!_TAG_PROGRAM_VERSION\t{}\t//", version())?;
+
+    let mut lines: Vec<String> = tags
+        .iter()
+        .map(|tag| {
+            let mut line = format!(
+                "{}\t{}\t{};\"\t{}\tline:{}",
+                tag.name,
+                relative(&tag.file, base).display(),
+                tag.line + 1,
+                ctags_kind(tag.kind).0,
+                tag.line + 1,
+            );
+            if let Some((kind, ref name)) = tag.scope {
+                line.push_str(&format!("\t{}:{}", ctags_kind(kind).1, name));
+            }
+            line
+        })
+        .collect();
+    // Sorted by name (the first field), as declared in the header, so that
+    // editors can binary search the file.
+    lines.sort();
+    lines.dedup();
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()
+}
+
+fn write_etags<W: Write>(tags: &[Tag], base: &Path, mut out: W) -> io::Result<()> {
+    let mut by_file: BTreeMap<&Path, Vec<&Tag>> = BTreeMap::new();
+    for tag in tags {
+        by_file.entry(&tag.file).or_default().push(tag);
+    }
+    for (file, tags) in by_file {
+        let text = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", file.display(), e);
+                continue;
+            }
+        };
+        let name = relative(file, base).display().to_string();
+        out.write_all(etags_section(&name, &text, &tags).as_bytes())?;
+    }
+    out.flush()
+}
+
+/// The section of a `TAGS` file for the file called `name`, which contains
+/// `text`.
+fn etags_section(name: &str, text: &str, tags: &[&Tag]) -> String {
+    let mut line_starts = vec![0];
+    line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+
+    let mut entries = String::new();
+    for tag in tags {
+        let start = match line_starts.get(tag.line) {
+            Some(start) => *start,
+            None => continue,
+        };
+        let line = text[start..].lines().next().unwrap_or("");
+        // The text of the line up to the end of the definition's name, which
+        // Emacs searches for.
+        let pattern = line
+            .char_indices()
+            .nth(tag.column)
+            .map(|(col, _)| col + tag.name.len())
+            .filter(|end| line.get(..*end).map_or(false, |l| l.ends_with(&*tag.name)))
+            .map_or(line, |end| &line[..end]);
+        entries.push_str(&format!(
+            "{}\u{7f}{}\u{1}{},{}\n",
+            pattern,
+            tag.name,
+            tag.line + 1,
+            start
+        ));
+    }
+    format!("\u{c}\n{},{}\n{}", name, entries.len(), entries)
+}
+
+fn help() -> &'static str {
+    r#"
+rls tags [PROJECT_DIR] [OPTIONS]
+
+Builds the project (the current directory by default) and writes a Universal
+Ctags compatible tags file for its definitions.
+
+Options:
+    --etags                 write an Emacs TAGS file instead
+    --output FILE           write to FILE rather than `tags` (or `TAGS`) in the
+                            project directory
+    --no-crate-blacklist    include crates from the RLS's crate blacklist
+"#
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, line: usize, column: usize, kind: SymbolKind) -> Tag {
+        Tag {
+            name: name.to_owned(),
+            file: PathBuf::from("/project/src/lib.rs"),
+            line,
+            column,
+            kind,
+            scope: None,
+        }
+    }
+
+    #[test]
+    fn ctags_are_sorted_and_scoped() {
+        let mut method = tag("new", 3, 11, SymbolKind::Method);
+        method.scope = Some((SymbolKind::Struct, "Foo".to_owned()));
+        let tags = vec![tag("Foo", 0, 11, SymbolKind::Struct), method];
+
+        let mut out = vec![];
+        write_ctags(&tags, Path::new("/project"), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().filter(|l| !l.starts_with("!_TAG_")).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Foo\tsrc/lib.rs\t1;\"\ts\tline:1",
+                "new\tsrc/lib.rs\t4;\"\tP\tline:4\tstruct:Foo",
+            ]
+        );
+    }
+
+    #[test]
+    fn etags_sections() {
+        let text = "pub struct Foo;\n\nfn bar() {}\n";
+        let foo = tag("Foo", 0, 11, SymbolKind::Struct);
+        let bar = tag("bar", 2, 3, SymbolKind::Function);
+
+        let section = etags_section("src/lib.rs", text, &[&foo, &bar]);
+        let entries = "pub struct Foo\u{7f}Foo\u{1}1,0\nfn bar\u{7f}bar\u{1}3,17\n";
+        assert_eq!(
+            section,
+            format!("\u{c}\nsrc/lib.rs,{}\n{}", entries.len(), entries)
+        );
+    }
+}
@@ -25,8 +25,9 @@ use crate::actions::progress::ProgressUpdate;
 use crate::build::cargo_plan::CargoPlan;
 use crate::build::environment::{self, Environment, EnvironmentLock};
 use crate::build::plan::{BuildPlan, Crate};
+use crate::build::plan_cache::PlanCache;
 use crate::build::{BufWriter, BuildResult, CompilationContext, Internals, PackageArg};
-use crate::config::Config;
+use crate::config::{Config, Inferrable};
 use crate::lsp_data::{Position, Range};
 use log::{debug, trace, warn};
 use rls_data::Analysis;
@@ -204,7 +205,7 @@ fn run_cargo_ws(
 
     // TODO: It might be feasible to keep this CargoOptions structure cached and regenerate
     // it on every relevant configuration change
-    let (opts, rustflags, clear_env_rust_log, cfg_test) = {
+    let (opts, rustflags, clear_env_rust_log, cfg_test, plan_cache) = {
         // We mustn't lock configuration for the whole build process
         let rls_config = rls_config.lock().unwrap();
 
@@ -223,6 +224,7 @@ fn run_cargo_ws(
             rustflags,
             rls_config.clear_env_rust_log,
             rls_config.cfg_test,
+            PlanCache::new(manifest_path.parent().unwrap(), &rls_config),
         )
     };
 
@@ -293,10 +295,18 @@ fn run_cargo_ws(
     let exec = Arc::new(exec) as Arc<dyn Executor>;
     match compile_with_exec(&ws, &compile_opts, &exec) {
         Ok(_) => {
+            let cx = compilation_cx.lock().unwrap();
             trace!(
                 "Created build plan after Cargo compilation routine: {:?}",
-                compilation_cx.lock().unwrap().build_plan
+                cx.build_plan
             );
+
+            // Allow the next session to skip running Cargo
+            if let (Some(plan_cache), BuildPlan::Cargo(plan)) = (plan_cache, &cx.build_plan) {
+                if plan.is_ready() {
+                    plan_cache.save(plan, cx.cwd.as_ref().map(|p| &**p));
+                }
+            }
         }
         Err(e) => {
             if !reached_primary.load(Ordering::SeqCst) {
@@ -583,6 +593,7 @@ impl Executor for RlsExecutor {
                 &build_dir,
                 Arc::clone(&self.config),
                 &self.env_lock.as_facade(),
+                None,
             ) {
             self.compiler_messages.lock().unwrap().append(&mut messages);
             self.analysis.lock().unwrap().append(&mut analysis);
@@ -650,6 +661,56 @@ impl CargoOptions {
             ..CargoOptions::default()
         }
     }
+
+    /// The options affecting how packages are built, as arguments to Cargo.
+    /// The targets to build are left to the command.
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(target) = &self.target {
+            args.push("--target".to_owned());
+            args.push(target.clone());
+        }
+        if self.all_features {
+            args.push("--all-features".to_owned());
+        } else if !self.features.is_empty() {
+            args.push("--features".to_owned());
+            args.push(self.features.join(" "));
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_owned());
+        }
+        args
+    }
+}
+
+/// Returns the arguments with which Cargo commands run outside of the RLS
+/// (e.g., from code lenses) build the project as configured in `config`, so
+/// that they don't rebuild it with different settings, and the `RUSTFLAGS` to
+/// run them with if the configuration changes those.
+pub fn command_options(config: &Config) -> (Vec<String>, Option<String>) {
+    let mut args = CargoOptions::new(config).to_args();
+    // The inferred target directory is the RLS's own.
+    if let Inferrable::Specified(Some(target_dir)) = &config.target_dir {
+        args.push("--target-dir".to_owned());
+        args.push(target_dir.display().to_string());
+    }
+    let rustflags = if config.rustflags.is_some() || config.sysroot.is_some() {
+        Some(prepare_cargo_rustflags(config))
+    } else {
+        None
+    };
+    (args, rustflags)
+}
+
+/// Describes the configuration which affects the build plan created by Cargo,
+/// so that a cached plan can be invalidated when it changes.
+pub(super) fn plan_config_fingerprint(config: &Config) -> String {
+    format!(
+        "{:?} {} {}",
+        CargoOptions::new(config),
+        prepare_cargo_rustflags(config),
+        config.cfg_test
+    )
 }
 
 fn prepare_cargo_rustflags(config: &Config) -> String {
@@ -91,6 +91,10 @@ impl CargoPlan {
         !self.compiler_jobs.is_empty()
     }
 
+    crate fn built_packages(&self) -> &HashSet<String> {
+        &self.built_packages
+    }
+
     /// Cache a given compiler invocation in `ProcessBuilder` for a given
     /// `PackageId` and `TargetKind` in `Target`, to be used when processing
     /// cached build plan.
@@ -350,6 +354,39 @@ impl CargoPlan {
         }
     }
 
+    /// For every unit in a topologically sorted queue, returns the positions
+    /// in the queue of the units it has to wait for. Dependencies outside of
+    /// the queue are looked through, since these are not rebuilt.
+    fn queue_dependencies(&self, queue: &[UnitKey]) -> Vec<Vec<usize>> {
+        let positions: HashMap<&UnitKey, usize> =
+            queue.iter().enumerate().map(|(i, key)| (key, i)).collect();
+
+        queue
+            .iter()
+            .map(|key| {
+                let mut deps = HashSet::new();
+                let mut visited = HashSet::new();
+                let mut to_process = vec![key];
+                while let Some(unit) = to_process.pop() {
+                    for dep in self.dep_graph.get(unit).into_iter().flat_map(|deps| deps) {
+                        if !visited.insert(dep) {
+                            continue;
+                        }
+                        match positions.get(dep) {
+                            Some(&position) => {
+                                deps.insert(position);
+                            }
+                            None => to_process.push(dep),
+                        }
+                    }
+                }
+
+                let mut deps: Vec<_> = deps.into_iter().collect();
+                deps.sort();
+                deps
+            }).collect()
+    }
+
     crate fn prepare_work<T: AsRef<Path> + fmt::Debug>(
         &self,
         modified: &[T],
@@ -401,28 +438,52 @@ impl CargoPlan {
                 return WorkStatus::NeedsCargo(PackageArg::Default);
             }
 
-            let queue = self.topological_sort(&graph);
-            trace!(
-                "Topologically sorted dirty graph: {:?} {}",
-                queue,
-                self.is_ready()
-            );
-            let jobs: Option<Vec<_>> = queue
-                .iter()
-                .map(|x| self.compiler_jobs.get(x).cloned())
-                .collect();
-
-            // It is possible that we want a job which is not in our cache (compiler_jobs),
-            // for example we might be building a workspace with an error in a crate and later
-            // crates within the crate that depend on the error-ing one have never been built.
-            // In that case we need to build from scratch so that everything is in our cache, or
-            // we cope with the error. In the error case, jobs will be None.
-            match jobs {
-                None => WorkStatus::NeedsCargo(PackageArg::Default),
-                Some(jobs) => {
-                    assert!(!jobs.is_empty());
-                    WorkStatus::Execute(JobQueue::with_commands(jobs))
-                }
+            self.job_queue(&graph)
+        }
+    }
+
+    /// Returns the work needed to rebuild every unit with a cached compiler
+    /// invocation, e.g. to retrieve diagnostics and analysis data for the whole
+    /// plan after it's been loaded from the cache of a previous session.
+    crate fn prepare_all_work(&self) -> WorkStatus {
+        if !self.is_ready() {
+            return WorkStatus::NeedsCargo(PackageArg::Default);
+        }
+
+        let graph: HashMap<_, _> = self
+            .rev_dep_graph
+            .iter()
+            .filter(|&(unit, _)| self.compiler_jobs.contains_key(unit))
+            .map(|(unit, deps)| (unit.clone(), deps.clone()))
+            .collect();
+        self.job_queue(&graph)
+    }
+
+    /// Creates a queue of cached compiler invocations for a given (dirty) rev
+    /// dep graph.
+    fn job_queue(&self, graph: &HashMap<UnitKey, HashSet<UnitKey>>) -> WorkStatus {
+        let queue = self.topological_sort(graph);
+        trace!(
+            "Topologically sorted dirty graph: {:?} {}",
+            queue,
+            self.is_ready()
+        );
+        let jobs: Option<Vec<_>> = queue
+            .iter()
+            .map(|x| self.compiler_jobs.get(x).cloned())
+            .collect();
+
+        // It is possible that we want a job which is not in our cache (compiler_jobs),
+        // for example we might be building a workspace with an error in a crate and later
+        // crates within the crate that depend on the error-ing one have never been built.
+        // In that case we need to build from scratch so that everything is in our cache, or
+        // we cope with the error. In the error case, jobs will be None.
+        match jobs {
+            None => WorkStatus::NeedsCargo(PackageArg::Default),
+            Some(jobs) => {
+                assert!(!jobs.is_empty());
+                let deps = self.queue_dependencies(&queue);
+                WorkStatus::Execute(JobQueue::with_dependencies(jobs, deps))
             }
         }
     }
@@ -98,6 +98,7 @@ pub struct EnvironmentLock {
 
 /// Helper type that provides a unified way to access both outer and inner types of
 /// `EnvironmentLock` lock interfaces.
+#[derive(Clone)]
 pub enum EnvironmentLockFacade {
     Outer(Arc<EnvironmentLock>),
     Inner(InnerLock),
@@ -144,6 +145,7 @@ impl<'a> EnvironmentLock {
 }
 
 /// Acts as an interface through which user can acquire the second, inner lock of `EnvironmentLock`.
+#[derive(Clone)]
 pub struct InnerLock;
 
 impl<'a> InnerLock {
@@ -13,16 +13,18 @@
 //!
 //! Provides deserialization structs for the build plan format as it is output
 //! by `cargo build --build-plan` and means to execute that plan as part of the
-//! RLS build to retrieve diagnostics and analysis data.
+//! RLS build to retrieve diagnostics and analysis data. Such a plan can be
+//! provided by an external build system, either as a file or a command.
 //!
 //! Additionally, we allow to build the analysis data with an external command,
 //! which should return a list of save-analysis JSON files to be reloaded by RLS.
 //! From these we construct an internal build plan that is used to rebuild
-//! the project incrementally ourselves.
+//! the project incrementally ourselves. The command can also report rustc
+//! JSON diagnostics, either inline or by pointing to files containing them.
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::hash_map::DefaultHasher;
-use std::fs::File;
+use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
 use std::io::BufRead;
 use std::io::Read;
@@ -32,9 +34,10 @@ use std::process::{Command, Stdio};
 use crate::build::BuildResult;
 use crate::build::plan::{BuildKey, BuildGraph, JobQueue, WorkStatus};
 use crate::build::rustc::src_path;
+use crate::config::Config;
 
 use cargo::util::{process, ProcessBuilder};
-use log::trace;
+use log::{trace, warn};
 use rls_data::{Analysis, CompilationOptions};
 use serde_derive::Deserialize;
 
@@ -54,9 +57,22 @@ fn cmd_line_to_command<S: AsRef<str>>(cmd_line: &S, cwd: &Path) -> Result<Comman
     Ok(cmd)
 }
 
+/// Prefix of an output line of the external build command, pointing to a file
+/// with rustc JSON diagnostics (as emitted with `--error-format=json`).
+const DIAGNOSTICS_FILE_PREFIX: &str = "diagnostics:";
+
 /// Performs a build using an external command and interprets the results.
 /// The command should output on stdout a list of save-analysis .json files
-/// to be reloaded by the RLS.
+/// to be reloaded by the RLS. Additionally, each output line can be either:
+/// * a rustc JSON diagnostic message (a line starting with `{`), or
+/// * `diagnostics:<path>`, where `<path>` is a file with rustc JSON diagnostic
+///   messages, one per line.
+///
+/// Empty lines are ignored, as are lines starting with `{` which aren't valid
+/// JSON.
+///
+/// Relative paths, including the ones in the diagnostics, are relative to the
+/// build directory.
 /// Note: This is *very* experimental and preliminary - this can viewed as
 /// an experimentation until a more complete solution emerges.
 pub(super) fn build_with_external_cmd<S: AsRef<str>>(
@@ -82,13 +98,16 @@ pub(super) fn build_with_external_cmd<S: AsRef<str>>(
     };
 
     let reader = std::io::BufReader::new(child.stdout.unwrap());
+    let lines = reader.lines().filter_map(|res| res.ok());
+    let BuildOutput { files, messages } = match parse_build_output(lines, &build_dir) {
+        Ok(output) => output,
+        Err(cause) => {
+            let err_msg = format!("Couldn't read diagnostics: {}", cause);
+            return (BuildResult::Err(err_msg, Some(cmd_line.to_owned())), Err(()));
+        }
+    };
 
-    let files = reader.lines().filter_map(|res| res.ok())
-        .map(PathBuf::from)
-        // Relative paths are relative to build command, not RLS itself (cwd may be different)
-        .map(|path| if !path.is_absolute() { build_dir.join(path) } else { path });
-
-    let analyses = match read_analysis_files(files) {
+    let analyses = match read_analysis_files(files.iter()) {
         Ok(analyses) => analyses,
         Err(cause) => {
             let err_msg = format!("Couldn't read analysis data: {}", cause);
@@ -97,7 +116,144 @@ pub(super) fn build_with_external_cmd<S: AsRef<str>>(
     };
 
     let plan = plan_from_analysis(&analyses, &build_dir);
-    (BuildResult::Success(build_dir, vec![], analyses, HashMap::default(), false), plan)
+    (BuildResult::Success(build_dir, messages, analyses, HashMap::default(), false), plan)
+}
+
+/// What an external build command reported on stdout.
+#[derive(Debug, Default, PartialEq)]
+struct BuildOutput {
+    /// Save-analysis files to be reloaded.
+    files: Vec<PathBuf>,
+    /// rustc JSON diagnostic messages.
+    messages: Vec<String>,
+}
+
+/// Interprets the stdout of an external build command, as described for
+/// `build_with_external_cmd`. Fails if a diagnostics file can't be read.
+fn parse_build_output<I>(lines: I, build_dir: &Path) -> Result<BuildOutput, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Relative paths are relative to build command, not RLS itself (cwd may be different)
+    let absolute = |path: PathBuf| if !path.is_absolute() { build_dir.join(path) } else { path };
+
+    let mut output = BuildOutput::default();
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        } else if line.starts_with('{') {
+            output.messages.extend(json_message(line));
+        } else if line.starts_with(DIAGNOSTICS_FILE_PREFIX) {
+            let path = absolute(PathBuf::from(&line[DIAGNOSTICS_FILE_PREFIX.len()..]));
+            output.messages.extend(read_diagnostics_file(&path)?);
+        } else {
+            output.files.push(absolute(PathBuf::from(line)));
+        }
+    }
+    Ok(output)
+}
+
+/// Returns `line` if it's a JSON message, logging it otherwise.
+fn json_message(line: String) -> Option<String> {
+    match serde_json::from_str::<serde_json::Value>(&line) {
+        Ok(_) => Some(line),
+        Err(err) => {
+            warn!("Ignoring malformed diagnostic `{}`: {}", line, err);
+            None
+        }
+    }
+}
+
+/// Reads rustc JSON diagnostic messages from a given file, one per line.
+fn read_diagnostics_file(path: &Path) -> Result<Vec<String>, String> {
+    trace!(
+        "external::read_diagnostics_file: Attempt to read `{}`",
+        path.display()
+    );
+
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let reader = std::io::BufReader::new(file);
+
+    let mut messages = vec![];
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if !line.trim().is_empty() {
+            messages.extend(json_message(line));
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Source of a build plan in the `cargo build --build-plan` format, provided
+/// by an external build system.
+#[derive(Debug)]
+crate enum PlanSource {
+    /// Path to a JSON file with the build plan.
+    File(PathBuf),
+    /// Command printing the build plan on stdout.
+    Command(String),
+}
+
+impl PlanSource {
+    /// Returns where the configuration says to get the build plan from, if
+    /// anywhere. Empty values count as unset.
+    crate fn from_config(config: &Config) -> Option<PlanSource> {
+        if let Some(path) = config.build_plan_path.as_ref().filter(|p| !p.as_os_str().is_empty()) {
+            return Some(PlanSource::File(path.clone()));
+        }
+        config
+            .build_plan_command
+            .as_ref()
+            .filter(|cmd| !cmd.trim().is_empty())
+            .map(|cmd| PlanSource::Command(cmd.clone()))
+    }
+
+    /// Returns the command line printing the build plan, if any.
+    crate fn cmd_line(&self) -> Option<String> {
+        match self {
+            PlanSource::File(_) => None,
+            PlanSource::Command(cmd_line) => Some(cmd_line.clone()),
+        }
+    }
+
+    /// Reads the build plan. Relative paths are relative to the build directory.
+    crate fn load(&self, build_dir: &Path) -> Result<ExternalPlan, String> {
+        let json = match self {
+            PlanSource::File(path) => {
+                let path = build_dir.join(path);
+                fs::read_to_string(&path)
+                    .map_err(|e| format!("Couldn't read build plan {}: {}", path.display(), e))?
+            }
+            PlanSource::Command(cmd_line) => {
+                let mut cmd = cmd_line_to_command(cmd_line, build_dir)
+                    .map_err(|_| format!("Couldn't treat {} as command", cmd_line))?;
+                let output = cmd
+                    .output()
+                    .map_err(|io| format!("Couldn't execute: {} ({:?})", cmd_line, io.kind()))?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    return Err(format!("Couldn't execute: {} ({})", cmd_line, stderr.trim()));
+                }
+                String::from_utf8(output.stdout).map_err(|e| e.to_string())?
+            }
+        };
+
+        parse_build_plan(&json, build_dir)
+    }
+}
+
+/// Deserializes a build plan and checks that it can be run by the RLS.
+fn parse_build_plan(json: &str, build_dir: &Path) -> Result<ExternalPlan, String> {
+    let raw: RawPlan =
+        serde_json::from_str(json).map_err(|e| format!("Couldn't parse build plan: {}", e))?;
+    let plan = ExternalPlan::try_from_raw(build_dir, raw)
+        .map_err(|_| "Build plan has dependencies outside of it".to_owned())?;
+
+    if !plan.units.values().any(|unit| is_rustc(&unit.command)) {
+        return Err("Build plan has no rustc invocations".to_owned());
+    }
+    Ok(plan)
 }
 
 /// Reads and deserializes given save-analysis JSON files into corresponding
@@ -290,6 +446,13 @@ impl ExternalPlan {
 
         Ok(ExternalPlan::with_units(units))
     }
+
+    /// Returns the work needed to run every rustc invocation in the plan, e.g.
+    /// after it's been loaded.
+    crate fn prepare_all_work(&self) -> WorkStatus {
+        let topo = self.topological_sort(self.units());
+        rustc_job_queue(topo)
+    }
 }
 
 impl BuildGraph for ExternalPlan {
@@ -433,14 +596,29 @@ impl BuildGraph for ExternalPlan {
         let dirties = self.dirties_transitive(files);
         let topo = self.topological_sort(dirties);
 
-        let cmds = topo.into_iter().map(|unit| unit.command.clone()).collect();
-
-        WorkStatus::Execute(JobQueue::with_commands(cmds))
+        rustc_job_queue(topo)
     }
 }
 
+/// Creates a queue of the rustc invocations among the topologically sorted
+/// units, which we run in-process. Other invocations (e.g. running build
+/// scripts) are left to the external build system.
+fn rustc_job_queue(units: Vec<&Invocation>) -> WorkStatus {
+    let cmds = units
+        .into_iter()
+        .filter(|unit| is_rustc(&unit.command))
+        .map(|unit| unit.command.clone())
+        .collect();
+
+    WorkStatus::Execute(JobQueue::with_commands(cmds))
+}
+
+fn is_rustc(cmd: &ProcessBuilder) -> bool {
+    Path::new(cmd.get_program()).ends_with("rustc")
+}
+
 fn guess_rustc_src_path(build_dir: &Path, cmd: &ProcessBuilder) -> Option<PathBuf> {
-    if !Path::new(cmd.get_program()).ends_with("rustc") {
+    if !is_rustc(cmd) {
         return None;
     }
 
@@ -582,4 +760,100 @@ mod tests {
             to_paths(&["/my/repo/src/lib.rs", "/my/repo/build.rs"]),
         )
     }
+
+    #[test]
+    fn build_output() {
+        let lines = vec![
+            "target/debug/deps/save-analysis/repo.json",
+            "/other/save-analysis/dep.json",
+            "",
+            r#"{"message": "unused variable: `x`", "level": "warning"}"#,
+            r#"{"message": "truncated"#,
+        ];
+        let output = parse_build_output(
+            lines.iter().map(|line| line.to_string()),
+            Path::new("/my/repo"),
+        ).unwrap();
+        assert_eq!(
+            output.files,
+            vec![
+                PathBuf::from("/my/repo/target/debug/deps/save-analysis/repo.json"),
+                PathBuf::from("/other/save-analysis/dep.json"),
+            ]
+        );
+        assert_eq!(output.messages, vec![lines[3].to_owned()]);
+    }
+
+    #[test]
+    fn build_output_diagnostics_files() {
+        let dir = std::env::temp_dir().join(format!("rls-external-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("diagnostics.json"),
+            "{\"message\": \"a\"}\n\nnot json\n{\"message\": \n{\"message\": \"b\"}\n",
+        ).unwrap();
+
+        let lines = vec![
+            "diagnostics:diagnostics.json".to_owned(),
+            r#"{"message": "c"}"#.to_owned(),
+        ];
+        let output = parse_build_output(lines, &dir).unwrap();
+        assert!(output.files.is_empty());
+        assert_eq!(
+            output.messages,
+            vec![
+                r#"{"message": "a"}"#,
+                r#"{"message": "b"}"#,
+                r#"{"message": "c"}"#,
+            ]
+        );
+
+        let missing = vec!["diagnostics:missing.json".to_owned()];
+        assert!(parse_build_output(missing, &dir).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parse_build_plan_without_rustc() {
+        let build_dir = std::env::temp_dir();
+
+        let plan = r#"{"invocations": [
+            { "deps": [], "program": "/my/repo/target/build-script-build", "args": [], "env": {}, "outputs": [] }
+        ]}"#;
+        assert!(parse_build_plan(plan, &build_dir).is_err());
+
+        let plan = r#"{"invocations": [
+            { "deps": [],  "program": "rustc", "args": ["--crate-name", "build_script_build", "/my/repo/build.rs"], "env": {}, "outputs": [] },
+            { "deps": [0], "program": "/my/repo/target/build-script-build", "args": [], "env": {}, "outputs": [] },
+            { "deps": [1], "program": "rustc", "args": ["--crate-name", "repo", "/my/repo/src/lib.rs"], "env": {}, "outputs": [] }
+        ]}"#;
+        let plan = parse_build_plan(plan, &build_dir).unwrap();
+        assert_eq!(SrcPaths::from(&plan).0.len(), 2);
+
+        let plan = r#"{"invocations": [
+            { "deps": [1], "program": "rustc", "args": ["--crate-name", "repo", "/my/repo/src/lib.rs"], "env": {}, "outputs": [] }
+        ]}"#;
+        assert!(parse_build_plan(plan, &build_dir).is_err());
+    }
+
+    #[test]
+    fn plan_source_from_config() {
+        let source = |path: Option<&str>, cmd: Option<&str>| {
+            let mut config = Config::default();
+            config.build_plan_path = path.map(PathBuf::from);
+            config.build_plan_command = cmd.map(str::to_owned);
+            PlanSource::from_config(&config).map(|source| format!("{:?}", source))
+        };
+        assert_eq!(source(None, None), None);
+        assert_eq!(source(Some(""), Some(" ")), None);
+        assert_eq!(
+            source(Some("plan.json"), Some("make plan")),
+            Some(r#"File("plan.json")"#.to_owned())
+        );
+        assert_eq!(
+            source(Some(""), Some("make plan")),
+            Some(r#"Command("make plan")"#.to_owned())
+        );
+    }
 }
@@ -16,6 +16,7 @@ use self::plan::{BuildGraph, BuildPlan, WorkStatus};
 use ::cargo::util::CargoError;
 use crate::actions::post_build::PostBuildHandler;
 use crate::actions::progress::{ProgressNotifier, ProgressUpdate};
+use crate::concurrency::CancellationToken;
 use crate::config::Config;
 use crate::lsp_data::Range;
 use log::{debug, info, trace};
@@ -32,6 +33,7 @@ use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
+pub use self::cargo::command_options;
 pub use self::plan::{Crate, Edition};
 
 mod cargo;
@@ -39,18 +41,19 @@ mod cargo_plan;
 pub mod environment;
 mod external;
 mod plan;
+mod plan_cache;
 mod rustc;
 
 /// Manages builds.
 ///
 /// The IDE will request builds quickly (possibly on every keystroke), there is
 /// no point running every one. We also avoid running more than one build at once.
-/// We cannot cancel builds. It might be worth running builds in parallel or
-/// canceling a started build.
 ///
-/// High priority builds are started 'straightaway' (builds cannot be interrupted).
-/// Normal builds are started after a timeout. A new build request cancels any
-/// pending build requests.
+/// High priority builds are started 'straightaway'. Normal builds are started
+/// after a timeout. A new build request cancels any pending build requests.
+/// A new normal build request also cancels a normal build which is in progress
+/// (see `Internals::cancel_build`), after which the new build is started
+/// without waiting.
 ///
 /// From the client's point of view, a build request is not guaranteed to cause
 /// a build. However, a build is guaranteed to happen and that build will begin
@@ -92,6 +95,10 @@ struct Internals {
     // This lock should only be held transiently.
     config: Arc<Mutex<Config>>,
     building: AtomicBool,
+    /// Signalled to abort the build in progress. Replaced with a fresh token
+    /// whenever a build is started.
+    // This lock should only be held transiently.
+    cancel_token: Mutex<CancellationToken>,
     /// A list of threads blocked on the current build queue. They should be
     /// resumed when there are no builds to run.
     blocked: Mutex<Vec<thread::Thread>>,
@@ -284,6 +291,13 @@ impl BuildQueue {
         let internals_clone = self.internals.clone();
 
         let mut queued = self.queued.lock().unwrap();
+        // A normal build in progress is made obsolete by the new request, so
+        // there is no point in letting it run to completion.
+        if priority == BuildPriority::Normal {
+            if let Build::InProgress = queued.0 {
+                self.internals.cancel_build();
+            }
+        }
         Self::push_build(&mut queued, build);
 
         // Need to spawn while holding the lock on queued so that we don't race.
@@ -349,10 +363,15 @@ impl BuildQueue {
     // Run the build thread. This thread will keep going until the build queue is
     // empty, then terminate.
     fn run_thread(queued: Arc<Mutex<(Build, Build)>>, internals: &Internals) {
+        // Whether the previous build was cancelled in favour of the next one.
+        let mut restarting = false;
         loop {
             // Find the next build to run, or terminate if there are no builds.
             let build = {
                 let mut queued = queued.lock().unwrap();
+                // Reset while holding the lock, so that a cancellation can only
+                // ever be signalled for a build which has already been dequeued.
+                *internals.cancel_token.lock().unwrap() = CancellationToken::new();
                 if queued.1.is_pending_fresh() {
                     let mut build = Build::InProgress;
                     mem::swap(&mut queued.1, &mut build);
@@ -366,8 +385,9 @@ impl BuildQueue {
                 }
             };
 
-            // Normal priority threads sleep before starting up.
-            if build.priority == BuildPriority::Normal {
+            // Normal priority threads sleep before starting up, unless they
+            // replace a cancelled build, in which case we've already waited.
+            if build.priority == BuildPriority::Normal && !restarting {
                 let build_wait = internals.build_wait();
                 debug!("sleeping {:.1?}", build_wait);
                 thread::sleep(build_wait);
@@ -409,13 +429,16 @@ impl BuildQueue {
                 &build.built_files,
                 progress_sender,
             );
-            // Assert that the build was not squashed.
-            if let BuildResult::Squashed = result {
-                unreachable!();
-            }
+            // The build can only be squashed if it was cancelled, in which
+            // case the build that cancelled it is already queued.
+            restarting = match result {
+                BuildResult::Squashed => true,
+                _ => false,
+            };
 
             let mut pbh = build.pbh;
-            {
+            // Threads blocked on a cancelled build have to wait for the next one.
+            if !restarting {
                 let mut blocked = internals.blocked.lock().unwrap();
                 pbh.blocked_threads.extend(blocked.drain(..));
             }
@@ -458,6 +481,7 @@ impl Internals {
             // instances, be sure to use a global lock to ensure env var consistency
             env_lock: EnvironmentLock::get(),
             building: AtomicBool::new(false),
+            cancel_token: Mutex::new(CancellationToken::new()),
             blocked: Mutex::new(vec![]),
             last_build_duration: RwLock::default(),
         }
@@ -528,6 +552,7 @@ impl Internals {
 
         // If the build plan has already been cached, use it, unless Cargo
         // has to be specifically rerun (e.g. when build scripts changed)
+        let mut from_plan_cache = false;
         let work = {
             let modified: Vec<_> = self.dirty_files.lock().unwrap().keys().cloned().collect();
 
@@ -535,8 +560,13 @@ impl Internals {
             let build_dir = cx.build_dir.clone().unwrap();
             let needs_rebuild = cx.needs_rebuild;
 
+            let (build_command, plan_source) = {
+                let config = self.config.lock().unwrap();
+                (config.build_command.clone(), external::PlanSource::from_config(&config))
+            };
+
             // Check if an external build command was provided and execute that, instead.
-            if let Some(cmd) = self.config.lock().unwrap().build_command.clone() {
+            if let Some(cmd) = build_command {
                 match (needs_rebuild, &cx.build_plan) {
                     (false, BuildPlan::External(ref plan)) => {
                         plan.prepare_work(&modified)
@@ -547,27 +577,67 @@ impl Internals {
                         (result, Ok(plan)) => {
                             cx.needs_rebuild = false;
                             cx.build_plan = BuildPlan::External(plan);
-                            // Since we don't support diagnostics in external
-                            // builds it might be worth rerunning the commands
-                            // ourselves again to get both analysis *and* diagnostics
+                            // Diagnostics are only available if reported by the
+                            // external command, otherwise it might be worth
+                            // rerunning the commands ourselves again to get both
+                            // analysis *and* diagnostics
                             return result;
                         }
                     },
                 }
+            // Otherwise run rustc ourselves for an externally provided build plan.
+            } else if let Some(source) = plan_source {
+                match (needs_rebuild, &cx.build_plan) {
+                    (false, BuildPlan::External(ref plan)) => {
+                        plan.prepare_work(&modified)
+                    },
+                    // We need to rebuild; reload the build plan.
+                    _ => match source.load(&build_dir) {
+                        Ok(plan) => {
+                            let work = plan.prepare_all_work();
+                            cx.needs_rebuild = false;
+                            cx.cwd = Some(build_dir);
+                            cx.build_plan = BuildPlan::External(plan);
+                            work
+                        }
+                        Err(cause) => return BuildResult::Err(cause, source.cmd_line()),
+                    },
+                }
             // Fall back to Cargo
             } else {
-                // Cargo plan is recreated and `needs_rebuild` reset if we run cargo::cargo().
-                match cx.build_plan {
-                    BuildPlan::External(_) => {
-                        WorkStatus::NeedsCargo(PackageArg::Default)
-                    },
-                    BuildPlan::Cargo(ref plan) => {
-                        match plan.prepare_work(&modified) {
-                            // Don't reuse the plan if we need to rebuild
-                            WorkStatus::Execute(_) if needs_rebuild => {
-                                WorkStatus::NeedsCargo(PackageArg::Default)
-                            },
-                            work => work,
+                // On a fresh start, try to reuse the plan cached by a previous session.
+                let has_plan = match cx.build_plan {
+                    BuildPlan::Cargo(ref plan) => plan.is_ready(),
+                    BuildPlan::External(_) => false,
+                };
+                let cached = if needs_rebuild && !has_plan {
+                    plan_cache::PlanCache::new(&build_dir, &self.config.lock().unwrap())
+                        .and_then(|cache| cache.load())
+                } else {
+                    None
+                };
+
+                if let Some((plan, cwd)) = cached {
+                    from_plan_cache = true;
+                    let work = plan.prepare_all_work();
+                    cx.cwd = cwd;
+                    cx.build_plan = BuildPlan::Cargo(plan);
+                    cx.needs_rebuild = false;
+                    work
+                } else {
+                    // Cargo plan is recreated and `needs_rebuild` reset if we run cargo::cargo().
+                    match cx.build_plan {
+                        BuildPlan::External(_) => {
+                            WorkStatus::NeedsCargo(PackageArg::Default)
+                        },
+                        BuildPlan::Cargo(ref plan) => {
+                            match plan.prepare_work(&modified) {
+                                // Don't reuse the plan if we need to rebuild
+                                WorkStatus::Execute(_) if needs_rebuild => {
+                                    WorkStatus::NeedsCargo(PackageArg::Default)
+                                },
+                                work => work,
+                            }
                         }
                     }
                 }
@@ -576,8 +646,19 @@ impl Internals {
         trace!("Specified work: {:#?}", work);
 
         let result = match work {
-            WorkStatus::NeedsCargo(package_arg) => cargo::cargo(self, package_arg, progress_sender),
-            WorkStatus::Execute(job_queue) => job_queue.execute(self, progress_sender),
+            WorkStatus::NeedsCargo(package_arg) => {
+                cargo::cargo(self, package_arg, progress_sender.clone())
+            }
+            WorkStatus::Execute(job_queue) => job_queue.execute(self, progress_sender.clone()),
+        };
+        // The cached plan may be outdated in ways its fingerprint doesn't
+        // catch, so let Cargo recreate it rather than report its failure.
+        let result = if from_plan_cache && plan_cache::is_stale(&result) {
+            info!("build with the cached build plan failed, running Cargo");
+            self.compilation_cx.lock().unwrap().needs_rebuild = true;
+            cargo::cargo(self, PackageArg::Default, progress_sender)
+        } else {
+            result
         };
 
         if let BuildResult::Success(.., true) = result {
@@ -589,6 +670,17 @@ impl Internals {
         result
     }
 
+    /// Requests the build in progress to stop as soon as possible. The build
+    /// then returns `BuildResult::Squashed`.
+    ///
+    /// Only builds which execute a cached `JobQueue` can be cancelled, between
+    /// or during its rustc invocations; a build running Cargo always completes
+    /// since it is responsible for (re)creating the build plan.
+    fn cancel_build(&self) {
+        trace!("cancelling build in progress");
+        self.cancel_token.lock().unwrap().cancel();
+    }
+
     /// Returns a pre-build wait time facilitating build debouncing.
     ///
     /// Uses client configured value, or attempts to infer an appropriate
@@ -661,3 +753,18 @@ fn dont_auto_tune_build_wait_configured() {
     *i.last_build_duration.write().unwrap() = Some(Duration::from_millis(70));
     assert_eq!(i.build_wait(), Duration::from_millis(350));
 }
+
+#[test]
+fn cancelled_job_queue_is_squashed() {
+    let i = Internals::new(Arc::new(Vfs::new()), Arc::default());
+    i.compilation_cx.lock().unwrap().build_dir = Some(PathBuf::from("."));
+
+    let job_queue = plan::JobQueue::with_commands(vec![::cargo::util::process("rustc")]);
+    i.cancel_build();
+
+    let (progress_sender, _progress_receiver) = channel();
+    match job_queue.execute(&i, progress_sender) {
+        BuildResult::Squashed => {}
+        result => panic!("expected a squashed build, got {:?}", result),
+    }
+}
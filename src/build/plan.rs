@@ -9,8 +9,10 @@ use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
-use std::sync::mpsc::Sender;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
+use std::thread;
 
 use cargo::util::ProcessBuilder;
 use log::trace;
@@ -19,6 +21,7 @@ use crate::actions::progress::ProgressUpdate;
 use crate::build::{BuildResult, Internals, PackageArg};
 use crate::build::cargo_plan::CargoPlan;
 use crate::build::external::ExternalPlan;
+use crate::concurrency::CancellationToken;
 
 crate trait BuildKey {
     type Key: Eq + Hash;
@@ -72,8 +75,17 @@ impl BuildPlan {
     }
 }
 
+/// A set of compiler invocations to be run, along with the dependencies
+/// between them.
 #[derive(Debug)]
-crate struct JobQueue(Vec<ProcessBuilder>);
+crate struct JobQueue {
+    /// A stack of compiler invocations, topologically sorted such that the
+    /// last one can be run first.
+    jobs: Vec<ProcessBuilder>,
+    /// Indices of the jobs that each job has to wait for. If this is `None`,
+    /// every job waits for the one after it, i.e. the jobs are run sequentially.
+    deps: Option<Vec<Vec<usize>>>,
+}
 
 /// Returns an immediately next argument to the one specified in a given
 /// ProcessBuilder (or `None` if the searched or the next argument could not be found).
@@ -92,17 +104,38 @@ fn proc_argument_value<T: AsRef<OsStr>>(prc: &ProcessBuilder, key: T) -> Option<
 }
 
 impl JobQueue {
+    /// Creates a queue of jobs run sequentially, starting from the last one.
     crate fn with_commands(jobs: Vec<ProcessBuilder>) -> JobQueue {
-        JobQueue(jobs)
+        JobQueue { jobs, deps: None }
     }
 
-    crate fn dequeue(&mut self) -> Option<ProcessBuilder> {
-        self.0.pop()
+    /// Creates a queue of jobs, where `deps[i]` holds the indices of the jobs
+    /// that `jobs[i]` depends on. Independent jobs can be run concurrently.
+    crate fn with_dependencies(jobs: Vec<ProcessBuilder>, deps: Vec<Vec<usize>>) -> JobQueue {
+        assert_eq!(jobs.len(), deps.len());
+        JobQueue { jobs, deps: Some(deps) }
+    }
+
+    /// Returns the indices of the jobs that each job has to wait for.
+    fn dependencies(&self) -> Vec<Vec<usize>> {
+        match self.deps {
+            Some(ref deps) => deps.clone(),
+            None => (0..self.jobs.len())
+                .map(|i| if i + 1 < self.jobs.len() { vec![i + 1] } else { vec![] })
+                .collect(),
+        }
     }
 
     /// Performs a rustc build using cached compiler invocations.
+    ///
+    /// Jobs which don't depend on each other are started concurrently, bounded
+    /// by the `jobs` config option (defaulting to the number of CPUs). Each
+    /// compilation only holds the process-wide environment lock until its crate
+    /// is expanded (the env vars and the cwd are read by e.g. `env!`), so the
+    /// analysis of independent crates overlaps. Results are merged in the order
+    /// of the job stack, regardless of the order in which the jobs finished.
     pub(super) fn execute(
-        mut self,
+        self,
         internals: &Internals,
         progress_sender: Sender<ProgressUpdate>,
     ) -> BuildResult {
@@ -110,87 +143,93 @@ impl JobQueue {
         // returned results will replace currently held diagnostics/analyses.
         // Either allow to return a BuildResult::Squashed here or just delegate
         // to Cargo (which we do currently) in `prepare_work`
-        assert!(!self.0.is_empty());
+        assert!(!self.jobs.is_empty());
 
-        let mut compiler_messages = vec![];
-        let mut analyses = vec![];
-        let mut input_files = HashMap::<_, HashSet<_>>::new();
-        let (build_dir, mut cwd) = {
+        let cancel_token = internals.cancel_token.lock().unwrap().clone();
+        let (build_dir, cwd) = {
             let comp_cx = internals.compilation_cx.lock().unwrap();
             (
                 comp_cx.build_dir.clone().expect("no build directory"),
                 comp_cx.cwd.clone(),
             )
         };
-
-        // Go through cached compiler invocations sequentially, collecting each
-        // invocation's compiler messages for diagnostics and analysis data
-        while let Some(job) = self.dequeue() {
-            trace!("Executing: {:#?}", job);
-            let mut args: Vec<_> = job
-                .get_args()
-                .iter()
-                .cloned()
-                .map(|x| x.into_string().expect("cannot stringify job args"))
-                .collect();
-
-            let program = job
-                .get_program()
-                .clone()
-                .into_string()
-                .expect("cannot stringify job program");
-            args.insert(0, program.clone());
-
-            // Needed to parse rustc diagnostics
-            if args.iter().find(|x| x.as_str() == "--error-format=json").is_none() {
-                args.push("--error-format=json".to_owned());
-            }
-
-            if args.iter().find(|x| x.as_str() == "--sysroot").is_none() {
-                let sysroot = super::rustc::current_sysroot()
-                    .expect("need to specify SYSROOT env var or use rustup or multirust");
-
-                let config = internals.config.lock().unwrap();
-                if config.sysroot.is_none() {
-                    args.push("--sysroot".to_owned());
-                    args.push(sysroot);
-                }
-            }
-
-            // Send a window/progress notification.
-            {
-                let crate_name = proc_argument_value(&job, "--crate-name").and_then(|x| x.to_str());
-                let update = match crate_name {
-                    Some(name) => {
-                        let cfg_test = job.get_args().iter().any(|arg| arg == "--test");
-                        ProgressUpdate::Message(if cfg_test {
-                            format!("{} cfg(test)", name)
-                        } else {
-                            name.to_owned()
-                        })
-                    }
-                    None => {
-                        // divide by zero is avoided by earlier assert!
-                        let percentage = compiler_messages.len() as f64 / self.0.len() as f64;
-                        ProgressUpdate::Percentage(percentage)
-                    }
-                };
+        let max_jobs = internals
+            .config
+            .lock()
+            .unwrap()
+            .jobs
+            .map_or_else(num_cpus::get, |jobs| jobs as usize)
+            .max(1);
+
+        let job_count = self.jobs.len();
+        let jobs = &self.jobs;
+        let (results, finished) = schedule(
+            &self.dependencies(),
+            max_jobs,
+            &cancel_token,
+            |index, finished, result_sender| {
+                let job = &jobs[index];
+                trace!("Executing: {:#?}", job);
 
                 progress_sender
-                    .send(update)
+                    .send(progress_update(job, finished, job_count))
                     .expect("Failed to send progress update");
-            }
 
-            match super::rustc::rustc(
-                &internals.vfs,
-                &args,
-                job.get_envs(),
-                job.get_cwd().or_else(|| cwd.as_ref().map(|p| &**p)),
-                &build_dir,
-                Arc::clone(&internals.config),
-                &internals.env_lock.as_facade(),
-            ) {
-                BuildResult::Success(c, mut messages, mut analysis, files, success) => {
+                let args = rustc_args(job, internals);
+                let envs = job.get_envs().clone();
+                let job_cwd = job.get_cwd().map(Path::to_path_buf).or_else(|| cwd.clone());
+                let build_dir = build_dir.clone();
+                let vfs = Arc::clone(&internals.vfs);
+                let config = Arc::clone(&internals.config);
+                let env_lock = Arc::clone(&internals.env_lock);
+                let cancel_token = cancel_token.clone();
+
+                thread::spawn(move || {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        super::rustc::rustc(
+                            &vfs,
+                            &args,
+                            &envs,
+                            job_cwd.as_ref().map(|p| &**p),
+                            &build_dir,
+                            config,
+                            &env_lock.as_facade(),
+                            Some(cancel_token),
+                        )
+                    }));
+                    let cmd = format!("{} {}", args[0], args.join(" "));
+                    let result = match result {
+                        Ok(BuildResult::Err(cause, _)) => BuildResult::Err(cause, Some(cmd)),
+                        Ok(result) => result,
+                        Err(_) => BuildResult::Err("rustc panicked".to_owned(), Some(cmd)),
+                    };
+                    // The scheduler waits for every job it started, so it's
+                    // still listening.
+                    result_sender.send((index, result)).unwrap();
+                });
+            },
+            |result| match result {
+                BuildResult::Success(.., true) => true,
+                _ => false,
+            },
+        );
+
+        if cancel_token.is_cancelled() {
+            trace!("Build cancelled, {} of {} jobs finished", finished, job_count);
+            return BuildResult::Squashed;
+        }
+
+        let mut compiler_messages = vec![];
+        let mut analyses = vec![];
+        let mut input_files = HashMap::<_, HashSet<_>>::new();
+        let mut cwd = cwd;
+        let mut success = true;
+
+        // Go through the results in the order of the job stack, collecting each
+        // invocation's compiler messages for diagnostics and analysis data
+        for result in results.into_iter().rev() {
+            match result {
+                Some(BuildResult::Success(c, mut messages, mut analysis, files, job_success)) => {
                     compiler_messages.append(&mut messages);
                     analyses.append(&mut analysis);
                     for (file, inputs) in files {
@@ -198,24 +237,15 @@ impl JobQueue {
                     }
 
                     cwd = Some(c);
-
-                    // This compilation failed, but the build as a whole does not
+                    // A compilation failed, but the build as a whole does not
                     // need to error out.
-                    if !success {
-                        return BuildResult::Success(
-                            cwd.unwrap(),
-                            compiler_messages,
-                            analyses,
-                            input_files,
-                            false,
-                        );
-                    }
-                }
-                BuildResult::Err(cause, _) => {
-                    let cmd = format!("{} {}", program, args.join(" "));
-                    return BuildResult::Err(cause, Some(cmd));
+                    success &= job_success;
                 }
-                _ => {}
+                Some(BuildResult::Err(cause, cmd)) => return BuildResult::Err(cause, cmd),
+                Some(BuildResult::Squashed) => return BuildResult::Squashed,
+                Some(BuildResult::CargoError { .. }) => unreachable!(),
+                // Not run due to a failed compilation
+                None => {}
             }
         }
 
@@ -224,11 +254,135 @@ impl JobQueue {
             compiler_messages,
             analyses,
             input_files,
-            true,
+            success,
         )
     }
 }
 
+/// Runs jobs whose dependencies are given by `deps` (`deps[i]` being the
+/// indices of the jobs that job `i` waits for), starting each one with `start`
+/// once its dependencies have succeeded. `start` is given the index of the job,
+/// the number of jobs finished so far and a sender for the job's result, which
+/// it must eventually send along with the index.
+///
+/// At most `max_jobs` are run at once, ready jobs with higher indices first.
+/// Like a sequential build, no more jobs are started once one of them fails
+/// (according to `succeeded`) or `cancel_token` is signalled.
+///
+/// Returns the result of each job which was run, and the number of finished
+/// jobs.
+fn schedule<T>(
+    deps: &[Vec<usize>],
+    max_jobs: usize,
+    cancel_token: &CancellationToken,
+    mut start: impl FnMut(usize, usize, Sender<(usize, T)>),
+    succeeded: impl Fn(&T) -> bool,
+) -> (Vec<Option<T>>, usize) {
+    let job_count = deps.len();
+    let mut pending_deps: Vec<usize> = deps.iter().map(Vec::len).collect();
+    let mut dependents = vec![vec![]; job_count];
+    for (job, deps) in deps.iter().enumerate() {
+        for &dep in deps {
+            dependents[dep].push(job);
+        }
+    }
+    // Kept sorted, so that jobs are started in the order of the job stack.
+    let mut ready: Vec<usize> = (0..job_count).filter(|&i| pending_deps[i] == 0).collect();
+
+    let (result_sender, result_receiver) = channel();
+    let mut results: Vec<Option<T>> = (0..job_count).map(|_| None).collect();
+    let mut running = 0;
+    let mut finished = 0;
+    let mut failed = false;
+
+    loop {
+        while running < max_jobs && !failed && !cancel_token.is_cancelled() {
+            let index = match ready.pop() {
+                Some(index) => index,
+                None => break,
+            };
+            start(index, finished, result_sender.clone());
+            running += 1;
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        let (index, result) = result_receiver.recv().unwrap();
+        running -= 1;
+        finished += 1;
+
+        if succeeded(&result) {
+            for &dependent in &dependents[index] {
+                pending_deps[dependent] -= 1;
+                if pending_deps[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+            ready.sort();
+        } else {
+            failed = true;
+        }
+        results[index] = Some(result);
+    }
+
+    (results, finished)
+}
+
+/// Returns the full rustc command line for a cached compiler invocation,
+/// starting with the program.
+fn rustc_args(job: &ProcessBuilder, internals: &Internals) -> Vec<String> {
+    let mut args: Vec<_> = job
+        .get_args()
+        .iter()
+        .cloned()
+        .map(|x| x.into_string().expect("cannot stringify job args"))
+        .collect();
+
+    let program = job
+        .get_program()
+        .clone()
+        .into_string()
+        .expect("cannot stringify job program");
+    args.insert(0, program);
+
+    // Needed to parse rustc diagnostics
+    if args.iter().find(|x| x.as_str() == "--error-format=json").is_none() {
+        args.push("--error-format=json".to_owned());
+    }
+
+    if args.iter().find(|x| x.as_str() == "--sysroot").is_none() {
+        let sysroot = super::rustc::current_sysroot()
+            .expect("need to specify SYSROOT env var or use rustup or multirust");
+
+        let config = internals.config.lock().unwrap();
+        if config.sysroot.is_none() {
+            args.push("--sysroot".to_owned());
+            args.push(sysroot);
+        }
+    }
+
+    args
+}
+
+/// Returns a window/progress update for a job about to be started.
+fn progress_update(job: &ProcessBuilder, finished: usize, job_count: usize) -> ProgressUpdate {
+    let crate_name = proc_argument_value(job, "--crate-name").and_then(|x| x.to_str());
+    match crate_name {
+        Some(name) => {
+            let cfg_test = job.get_args().iter().any(|arg| arg == "--test");
+            ProgressUpdate::Message(if cfg_test {
+                format!("{} cfg(test)", name)
+            } else {
+                name.to_owned()
+            })
+        }
+        // divide by zero is avoided by the assert! in `execute`
+        None => ProgressUpdate::Percentage(finished as f64 / job_count as f64),
+    }
+}
+
 /// Build system-agnostic, basic compilation unit
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Crate {
@@ -252,3 +406,112 @@ impl Default for Edition {
         Edition::Edition2015
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo::util::process;
+
+    #[test]
+    fn sequential_job_dependencies() {
+        let queue = JobQueue::with_commands(vec![process("a"), process("b"), process("c")]);
+        // The last job is run first, every other job waits for the one after it
+        assert_eq!(queue.dependencies(), vec![vec![1], vec![2], vec![]]);
+    }
+
+    #[test]
+    fn explicit_job_dependencies() {
+        let jobs = vec![process("a"), process("b"), process("c")];
+        let deps = vec![vec![1, 2], vec![2], vec![]];
+        let queue = JobQueue::with_dependencies(jobs, deps.clone());
+        assert_eq!(queue.dependencies(), deps);
+    }
+
+    /// Schedules jobs which finish immediately, one at a time, with `failing`
+    /// failing. Returns the order the jobs were started in, and their results.
+    fn schedule_in_order(
+        deps: &[Vec<usize>],
+        failing: Option<usize>,
+        cancel_token: &CancellationToken,
+    ) -> (Vec<usize>, Vec<Option<bool>>) {
+        let mut order = vec![];
+        let (results, _) = schedule(
+            deps,
+            1,
+            cancel_token,
+            |index, _, sender| {
+                order.push(index);
+                sender.send((index, Some(index) != failing)).unwrap();
+            },
+            |&ok| ok,
+        );
+        (order, results)
+    }
+
+    #[test]
+    fn schedule_follows_dependencies() {
+        let token = CancellationToken::new();
+        let (order, results) = schedule_in_order(&[vec![1], vec![2], vec![]], None, &token);
+        assert_eq!(order, vec![2, 1, 0]);
+        assert_eq!(results, vec![Some(true); 3]);
+
+        let (order, _) = schedule_in_order(&[vec![3], vec![], vec![3], vec![]], None, &token);
+        assert_eq!(order, vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn schedule_stops_after_failure() {
+        let token = CancellationToken::new();
+        let (order, results) = schedule_in_order(&[vec![1], vec![2], vec![]], Some(1), &token);
+        assert_eq!(order, vec![2, 1]);
+        assert_eq!(results, vec![None, Some(false), Some(true)]);
+
+        let (order, _) = schedule_in_order(&[vec![], vec![], vec![]], Some(2), &token);
+        assert_eq!(order, vec![2]);
+    }
+
+    #[test]
+    fn schedule_stops_when_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let (order, results) = schedule_in_order(&[vec![], vec![]], None, &token);
+        assert!(order.is_empty());
+        assert_eq!(results, vec![None, None]);
+    }
+
+    #[test]
+    fn schedule_runs_independent_jobs_concurrently() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        // Job 0 depends on all the others, which are independent
+        let deps = vec![vec![1, 2, 3, 4, 5], vec![], vec![], vec![], vec![], vec![]];
+        // The number of jobs running, and the most which ran at once
+        let running = Arc::new(Mutex::new((0, 0)));
+        let (results, finished) = schedule(
+            &deps,
+            2,
+            &CancellationToken::new(),
+            |index, finished, sender| {
+                if index == 0 {
+                    assert_eq!(finished, 5);
+                }
+                let running = Arc::clone(&running);
+                thread::spawn(move || {
+                    {
+                        let mut running = running.lock().unwrap();
+                        running.0 += 1;
+                        running.1 = running.1.max(running.0);
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                    running.lock().unwrap().0 -= 1;
+                    sender.send((index, ())).unwrap();
+                });
+            },
+            |_| true,
+        );
+        assert_eq!(finished, 6);
+        assert!(results.iter().all(Option::is_some));
+        assert!(running.lock().unwrap().1 <= 2);
+    }
+}
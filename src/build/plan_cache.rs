@@ -0,0 +1,890 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Persists the Cargo build plan in the RLS target directory, so that after a
+//! restart we can rebuild the workspace using the cached compiler invocations
+//! instead of running Cargo again.
+//!
+//! The cached plan is only reused if its fingerprint matches, that is, if
+//! none of Cargo.lock, the manifests and build scripts of the planned packages,
+//! the targets Cargo discovers in them, the `.cargo/config` files, the
+//! toolchain, the Cargo-related configuration or the files the build scripts
+//! asked to be rerun on change (`cargo:rerun-if-changed`) have changed since the
+//! plan was saved. Since the cached invocations refer to the artifacts of the
+//! dependencies and to the output of the build scripts, these have to still
+//! exist as well.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use cargo::core::compiler::{CompileMode, Kind};
+use cargo::core::profiles::Profile;
+use cargo::core::{Edition, LibKind, PackageId, Target, TargetKind};
+use cargo::util::{homedir, important_paths, process, ProcessBuilder};
+use log::{debug, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::build::cargo::plan_config_fingerprint;
+use crate::build::cargo_plan::{CargoPlan, OwnedUnit, UnitKey};
+use crate::build::BuildResult;
+use crate::config::Config;
+
+/// Name of the file the plan is cached in, inside of the RLS target directory.
+const CACHE_FILE_NAME: &str = "build-plan.json";
+
+/// Bumped whenever the format of the cached plan changes.
+const CACHE_FORMAT_VERSION: u32 = 3;
+
+/// Handle to the cached build plan of a given workspace.
+crate struct PlanCache {
+    path: PathBuf,
+    manifest_path: PathBuf,
+    /// Describes the configuration affecting the build plan.
+    config: String,
+}
+
+impl PlanCache {
+    /// Returns the cache for the workspace containing `build_dir`, or `None`
+    /// if there is no Cargo workspace.
+    crate fn new(build_dir: &Path, config: &Config) -> Option<PlanCache> {
+        let manifest_path = important_paths::find_root_manifest_for_wd(build_dir).ok()?;
+        // Mirrors the target directory used by `cargo::make_cargo_config`.
+        let target_dir = config
+            .target_dir
+            .as_ref()
+            .clone()
+            .unwrap_or_else(|| manifest_path.parent().unwrap().join("target").join("rls"));
+
+        Some(PlanCache {
+            path: target_dir.join(CACHE_FILE_NAME),
+            manifest_path,
+            config: plan_config_fingerprint(config),
+        })
+    }
+
+    /// Loads the cached plan, along with the working directory of the cached
+    /// compiler invocations, provided it's still up to date.
+    crate fn load(&self) -> Option<(CargoPlan, Option<PathBuf>)> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let cached: CachedPlan = match serde_json::from_str(&contents) {
+            Ok(cached) => cached,
+            Err(err) => {
+                debug!("Couldn't parse cached build plan {:?}: {}", self.path, err);
+                return None;
+            }
+        };
+
+        if cached.version != CACHE_FORMAT_VERSION {
+            trace!("Ignoring cached build plan with format {}", cached.version);
+            return None;
+        }
+        if self.fingerprint(&cached.inputs) != Some(cached.fingerprint) {
+            trace!("Ignoring outdated cached build plan {:?}", self.path);
+            return None;
+        }
+        if let Some(artifact) = cached.missing_artifact() {
+            trace!("Ignoring cached build plan, {:?} is missing", artifact);
+            return None;
+        }
+
+        let plan = cached.to_plan(&self.manifest_path);
+        if !plan.is_ready() {
+            return None;
+        }
+        debug!("Loaded cached build plan from {:?}", self.path);
+        Some((plan, cached.cwd))
+    }
+
+    /// Saves the plan, overwriting any previously cached one. Failures are
+    /// only logged, since caching is merely an optimisation.
+    crate fn save(&self, plan: &CargoPlan, cwd: Option<&Path>) {
+        let cached = match CachedPlan::from_plan(plan, cwd, &self.manifest_path) {
+            Some(cached) => cached,
+            None => {
+                debug!("Build plan can't be cached");
+                return;
+            }
+        };
+        let cached = match self.fingerprint(&cached.inputs) {
+            Some(fingerprint) => CachedPlan { fingerprint, ..cached },
+            None => return,
+        };
+
+        let result = serde_json::to_string(&cached)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                fs::write(&self.path, json).map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => trace!("Cached build plan in {:?}", self.path),
+            Err(err) => warn!("Couldn't cache build plan in {:?}: {}", self.path, err),
+        }
+    }
+
+    /// Computes a fingerprint of the toolchain, the configuration and the
+    /// given inputs. Returns `None` if any of the required files can't be read.
+    fn fingerprint(&self, inputs: &FingerprintInputs) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+
+        crate::version().hash(&mut hasher);
+        super::rustc::current_sysroot().hash(&mut hasher);
+        // Catches a changed RLS (and so rustc) without a version bump,
+        // e.g. after a nightly update.
+        env::current_exe()
+            .and_then(fs::metadata)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .hash(&mut hasher);
+        self.config.hash(&mut hasher);
+
+        for file in &inputs.files {
+            file.hash(&mut hasher);
+            fs::read(file).ok()?.hash(&mut hasher);
+        }
+        // Unlike the files above, these don't have to exist.
+        for file in &inputs.config_files {
+            file.hash(&mut hasher);
+            fs::read(file).ok().hash(&mut hasher);
+        }
+        for dir in &inputs.package_dirs {
+            discovered_targets(dir).hash(&mut hasher);
+        }
+        // Like Cargo, only look at the modification time of directories.
+        for path in &inputs.build_script_inputs {
+            path.hash(&mut hasher);
+            if path.is_dir() {
+                fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .hash(&mut hasher);
+            } else {
+                fs::read(path).ok().hash(&mut hasher);
+            }
+        }
+        Some(hasher.finish())
+    }
+}
+
+/// Returns whether the build executing a cached plan failed because the plan
+/// is outdated, in which case Cargo has to recreate it. Besides internal
+/// errors, these are the compiler errors about missing or incompatible
+/// dependencies.
+crate fn is_stale(result: &BuildResult) -> bool {
+    // Can't find crate, found possibly newer version of crate and found crate
+    // compiled by an incompatible version of rustc.
+    const STALE_DEPENDENCY_CODES: &[&str] = &["E0460", "E0463", "E0514"];
+
+    match result {
+        BuildResult::Success(_, diagnostics, .., false) => diagnostics.iter().any(|diagnostic| {
+            serde_json::from_str::<serde_json::Value>(diagnostic)
+                .ok()
+                .and_then(|diagnostic| diagnostic["code"]["code"].as_str().map(str::to_owned))
+                .map_or(false, |code| STALE_DEPENDENCY_CODES.contains(&&*code))
+        }),
+        BuildResult::Success(..) | BuildResult::Squashed => false,
+        BuildResult::Err(..) | BuildResult::CargoError { .. } => true,
+    }
+}
+
+/// What the fingerprint of a cached plan is computed from, besides the
+/// toolchain and the RLS configuration.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FingerprintInputs {
+    /// Files whose contents are part of the fingerprint, and which have to
+    /// exist for the plan to be used.
+    files: Vec<PathBuf>,
+    /// Cargo configuration files whose contents, or absence, are part of the
+    /// fingerprint.
+    config_files: Vec<PathBuf>,
+    /// Directories of local packages, whose automatically discovered targets
+    /// are part of the fingerprint.
+    package_dirs: Vec<PathBuf>,
+    /// Files and directories the build scripts are rerun on changes to, whose
+    /// contents, or absence, are part of the fingerprint.
+    build_script_inputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedPlan {
+    version: u32,
+    fingerprint: u64,
+    inputs: FingerprintInputs,
+    cwd: Option<PathBuf>,
+    built_packages: Vec<String>,
+    units: Vec<CachedUnit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedUnit {
+    package_id: PackageId,
+    target: CachedTarget,
+    mode: CachedCompileMode,
+    host: bool,
+    /// Indices of the units this one depends on.
+    deps: Vec<usize>,
+    job: Option<CachedJob>,
+    input_files: Option<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedTarget {
+    name: String,
+    kind: CachedTargetKind,
+    src_path: PathBuf,
+    edition: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum CachedTargetKind {
+    Lib(Vec<String>),
+    Bin,
+    Test,
+    Bench,
+    ExampleLib(Vec<String>),
+    ExampleBin,
+    CustomBuild,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum CachedCompileMode {
+    Test,
+    Build,
+    Check { test: bool },
+    Bench,
+    Doc { deps: bool },
+    Doctest,
+    RunCustomBuild,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedJob {
+    program: String,
+    args: Vec<String>,
+    envs: BTreeMap<String, Option<String>>,
+    cwd: Option<PathBuf>,
+}
+
+impl CachedPlan {
+    /// Converts the plan into its cached form. The fingerprint is left to be
+    /// filled in by the caller. Returns `None` if the plan contains data which
+    /// can't be represented, e.g. non-UTF-8 arguments.
+    fn from_plan(plan: &CargoPlan, cwd: Option<&Path>, manifest_path: &Path) -> Option<CachedPlan> {
+        let keys: Vec<&UnitKey> = plan.units.keys().collect();
+        let indices: HashMap<&UnitKey, usize> =
+            keys.iter().enumerate().map(|(i, key)| (*key, i)).collect();
+
+        let units = keys
+            .iter()
+            .map(|key| {
+                let unit = &plan.units[*key];
+                let deps = plan
+                    .dep_graph
+                    .get(*key)
+                    .into_iter()
+                    .flat_map(|deps| deps)
+                    .filter_map(|dep| indices.get(dep).cloned())
+                    .collect();
+                let job = match plan.compiler_jobs.get(*key) {
+                    Some(job) => Some(CachedJob::from_process(job)?),
+                    None => None,
+                };
+
+                Some(CachedUnit {
+                    package_id: unit.id.clone(),
+                    target: CachedTarget::from_target(&unit.target)?,
+                    mode: unit.mode.into(),
+                    host: unit.kind == Kind::Host,
+                    deps,
+                    job,
+                    input_files: plan.input_files.get(*key).cloned(),
+                })
+            }).collect::<Option<Vec<_>>>()?;
+
+        let mut built_packages: Vec<_> = plan.built_packages().iter().cloned().collect();
+        built_packages.sort();
+
+        Some(CachedPlan {
+            version: CACHE_FORMAT_VERSION,
+            fingerprint: 0,
+            inputs: fingerprint_inputs(manifest_path, plan),
+            cwd: cwd.map(Path::to_path_buf),
+            built_packages,
+            units,
+        })
+    }
+
+    /// Returns an artifact read by the cached compiler invocations which
+    /// doesn't exist and isn't produced by one of them, if there's any.
+    fn missing_artifact(&self) -> Option<PathBuf> {
+        let jobs: Vec<&CachedJob> = self
+            .units
+            .iter()
+            .filter_map(|unit| unit.job.as_ref())
+            .collect();
+        let planned: HashSet<PathBuf> = jobs.iter().filter_map(|job| job.output_stem()).collect();
+
+        jobs.iter()
+            .flat_map(|job| job.artifacts())
+            .find(|path| !path.exists() && !planned.contains(&path.with_extension("")))
+    }
+
+    fn to_plan(&self, manifest_path: &Path) -> CargoPlan {
+        let built_packages = self.built_packages.iter().cloned().collect();
+        let mut plan = CargoPlan::with_packages(manifest_path, built_packages);
+
+        let keys: Vec<UnitKey> = self
+            .units
+            .iter()
+            .map(|unit| {
+                let mode = CompileMode::from(&unit.mode);
+                (unit.package_id.clone(), unit.target.to_target(), mode)
+            }).collect();
+
+        for (unit, key) in self.units.iter().zip(&keys) {
+            plan.units.insert(
+                key.clone(),
+                OwnedUnit {
+                    id: key.0.clone(),
+                    target: key.1.clone(),
+                    profile: Profile::default(),
+                    kind: if unit.host { Kind::Host } else { Kind::Target },
+                    mode: key.2,
+                },
+            );
+
+            let deps: HashSet<UnitKey> = unit.deps.iter().map(|&dep| keys[dep].clone()).collect();
+            plan.rev_dep_graph.entry(key.clone()).or_insert_with(HashSet::new);
+            for dep in &deps {
+                plan.rev_dep_graph
+                    .entry(dep.clone())
+                    .or_insert_with(HashSet::new)
+                    .insert(key.clone());
+            }
+            plan.dep_graph.insert(key.clone(), deps);
+
+            if let Some(ref job) = unit.job {
+                plan.compiler_jobs.insert(key.clone(), job.to_process());
+            }
+            if let Some(ref input_files) = unit.input_files {
+                for file in input_files {
+                    plan.file_key_mapping
+                        .entry(file.clone())
+                        .or_default()
+                        .insert(key.clone());
+                }
+                plan.input_files.insert(key.clone(), input_files.clone());
+            }
+        }
+
+        plan
+    }
+}
+
+/// Returns the inputs which, when modified, invalidate the plan: Cargo.lock,
+/// the manifests and build scripts of the planned packages, the targets Cargo
+/// discovers in the local ones, the Cargo configuration files and the inputs
+/// of the build scripts.
+fn fingerprint_inputs(manifest_path: &Path, plan: &CargoPlan) -> FingerprintInputs {
+    let workspace_dir = manifest_path.parent().unwrap();
+    let mut files = vec![
+        manifest_path.to_owned(),
+        manifest_path.with_file_name("Cargo.lock"),
+    ];
+    let mut package_dirs = vec![workspace_dir.to_owned()];
+
+    for (id, target, _) in plan.units.keys() {
+        if let Ok(package_dir) = id.source_id().url().to_file_path() {
+            files.push(package_dir.join("Cargo.toml"));
+            package_dirs.push(package_dir);
+        }
+        if *target.kind() == TargetKind::CustomBuild && target.src_path().is_path() {
+            files.push(target.src_path().path().to_owned());
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    // A workspace without dependencies may have no lock file yet.
+    files.retain(|file| file.exists());
+    package_dirs.sort();
+    package_dirs.dedup();
+
+    let mut build_script_inputs: Vec<_> = plan
+        .compiler_jobs
+        .values()
+        .flat_map(build_script_inputs)
+        .collect();
+    build_script_inputs.sort();
+    build_script_inputs.dedup();
+
+    FingerprintInputs {
+        files,
+        config_files: config_files(workspace_dir),
+        package_dirs,
+        build_script_inputs,
+    }
+}
+
+/// Returns the paths the build script whose output `job` is compiled with
+/// asked to be rerun on changes to, as recorded by Cargo next to `OUT_DIR`.
+fn build_script_inputs(job: &ProcessBuilder) -> Vec<PathBuf> {
+    let env = |key: &str| job.get_envs().get(key).and_then(|value| value.as_ref());
+    let (out_dir, manifest_dir) = match (env("OUT_DIR"), env("CARGO_MANIFEST_DIR")) {
+        (Some(out_dir), Some(manifest_dir)) => (Path::new(out_dir), Path::new(manifest_dir)),
+        _ => return vec![],
+    };
+    let output = match fs::read_to_string(out_dir.with_file_name("output")) {
+        Ok(output) => output,
+        Err(_) => return vec![],
+    };
+
+    const RERUN_IF_CHANGED: &str = "cargo:rerun-if-changed=";
+    output
+        .lines()
+        .filter(|line| line.starts_with(RERUN_IF_CHANGED))
+        .map(|line| manifest_dir.join(&line[RERUN_IF_CHANGED.len()..]))
+        .collect()
+}
+
+/// Returns the files Cargo reads its configuration from when building the
+/// workspace in `workspace_dir`, whether or not they exist.
+fn config_files(workspace_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = workspace_dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect();
+    dirs.extend(homedir(workspace_dir));
+    dirs.sort();
+    dirs.dedup();
+
+    dirs.into_iter()
+        .flat_map(|dir| vec![dir.join("config"), dir.join("config.toml")])
+        .collect()
+}
+
+/// Returns the source files of the targets Cargo discovers in the package in
+/// `package_dir` without them being listed in its manifest, so that adding or
+/// removing one invalidates the plan.
+fn discovered_targets(package_dir: &Path) -> Vec<PathBuf> {
+    let mut targets: Vec<_> = ["build.rs", "src/lib.rs", "src/main.rs"]
+        .iter()
+        .map(|file| package_dir.join(file))
+        .filter(|file| file.exists())
+        .collect();
+
+    for dir in &["src/bin", "tests", "examples", "benches"] {
+        let entries = match fs::read_dir(package_dir.join(dir)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.extension() == Some(OsStr::new("rs")) {
+                targets.push(path);
+            } else if path.join("main.rs").exists() {
+                targets.push(path.join("main.rs"));
+            }
+        }
+    }
+
+    targets.sort();
+    targets
+}
+
+impl CachedTarget {
+    fn from_target(target: &Target) -> Option<CachedTarget> {
+        if !target.src_path().is_path() {
+            return None;
+        }
+
+        let crate_types =
+            |kinds: &[LibKind]| kinds.iter().map(|k| k.crate_type().to_owned()).collect();
+        let kind = match *target.kind() {
+            TargetKind::Lib(ref kinds) => CachedTargetKind::Lib(crate_types(kinds)),
+            TargetKind::Bin => CachedTargetKind::Bin,
+            TargetKind::Test => CachedTargetKind::Test,
+            TargetKind::Bench => CachedTargetKind::Bench,
+            TargetKind::ExampleLib(ref kinds) => CachedTargetKind::ExampleLib(crate_types(kinds)),
+            TargetKind::ExampleBin => CachedTargetKind::ExampleBin,
+            TargetKind::CustomBuild => CachedTargetKind::CustomBuild,
+        };
+
+        Some(CachedTarget {
+            name: target.name().to_owned(),
+            kind,
+            src_path: target.src_path().path().to_owned(),
+            edition: target.edition().to_string(),
+        })
+    }
+
+    fn to_target(&self) -> Target {
+        let name = &self.name;
+        let src_path = self.src_path.clone();
+        let edition = self.edition.parse().unwrap_or(Edition::Edition2015);
+        let lib_kinds = |kinds: &[String]| kinds.iter().map(|k| LibKind::from_str(k)).collect();
+
+        match self.kind {
+            CachedTargetKind::Lib(ref kinds) => {
+                Target::lib_target(name, lib_kinds(kinds), src_path, edition)
+            }
+            CachedTargetKind::Bin => Target::bin_target(name, src_path, None, edition),
+            CachedTargetKind::Test => Target::test_target(name, src_path, None, edition),
+            CachedTargetKind::Bench => Target::bench_target(name, src_path, None, edition),
+            CachedTargetKind::ExampleLib(ref kinds) => {
+                Target::example_target(name, lib_kinds(kinds), src_path, None, edition)
+            }
+            CachedTargetKind::ExampleBin => {
+                Target::example_target(name, vec![], src_path, None, edition)
+            }
+            CachedTargetKind::CustomBuild => Target::custom_build_target(name, src_path, edition),
+        }
+    }
+}
+
+impl From<CompileMode> for CachedCompileMode {
+    fn from(mode: CompileMode) -> CachedCompileMode {
+        match mode {
+            CompileMode::Test => CachedCompileMode::Test,
+            CompileMode::Build => CachedCompileMode::Build,
+            CompileMode::Check { test } => CachedCompileMode::Check { test },
+            CompileMode::Bench => CachedCompileMode::Bench,
+            CompileMode::Doc { deps } => CachedCompileMode::Doc { deps },
+            CompileMode::Doctest => CachedCompileMode::Doctest,
+            CompileMode::RunCustomBuild => CachedCompileMode::RunCustomBuild,
+        }
+    }
+}
+
+impl<'a> From<&'a CachedCompileMode> for CompileMode {
+    fn from(mode: &CachedCompileMode) -> CompileMode {
+        match *mode {
+            CachedCompileMode::Test => CompileMode::Test,
+            CachedCompileMode::Build => CompileMode::Build,
+            CachedCompileMode::Check { test } => CompileMode::Check { test },
+            CachedCompileMode::Bench => CompileMode::Bench,
+            CachedCompileMode::Doc { deps } => CompileMode::Doc { deps },
+            CachedCompileMode::Doctest => CompileMode::Doctest,
+            CachedCompileMode::RunCustomBuild => CompileMode::RunCustomBuild,
+        }
+    }
+}
+
+impl CachedJob {
+    /// Returns the value passed with the `flag` argument, if any.
+    fn arg(&self, flag: &str) -> Option<&str> {
+        self.args
+            .windows(2)
+            .find(|pair| pair[0] == flag)
+            .map(|pair| &*pair[1])
+    }
+
+    /// Returns the path of the library emitted by this invocation, minus the
+    /// extension, e.g. `target/rls/debug/deps/libfoo-0123456789abcdef`.
+    fn output_stem(&self) -> Option<PathBuf> {
+        const EXTRA_FILENAME: &str = "extra-filename=";
+        let extra_filename = self
+            .args
+            .windows(2)
+            .filter(|pair| pair[0] == "-C" && pair[1].starts_with(EXTRA_FILENAME))
+            .map(|pair| &pair[1][EXTRA_FILENAME.len()..])
+            .last()
+            .unwrap_or("");
+        let file_name = format!("lib{}{}", self.arg("--crate-name")?, extra_filename);
+
+        Some(self.resolve(self.arg("--out-dir")?).join(file_name))
+    }
+
+    /// Returns the artifacts read by this invocation: the dependencies passed
+    /// with `--extern`, the `-L dependency=` search paths and the output
+    /// directory of the build script.
+    fn artifacts(&self) -> Vec<PathBuf> {
+        let mut artifacts: Vec<_> = self
+            .args
+            .windows(2)
+            .filter_map(|pair| match &*pair[0] {
+                "--extern" => pair[1].splitn(2, '=').nth(1),
+                "-L" if pair[1].starts_with("dependency=") => pair[1].splitn(2, '=').nth(1),
+                _ => None,
+            }).map(|path| self.resolve(path))
+            .collect();
+        if let Some(Some(out_dir)) = self.envs.get("OUT_DIR") {
+            artifacts.push(self.resolve(out_dir));
+        }
+        artifacts
+    }
+
+    /// Resolves a path passed to the invocation against its working directory.
+    fn resolve(&self, path: &str) -> PathBuf {
+        match self.cwd {
+            Some(ref cwd) => cwd.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    fn from_process(job: &ProcessBuilder) -> Option<CachedJob> {
+        let envs = job
+            .get_envs()
+            .iter()
+            .map(|(k, v)| match v {
+                Some(v) => Some((k.clone(), Some(v.clone().into_string().ok()?))),
+                None => Some((k.clone(), None)),
+            }).collect::<Option<_>>()?;
+
+        Some(CachedJob {
+            program: job.get_program().clone().into_string().ok()?,
+            args: job
+                .get_args()
+                .iter()
+                .map(|arg| arg.clone().into_string().ok())
+                .collect::<Option<_>>()?,
+            envs,
+            cwd: job.get_cwd().map(Path::to_path_buf),
+        })
+    }
+
+    fn to_process(&self) -> ProcessBuilder {
+        let mut job = process(&self.program);
+        job.args(&self.args);
+        for (k, v) in &self.envs {
+            match v {
+                Some(v) => job.env(k, v),
+                None => job.env_remove(k),
+            };
+        }
+        if let Some(ref cwd) = self.cwd {
+            job.cwd(cwd);
+        }
+        job
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a package `foo` with a library in a new temporary directory.
+    fn package(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rls-plan-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        ).unwrap();
+        fs::write(dir.join("src").join("lib.rs"), "").unwrap();
+        dir
+    }
+
+    /// A plan checking the library of the package in `dir`.
+    fn plan(dir: &Path) -> CargoPlan {
+        let packages = vec!["foo".to_owned()].into_iter().collect();
+        let mut plan = CargoPlan::with_packages(&dir.join("Cargo.toml"), packages);
+
+        let url = url::Url::from_file_path(dir).unwrap();
+        let id: PackageId =
+            serde_json::from_value(serde_json::json!(format!("foo 0.1.0 (path+{})", url)))
+                .unwrap();
+        let src_path = dir.join("src").join("lib.rs");
+        let target = Target::lib_target("foo", vec![LibKind::Lib], src_path, Edition::Edition2018);
+        let mode = CompileMode::Check { test: false };
+        let key = (id.clone(), target.clone(), mode);
+
+        plan.units.insert(
+            key.clone(),
+            OwnedUnit {
+                id,
+                target,
+                profile: Profile::default(),
+                kind: Kind::Host,
+                mode,
+            },
+        );
+        plan.compiler_jobs.insert(key, process("rustc"));
+        plan
+    }
+
+    #[test]
+    fn saved_plan_loads() {
+        let dir = package("load");
+        let cache = PlanCache::new(&dir, &Config::default()).unwrap();
+        assert!(cache.load().is_none());
+
+        cache.save(&plan(&dir), Some(&dir));
+        let (loaded, cwd) = cache.load().unwrap();
+        assert_eq!(loaded.compiler_jobs.len(), 1);
+        assert_eq!(loaded.built_packages(), plan(&dir).built_packages());
+        assert_eq!(cwd, Some(dir.clone()));
+
+        // Editing the sources doesn't change the plan
+        fs::write(dir.join("src").join("lib.rs"), "pub fn foo() {}").unwrap();
+        assert!(cache.load().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saved_plan_invalidated() {
+        let dir = package("invalidate");
+        let cache = PlanCache::new(&dir, &Config::default()).unwrap();
+        let changes = vec![
+            ("Cargo.toml", "[package]\nname = \"foo\"\nversion = \"0.2.0\"\n"),
+            ("tests/it.rs", ""),
+            ("src/bin/tool/main.rs", ""),
+            ("examples/demo.rs", ""),
+            ("benches/bench.rs", ""),
+            (".cargo/config", "[build]\nrustflags = [\"-Dwarnings\"]\n"),
+        ];
+        for (file, contents) in changes {
+            cache.save(&plan(&dir), None);
+            assert!(cache.load().is_some());
+
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            assert!(cache.load().is_none(), "plan not invalidated by {}", file);
+        }
+
+        // As is removing a file the plan depends on
+        cache.save(&plan(&dir), None);
+        fs::remove_file(dir.join("Cargo.toml")).unwrap();
+        assert!(cache.load().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saved_plan_invalidated_by_build_script_inputs() {
+        let dir = package("rerun");
+        let cache = PlanCache::new(&dir, &Config::default()).unwrap();
+        let out_dir = dir.join("target").join("build").join("foo-1").join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        fs::write(
+            out_dir.with_file_name("output"),
+            "cargo:rustc-cfg=foo\ncargo:rerun-if-changed=data.txt\n",
+        ).unwrap();
+        fs::write(dir.join("data.txt"), "1").unwrap();
+
+        let mut plan = plan(&dir);
+        plan.compiler_jobs
+            .values_mut()
+            .next()
+            .unwrap()
+            .env("OUT_DIR", &out_dir)
+            .env("CARGO_MANIFEST_DIR", &dir);
+
+        cache.save(&plan, None);
+        assert!(cache.load().is_some());
+        fs::write(dir.join("data.txt"), "2").unwrap();
+        assert!(cache.load().is_none());
+
+        cache.save(&plan, None);
+        assert!(cache.load().is_some());
+        fs::remove_file(dir.join("data.txt")).unwrap();
+        assert!(cache.load().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saved_plan_needs_artifacts() {
+        let dir = package("artifacts");
+        let cache = PlanCache::new(&dir, &Config::default()).unwrap();
+        let deps = dir.join("target").join("deps");
+        let dep = deps.join("libbar-2.rmeta");
+        let out_dir = dir.join("target").join("build").join("foo-1").join("out");
+
+        let mut plan = plan(&dir);
+        plan.compiler_jobs
+            .values_mut()
+            .next()
+            .unwrap()
+            .args(&["--crate-name", "foo", "-C", "extra-filename=-1", "--out-dir"])
+            .arg(&deps)
+            .arg("-L")
+            .arg(format!("dependency={}", deps.display()))
+            .arg("--extern")
+            .arg(format!("bar={}", dep.display()))
+            // Artifacts produced by the plan itself don't have to exist yet
+            .arg("--extern")
+            .arg(format!("foo={}", deps.join("libfoo-1.rmeta").display()))
+            .env("OUT_DIR", &out_dir);
+        cache.save(&plan, None);
+
+        assert!(cache.load().is_none());
+        fs::create_dir_all(&deps).unwrap();
+        assert!(cache.load().is_none());
+        fs::write(&dep, "").unwrap();
+        assert!(cache.load().is_none());
+        fs::create_dir_all(&out_dir).unwrap();
+        assert!(cache.load().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stale_build_results() {
+        let build = |diagnostic: &str, success| {
+            let diagnostics = vec![diagnostic.to_owned()];
+            BuildResult::Success(PathBuf::new(), diagnostics, vec![], HashMap::new(), success)
+        };
+        let missing = r#"{"message": "can't find crate for `bar`", "code": {"code": "E0463"}}"#;
+        let type_error = r#"{"message": "mismatched types", "code": {"code": "E0308"}}"#;
+
+        assert!(is_stale(&build(missing, false)));
+        assert!(!is_stale(&build(type_error, false)));
+        assert!(!is_stale(&build("", true)));
+        assert!(!is_stale(&BuildResult::Squashed));
+        assert!(is_stale(&BuildResult::Err("rustc panicked".to_owned(), None)));
+    }
+
+    #[test]
+    fn cached_job_roundtrip() {
+        let mut job = process("rustc");
+        job.args(&["--crate-name", "foo", "src/lib.rs"])
+            .env("CARGO_PKG_NAME", "foo")
+            .env_remove("RUST_LOG")
+            .cwd("/my/repo");
+
+        let cached = CachedJob::from_process(&job).unwrap();
+        let json = serde_json::to_string(&cached).unwrap();
+        let cached: CachedJob = serde_json::from_str(&json).unwrap();
+        let restored = cached.to_process();
+
+        assert_eq!(restored.get_program(), job.get_program());
+        assert_eq!(restored.get_args(), job.get_args());
+        assert_eq!(restored.get_envs(), job.get_envs());
+        assert_eq!(restored.get_cwd(), job.get_cwd());
+    }
+
+    #[test]
+    fn compile_mode_roundtrip() {
+        let modes = vec![
+            CompileMode::Test,
+            CompileMode::Build,
+            CompileMode::Check { test: true },
+            CompileMode::Check { test: false },
+            CompileMode::Bench,
+            CompileMode::Doc { deps: true },
+            CompileMode::Doctest,
+            CompileMode::RunCustomBuild,
+        ];
+        for mode in modes {
+            let cached: CachedCompileMode = mode.into();
+            assert_eq!(CompileMode::from(&cached), mode);
+        }
+    }
+}
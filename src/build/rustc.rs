@@ -48,8 +48,10 @@ use self::syntax::edition::Edition as RustcEdition;
 use crate::build::environment::{Environment, EnvironmentLockFacade};
 use crate::build::{BufWriter, BuildResult};
 use crate::build::plan::{Crate, Edition};
+use crate::concurrency::CancellationToken;
 use crate::config::{ClippyPreference, Config};
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::OsString;
@@ -59,6 +61,13 @@ use std::sync::{Arc, Mutex};
 use std::process::Command;
 
 // Runs a single instance of rustc. Runs in-process.
+//
+// The env vars and the cwd are only set, under the environment lock, until the
+// crate is expanded (e.g., for `env!` and proc macros), so that the analysis of
+// other crates can run in the meantime.
+//
+// If a `cancel_token` is given and signalled, the compilation is aborted
+// before analysis and `BuildResult::Squashed` is returned.
 crate fn rustc(
     vfs: &Vfs,
     args: &[String],
@@ -67,6 +76,7 @@ crate fn rustc(
     build_dir: &Path,
     rls_config: Arc<Mutex<Config>>,
     env_lock: &EnvironmentLockFacade,
+    cancel_token: Option<CancellationToken>,
 ) -> BuildResult {
     trace!(
         "rustc - args: `{:?}`, envs: {:?}, cwd: {:?}, build dir: {:?}",
@@ -92,8 +102,11 @@ crate fn rustc(
     local_envs.entry("CARGO_MANIFEST_DIR".into())
         .or_insert_with(|| Some(build_dir.into()));
 
-    let (guard, _) = env_lock.lock();
-    let restore_env = Environment::push_with_lock(&local_envs, cwd, guard);
+    let env_lock = env_lock.clone();
+    let job_cwd = cwd.map(Path::to_path_buf);
+    // The cwd the compiler runs in when none is given
+    let default_cwd = Arc::new(Mutex::new(None));
+    let compiler_default_cwd = Arc::clone(&default_cwd);
 
     let buf = Arc::new(Mutex::new(vec![]));
     let err_buf = buf.clone();
@@ -117,7 +130,12 @@ crate fn rustc(
 
     let analysis = Arc::default();
     let input_files = Arc::default();
-    let controller = Box::new(RlsRustcCalls::new(Arc::clone(&analysis), Arc::clone(&input_files), clippy_pref));
+    let controller = Box::new(RlsRustcCalls::new(
+        Arc::clone(&analysis),
+        Arc::clone(&input_files),
+        clippy_pref,
+        cancel_token.clone(),
+    ));
 
     // rustc explicitly panics in run_compiler() on compile failure, regardless
     // if it encounters an ICE (internal compiler error) or not.
@@ -125,6 +143,15 @@ crate fn rustc(
     // regular compilation failure with errors?
     let result = ::std::panic::catch_unwind(|| {
         run(move || {
+            // The environment has to be set on the compiler thread, which is
+            // where `after_expand` restores it.
+            let (guard, _) = env_lock.lock();
+            let job_cwd = job_cwd.as_ref().map(|p| &**p);
+            let env = Environment::push_with_lock(&local_envs, job_cwd, guard);
+            *compiler_default_cwd.lock().unwrap() = Some(env.get_old_cwd().to_owned());
+            let args = absolute_paths(&args, &env::current_dir().expect("failed to read cwd"));
+            let _env = ExpansionEnv::set(env);
+
             // Replace stderr so we catch most errors.
             run_compiler(
                 &args,
@@ -135,6 +162,11 @@ crate fn rustc(
         })
     });
 
+    if cancel_token.map_or(false, |token| token.is_cancelled()) {
+        trace!("rustc - cancelled");
+        return BuildResult::Squashed;
+    }
+
     // FIXME(#25) given that we are running the compiler directly, there is no need
     // to serialize the error messages - we should pass them in memory.
     let err_buf = Arc::try_unwrap(err_buf).unwrap().into_inner().unwrap();
@@ -150,12 +182,70 @@ crate fn rustc(
     let input_files = Arc::try_unwrap(input_files).unwrap().into_inner().unwrap();
 
     let cwd = cwd
-        .unwrap_or_else(|| restore_env.get_old_cwd())
-        .to_path_buf();
+        .map(Path::to_path_buf)
+        .or_else(|| default_cwd.lock().unwrap().clone())
+        .unwrap_or_else(|| PathBuf::from("."));
 
     BuildResult::Success(cwd, stderr_json_msgs, analysis, input_files, result.is_ok())
 }
 
+thread_local! {
+    /// The environment of the compilation running on this thread, until it's
+    /// restored after expansion.
+    static EXPANSION_ENV: RefCell<Option<Environment<'static>>> = RefCell::new(None);
+}
+
+/// Keeps the environment of the compilation running on the current thread set
+/// until `ExpansionEnv::restore` is called, or at the latest until it's dropped
+/// (e.g., when the compilation stops before expansion).
+struct ExpansionEnv;
+
+impl ExpansionEnv {
+    fn set(env: Environment<'static>) -> ExpansionEnv {
+        EXPANSION_ENV.with(|current| *current.borrow_mut() = Some(env));
+        ExpansionEnv
+    }
+
+    /// Restores the env vars and the cwd, and releases the environment lock.
+    fn restore() {
+        let env = EXPANSION_ENV.with(|current| current.borrow_mut().take());
+        drop(env);
+    }
+}
+
+impl Drop for ExpansionEnv {
+    fn drop(&mut self) {
+        ExpansionEnv::restore();
+    }
+}
+
+/// Makes the paths in the arguments relative to `cwd` absolute, since the
+/// compiler keeps using them (to look up dependencies and to write its output)
+/// after the environment, and with it the cwd, has been restored.
+fn absolute_paths(args: &[String], cwd: &Path) -> Vec<String> {
+    const SEARCH_PATH_KINDS: &[&str] = &["dependency", "crate", "native", "framework", "all"];
+    let absolute = |path: &str| cwd.join(path).to_string_lossy().into_owned();
+    // `name=path` or `kind=path`
+    let absolute_value =
+        |value: &str, eq: usize| format!("{}={}", &value[..eq], absolute(&value[eq + 1..]));
+
+    let mut args = args.to_owned();
+    for i in 1..args.len() {
+        let value = &args[i];
+        let path = match (&*args[i - 1], value.find('=')) {
+            ("--out-dir", _) | ("-o", _) => absolute(value),
+            ("-L", Some(eq)) if SEARCH_PATH_KINDS.contains(&&value[..eq]) => {
+                absolute_value(value, eq)
+            }
+            ("-L", _) => absolute(value),
+            ("--extern", Some(eq)) => absolute_value(value, eq),
+            _ => continue,
+        };
+        args[i] = path;
+    }
+    args
+}
+
 // Our compiler controller. We mostly delegate to the default rustc
 // controller, but use our own callback for save-analysis.
 #[derive(Clone)]
@@ -164,6 +254,7 @@ struct RlsRustcCalls {
     analysis: Arc<Mutex<Option<Analysis>>>,
     input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     clippy_preference: ClippyPreference,
+    cancel_token: Option<CancellationToken>,
 }
 
 impl RlsRustcCalls {
@@ -171,12 +262,14 @@ impl RlsRustcCalls {
         analysis: Arc<Mutex<Option<Analysis>>>,
         input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
         clippy_preference: ClippyPreference,
+        cancel_token: Option<CancellationToken>,
     ) -> RlsRustcCalls {
         RlsRustcCalls {
             default_calls: Box::new(RustcDefaultCalls),
             analysis,
             input_files,
             clippy_preference,
+            cancel_token,
         }
     }
 }
@@ -273,6 +366,7 @@ impl<'a> CompilerCalls<'a> for RlsRustcCalls {
     ) -> CompileController<'a> {
         let analysis = self.analysis.clone();
         let input_files = self.input_files.clone();
+        let cancel_token = self.cancel_token.clone();
         #[cfg(feature = "clippy")]
         let clippy_preference = self.clippy_preference;
         let mut result = self.default_calls.build_controller(sess, matches);
@@ -286,6 +380,16 @@ impl<'a> CompilerCalls<'a> for RlsRustcCalls {
         }
 
         result.after_expand.callback = Box::new(move |state| {
+            // Nothing depends on the environment anymore, so let other
+            // compilations set theirs.
+            ExpansionEnv::restore();
+
+            // Analysis is the most expensive part of the compilation, so skip it
+            // if the build has been cancelled in the meantime.
+            if cancel_token.as_ref().map_or(false, |token| token.is_cancelled()) {
+                state.session.fatal("build cancelled");
+            }
+
             let cwd = &state.session.working_dir.0;
 
             let src_path = match state.input {
@@ -430,3 +534,120 @@ pub fn src_path(cwd: Option<&Path>, path: impl AsRef<Path>) -> Option<PathBuf> {
         (None, _) => std::env::current_dir().ok()?.join(path)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::environment::EnvironmentLock;
+    use std::fs;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn absolute_paths_of_arguments() {
+        let cwd = env::temp_dir().join("repo");
+        let absolute = |path: &str| cwd.join(path).to_string_lossy().into_owned();
+        let args: Vec<_> = vec![
+            "rustc".to_owned(),
+            "src/lib.rs".to_owned(),
+            "--out-dir".to_owned(),
+            "target".to_owned(),
+            "-L".to_owned(),
+            "dependency=deps".to_owned(),
+            "-L".to_owned(),
+            absolute("lib"),
+            "--extern".to_owned(),
+            "foo=deps/libfoo.rlib".to_owned(),
+            "--extern".to_owned(),
+            "proc_macro".to_owned(),
+        ];
+
+        assert_eq!(
+            absolute_paths(&args, &cwd),
+            vec![
+                "rustc".to_owned(),
+                "src/lib.rs".to_owned(),
+                "--out-dir".to_owned(),
+                absolute("target"),
+                "-L".to_owned(),
+                format!("dependency={}", absolute("deps")),
+                "-L".to_owned(),
+                absolute("lib"),
+                "--extern".to_owned(),
+                format!("foo={}", absolute("deps/libfoo.rlib")),
+                "--extern".to_owned(),
+                "proc_macro".to_owned(),
+            ]
+        );
+    }
+
+    /// Compiles the library `name` from `name.rs` in `dir` on a new thread.
+    fn compile(dir: &Path, name: &str, emit: String) -> thread::JoinHandle<BuildResult> {
+        let args = vec![
+            "rustc".to_owned(),
+            "--crate-name".to_owned(),
+            name.to_owned(),
+            "--crate-type=lib".to_owned(),
+            emit,
+            "--out-dir".to_owned(),
+            "out".to_owned(),
+            "--sysroot".to_owned(),
+            current_sysroot().unwrap(),
+            format!("{}.rs", name),
+        ];
+        let dir = dir.to_owned();
+        thread::spawn(move || {
+            rustc(
+                &Vfs::new(),
+                &args,
+                &HashMap::new(),
+                Some(&dir),
+                &dir,
+                Arc::new(Mutex::new(Config::default())),
+                &EnvironmentLock::get().as_facade(),
+                None,
+            )
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compilations_overlap_after_expansion() {
+        let dir = env::temp_dir().join(format!("rls-rustc-overlap-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("out")).unwrap();
+
+        // Reading `expanding` in `include_str!` blocks the expansion of `a` until
+        // it's written to, and writing the dep-info of `a` to `analyzing` blocks
+        // it after expansion until it's read.
+        let expanding = dir.join("expanding");
+        let analyzing = dir.join("analyzing");
+        for fifo in &[&expanding, &analyzing] {
+            assert!(Command::new("mkfifo").arg(fifo).status().unwrap().success());
+        }
+        let a_src = format!("pub const A: &str = include_str!({:?});", expanding);
+        fs::write(dir.join("a.rs"), a_src).unwrap();
+        fs::write(dir.join("b.rs"), "pub const B: u8 = 0;").unwrap();
+
+        let emit = format!("--emit=dep-info={},metadata", analyzing.display());
+        let a = compile(&dir, "a", emit);
+        // Once this returns, `a` is being expanded and holds the environment lock
+        fs::write(&expanding, "").unwrap();
+
+        // `b` can only be compiled if `a` releases the lock after expansion
+        let b = compile(&dir, "b", "--emit=metadata".to_owned());
+        let (sender, receiver) = channel();
+        thread::spawn(move || drop(sender.send(b.join().unwrap())));
+        let b = receiver.recv_timeout(Duration::from_secs(60));
+
+        fs::read(&analyzing).unwrap();
+        let a = a.join().unwrap();
+        match (a, b) {
+            (BuildResult::Success(.., true), Ok(BuildResult::Success(.., true))) => {}
+            (a, b) => panic!("unexpected results: {:?}, {:?}", a, b),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `rls check`, a headless mode which builds a project once, the same way the
+//! language server does, and reports the diagnostics (and suggested fixes) in a
+//! format suitable for CI: LSP-shaped JSON, SARIF 2.1 or JUnit XML.
+
+use crate::actions::diagnostics::{Diagnostic, Suggestion};
+use crate::actions::post_build::{AnalysisQueue, BuildResults, PostBuildHandler};
+use crate::actions::progress::{DiagnosticsNotifier, ProgressNotifier, ProgressUpdate};
+use crate::build::{BuildPriority, BuildQueue};
+use crate::concurrency::{ConcurrentJob, Jobs};
+use crate::config::{ClippyPreference, Config};
+use crate::lsp_data::{PublishDiagnosticsParams, Range};
+use crate::version;
+
+use languageserver_types::{DiagnosticSeverity, NumberOrString};
+use rls_analysis::{AnalysisHost, Target};
+use rls_vfs::Vfs;
+use serde_json::{self, json, Value};
+use url::Url;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Sarif,
+    Junit,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    project_dir: PathBuf,
+    format: Format,
+    output: Option<PathBuf>,
+    max_errors: usize,
+    max_warnings: Option<usize>,
+    clippy: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            project_dir: PathBuf::from("."),
+            format: Format::Json,
+            output: None,
+            max_errors: 0,
+            max_warnings: None,
+            clippy: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Expected a value after '{}'", arg))
+            };
+            let count = |value: &str| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("Expected a number after '{}'", arg))
+            };
+            match arg.as_str() {
+                "--format" => {
+                    options.format = match value()?.as_str() {
+                        "json" => Format::Json,
+                        "sarif" => Format::Sarif,
+                        "junit" => Format::Junit,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--output" => options.output = Some(PathBuf::from(value()?)),
+                "--max-errors" => options.max_errors = count(value()?)?,
+                "--max-warnings" => options.max_warnings = Some(count(value()?)?),
+                "--clippy" => options.clippy = true,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+                _ => options.project_dir = PathBuf::from(arg),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Run `rls check` with the arguments following `check`. Returns 0 if the
+/// number of errors and warnings is within the given limits, 1 if it isn't and
+/// 101 if the project could not be built at all.
+pub fn run(args: &[String]) -> i32 {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", help());
+        return 0;
+    }
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n{}", e, help());
+            return 101;
+        }
+    };
+    let project_dir = match env::current_dir()
+        .and_then(|cwd| cwd.join(&options.project_dir).canonicalize())
+    {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Could not find the project: {}", e);
+            return 101;
+        }
+    };
+
+    let mut config = Config::default();
+    if options.clippy {
+        config.clippy_preference = ClippyPreference::On;
+    }
+    if let Err(e) = config.infer_defaults(&project_dir) {
+        eprintln!("Could not load the project at {}: {}", project_dir.display(), e);
+        return 101;
+    }
+
+    // Rustc locates the secondary spans of a diagnostic for us, but SARIF and
+    // JUnit have nowhere to put them, so leave them in the message for those.
+    let related_information = options.format == Format::Json;
+    let (results, errors) = build(&project_dir, config, related_information);
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+
+    let mut results: Vec<_> = results.into_iter().filter(|(_, d)| !d.is_empty()).collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let report = match options.format {
+        Format::Json => serde_json::to_string_pretty(&to_json(&results)).unwrap(),
+        Format::Sarif => serde_json::to_string_pretty(&to_sarif(&results)).unwrap(),
+        Format::Junit => to_junit(&results),
+    };
+    let written = match options.output {
+        Some(ref path) => File::create(path).and_then(|mut f| writeln!(f, "{}", report)),
+        None => writeln!(io::stdout(), "{}", report),
+    };
+    if let Err(e) = written {
+        eprintln!("Could not write the report: {}", e);
+        return 101;
+    }
+
+    if !errors.is_empty() {
+        return 101;
+    }
+    let count = |severity| {
+        results
+            .iter()
+            .flat_map(|(_, diagnostics)| diagnostics)
+            .filter(|(d, _)| d.severity == Some(severity))
+            .count()
+    };
+    let (error_count, warning_count) = (
+        count(DiagnosticSeverity::Error),
+        count(DiagnosticSeverity::Warning),
+    );
+    eprintln!("{} errors, {} warnings", error_count, warning_count);
+    let too_many_warnings = options.max_warnings.map_or(false, |max| warning_count > max);
+    if error_count > options.max_errors || too_many_warnings {
+        1
+    } else {
+        0
+    }
+}
+
+// Builds the project in `project_dir` and returns the diagnostics the language
+// server would publish, and any errors preventing the build from running.
+fn build(
+    project_dir: &Path,
+    config: Config,
+    related_information_support: bool,
+) -> (BuildResults, Vec<String>) {
+    let build_queue = BuildQueue::new(Arc::new(Vfs::new()), Arc::new(Mutex::new(config)));
+    let results = Arc::new(Mutex::new(BuildResults::default()));
+    let errors = Arc::new(Mutex::new(vec![]));
+
+    let mut jobs = Jobs::default();
+    let (job, token) = ConcurrentJob::new();
+    jobs.add(job);
+
+    let pbh = PostBuildHandler {
+        analysis: Arc::new(AnalysisHost::new(Target::Debug)),
+        analysis_queue: Arc::new(AnalysisQueue::init()),
+        previous_build_results: results.clone(),
+        file_to_crates: Arc::default(),
+        project_path: project_dir.to_owned(),
+        show_warnings: true,
+        use_black_list: true,
+        related_information_support,
+        shown_cargo_error: Arc::default(),
+        active_build_count: Arc::new(AtomicUsize::new(1)),
+        notifier: Box::new(CheckNotifier(errors.clone())),
+        blocked_threads: vec![],
+        _token: token,
+    };
+    let notifier = Box::new(CheckNotifier(errors.clone()));
+    build_queue.request_build(project_dir, BuildPriority::Cargo, notifier, pbh);
+    // The post-build handler owns the job token, so this returns once the
+    // build results have been handled.
+    jobs.wait_for_all();
+
+    let results = results.lock().unwrap().drain().collect();
+    let errors = errors.lock().unwrap().drain(..).collect();
+    (results, errors)
+}
+
+// Diagnostics are collected from the post-build handler's results rather than
+// from the notifications, since those don't include the suggestions. All that
+// is left to handle here are errors from running the build itself.
+struct CheckNotifier(Arc<Mutex<Vec<String>>>);
+
+impl ProgressNotifier for CheckNotifier {
+    fn notify_begin_progress(&self) {}
+    fn notify_progress(&self, _: ProgressUpdate) {}
+    fn notify_end_progress(&self) {}
+}
+
+impl DiagnosticsNotifier for CheckNotifier {
+    fn notify_begin_diagnostics(&self) {}
+    fn notify_publish_diagnostics(&self, _: PublishDiagnosticsParams) {}
+    fn notify_error_diagnostics(&self, msg: String) {
+        self.0.lock().unwrap().push(msg);
+    }
+    fn notify_end_diagnostics(&self) {}
+}
+
+type FileDiagnostics = (PathBuf, Vec<(Diagnostic, Vec<Suggestion>)>);
+
+fn severity_name(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::Error) => "error",
+        Some(DiagnosticSeverity::Warning) => "warning",
+        Some(DiagnosticSeverity::Information) => "info",
+        Some(DiagnosticSeverity::Hint) | None => "hint",
+    }
+}
+
+fn code(diagnostic: &Diagnostic) -> Option<String> {
+    match diagnostic.code {
+        Some(NumberOrString::String(ref code)) if !code.is_empty() => Some(code.clone()),
+        Some(NumberOrString::Number(code)) => Some(code.to_string()),
+        _ => None,
+    }
+}
+
+fn file_url(path: &Path) -> String {
+    Url::from_file_path(path)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| path.display().to_string())
+}
+
+// An array of `textDocument/publishDiagnostics` parameters, with the
+// suggestions added to each diagnostic.
+fn to_json(results: &[FileDiagnostics]) -> Value {
+    let files = results
+        .iter()
+        .map(|(path, diagnostics)| {
+            let diagnostics: Vec<_> = diagnostics
+                .iter()
+                .map(|(diagnostic, suggestions)| {
+                    let mut json = serde_json::to_value(diagnostic).unwrap();
+                    let suggestions: Vec<_> = suggestions
+                        .iter()
+                        .map(|s| json!({ "range": s.range, "newText": s.new_text, "label": s.label }))
+                        .collect();
+                    json["suggestions"] = Value::Array(suggestions);
+                    json
+                })
+                .collect();
+            json!({ "uri": file_url(path), "diagnostics": diagnostics })
+        })
+        .collect();
+    Value::Array(files)
+}
+
+// SARIF lines and columns are one-based, LSP ones zero-based.
+fn sarif_region(range: &Range) -> Value {
+    json!({
+        "startLine": range.start.line + 1,
+        "startColumn": range.start.character + 1,
+        "endLine": range.end.line + 1,
+        "endColumn": range.end.character + 1,
+    })
+}
+
+fn to_sarif(results: &[FileDiagnostics]) -> Value {
+    let results: Vec<_> = results
+        .iter()
+        .flat_map(|(path, diagnostics)| diagnostics.iter().map(move |d| (path, d)))
+        .map(|(path, (diagnostic, suggestions))| {
+            let uri = file_url(path);
+            let level = match diagnostic.severity {
+                Some(DiagnosticSeverity::Error) => "error",
+                Some(DiagnosticSeverity::Warning) => "warning",
+                _ => "note",
+            };
+            let mut result = json!({
+                "level": level,
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri },
+                        "region": sarif_region(&diagnostic.range),
+                    }
+                }],
+            });
+            if let Some(code) = code(diagnostic) {
+                result["ruleId"] = Value::String(code);
+            }
+            if !suggestions.is_empty() {
+                let fixes: Vec<_> = suggestions
+                    .iter()
+                    .map(|s| {
+                        json!({
+                            "description": { "text": s.label },
+                            "artifactChanges": [{
+                                "artifactLocation": { "uri": uri },
+                                "replacements": [{
+                                    "deletedRegion": sarif_region(&s.range),
+                                    "insertedContent": { "text": s.new_text },
+                                }],
+                            }],
+                        })
+                    })
+                    .collect();
+                result["fixes"] = Value::Array(fixes);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://schemastore.azurewebsites.net/schemas/json/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rls",
+                    "version": version(),
+                    "informationUri": "https://github.com/rust-lang/rls",
+                }
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// One test case per diagnostic, each failing with the diagnostic.
+fn to_junit(results: &[FileDiagnostics]) -> String {
+    let mut cases = String::new();
+    let mut count = 0;
+    for (path, diagnostics) in results {
+        for (diagnostic, _) in diagnostics {
+            count += 1;
+            let start = diagnostic.range.start;
+            let name = format!("{}:{}:{}", path.display(), start.line + 1, start.character + 1);
+            let severity = severity_name(diagnostic);
+            let summary = match code(diagnostic) {
+                Some(code) => format!("{}[{}]", severity, code),
+                None => severity.to_owned(),
+            };
+            cases.push_str(&format!(
+                "    <testcase classname=\"{}\" name=\"{}\">\n      <failure type=\"{}\" message=\"{}\">{}</failure>\n    </testcase>\n",
+                xml_escape(diagnostic.source.as_ref().map_or("rustc", |s| &**s)),
+                xml_escape(&name),
+                severity,
+                xml_escape(diagnostic.message.lines().next().unwrap_or("")),
+                xml_escape(&format!("{}: {}\n --> {}", summary, diagnostic.message, name)),
+            ));
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n  <testsuite name=\"rls check\" tests=\"{0}\" failures=\"{0}\" errors=\"0\">\n{1}  </testsuite>\n</testsuites>",
+        count, cases
+    )
+}
+
+fn help() -> &'static str {
+    r#"
+rls check [PROJECT_DIR] [OPTIONS]
+
+Builds the project (the current directory by default) and reports diagnostics.
+
+Options:
+    --format json|sarif|junit  report format, defaults to json
+    --output FILE              write the report to FILE rather than stdout
+    --max-errors N             fail if there are more than N errors, defaults to 0
+    --max-warnings N           fail if there are more than N warnings, defaults
+                               to no limit
+    --clippy                   run clippy lints as well
+"#
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_data::Position;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn results() -> Vec<FileDiagnostics> {
+        let range = Range::new(Position::new(1, 4), Position::new(1, 7));
+        let diagnostic = Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::Warning),
+            code: Some(NumberOrString::String("unused_variables".to_owned())),
+            source: Some("rustc".to_owned()),
+            message: "unused variable: `foo`".to_owned(),
+            related_information: None,
+        };
+        let suggestion = Suggestion {
+            range,
+            new_text: "_foo".to_owned(),
+            label: "Change to `_foo`".to_owned(),
+        };
+        vec![(PathBuf::from("/src/main.rs"), vec![(diagnostic, vec![suggestion])])]
+    }
+
+    #[test]
+    fn parse_options() {
+        assert_eq!(
+            Options::parse(&args(&[
+                "--format", "sarif", "--max-warnings", "3", "--clippy", "project",
+            ])),
+            Ok(Options {
+                project_dir: PathBuf::from("project"),
+                format: Format::Sarif,
+                output: None,
+                max_errors: 0,
+                max_warnings: Some(3),
+                clippy: true,
+            })
+        );
+        assert!(Options::parse(&args(&["--format", "xml"])).is_err());
+        assert!(Options::parse(&args(&["--max-errors"])).is_err());
+        assert!(Options::parse(&args(&["--max-errors", "many"])).is_err());
+    }
+
+    #[test]
+    fn json_includes_suggestions() {
+        let json = to_json(&results());
+        let diagnostic = &json[0]["diagnostics"][0];
+        assert_eq!(diagnostic["severity"], 2);
+        assert_eq!(diagnostic["suggestions"][0]["newText"], "_foo");
+    }
+
+    #[test]
+    fn sarif_regions_are_one_based() {
+        let sarif = to_sarif(&results());
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["level"], "warning");
+        assert_eq!(result["ruleId"], "unused_variables");
+        let region = &result["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startLine"], 2);
+        assert_eq!(region["startColumn"], 5);
+        let fix = &result["fixes"][0]["artifactChanges"][0]["replacements"][0];
+        assert_eq!(fix["insertedContent"]["text"], "_foo");
+    }
+
+    #[test]
+    fn junit_escapes_messages() {
+        let junit = to_junit(&results());
+        assert!(junit.contains("tests=\"1\" failures=\"1\""));
+        assert!(junit.contains("name=\"/src/main.rs:2:5\""));
+        assert!(junit.contains("message=\"unused variable: `foo`\""));
+
+        let mut results = results();
+        (results[0].1)[0].0.message = "expected `<T>`".to_owned();
+        assert!(to_junit(&results).contains("expected `&lt;T&gt;`"));
+    }
+}
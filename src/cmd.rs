@@ -11,28 +11,38 @@
 //! This module presents the RLS as a command line interface, it takes simple
 //! versions of commands, turns them into messages the RLS will understand, runs
 //! the RLS as usual and prints the JSON result back on the command line.
+//!
+//! In batch mode, the commands are read from a file instead, and run once the
+//! project has been built and indexed. The result of each command is printed as
+//! a single line of JSON, which makes the RLS scriptable.
 
-use crate::actions::requests;
+use crate::actions::{notifications, requests};
 use crate::config::Config;
-use crate::server::{self, LsService, Notification, Request, RequestId};
+use crate::lsp_data::{LSPNotification, LSPRequest, NOTIFICATION__Progress};
+use crate::server::{self, Address, LsService, Notification, Request, RequestId, Stream};
 use rls_analysis::{AnalysisHost, Target};
 use rls_vfs::Vfs;
+use serde_json::{self, json, Value};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use languageserver_types::{
-    ClientCapabilities, CodeActionContext, CodeActionParams, CompletionItem,
+    ClientCapabilities, CodeActionContext, CodeActionParams, CodeLensParams, CompletionItem,
+    CompletionParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
     DocumentFormattingParams, DocumentRangeFormattingParams, DocumentSymbolParams,
-    FormattingOptions, InitializeParams, Position, Range, RenameParams, TextDocumentIdentifier,
-    TextDocumentPositionParams, TraceOption, WorkspaceSymbolParams,
+    FormattingOptions, InitializeParams, Position, Range, ReferenceContext, ReferenceParams,
+    RenameParams, TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, TraceOption, VersionedTextDocumentIdentifier,
+    WorkspaceSymbolParams,
 };
 
 use std::collections::HashMap;
 use std::fmt;
-use std::io::{stdin, stdout, Write};
+use std::fs;
+use std::io::{self, stdin, stdout, BufRead, BufReader, Write};
 use std::marker::PhantomData;
 use std::path::Path;
-use std::str::FromStr;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -47,10 +57,31 @@ macro_rules! print_verb {
     }
 }
 
+/// How long batch mode waits for the response to a request, or for the build
+/// triggered by a change to finish.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(120);
+
 /// Run the RLS in command line mode.
 pub fn run() {
-    let sender = init();
+    run_with(init());
+}
+
+/// Run the command line interface against an RLS which is already running and
+/// listening on `address` (see `--listen`), rather than starting one.
+pub fn run_connected(address: &Address) -> i32 {
+    match connect(address) {
+        Ok(sender) => {
+            run_with(sender);
+            0
+        }
+        Err(e) => {
+            println!("Could not connect to {}: {}", address, e);
+            101
+        }
+    }
+}
 
+fn run_with(sender: Sender<String>) {
     loop {
         // Present a prompt and read from stdin.
         print!("> ");
@@ -60,133 +91,12 @@ pub fn run() {
             .read_line(&mut input)
             .expect("Could not read from stdin");
 
-        // Split the input into an action command and args
-        let mut bits = input.split_whitespace();
-        let action = bits.next();
-        let action = match action {
-            Some(a) => a,
-            None => continue,
-        };
-
-        // Switch on the action and build an appropriate message.
-        let msg = match action {
-            "def" => {
-                let file_name = bits.next().expect("Expected file name");
-                let row = bits.next().expect("Expected line number");
-                let col = bits.next().expect("Expected column number");
-                def(file_name, row, col).to_string()
-            }
-            "rename" => {
-                let file_name = bits.next().expect("Expected file name");
-                let row = bits.next().expect("Expected line number");
-                let col = bits.next().expect("Expected column number");
-                let new_name = bits.next().expect("Expected new name");
-                rename(file_name, row, col, new_name).to_string()
-            }
-            "hover" => {
-                let file_name = bits.next().expect("Expected file name");
-                let row = bits.next().expect("Expected line number");
-                let col = bits.next().expect("Expected column number");
-                hover(file_name, row, col).to_string()
-            }
-            "symbol" => {
-                let query = bits.next().expect("Expected a query");
-                workspace_symbol(query).to_string()
-            }
-            "document" => {
-                let query = bits.next().expect("Expected file name");
-                document_symbol(query).to_string()
-            }
-            "format" => {
-                let file_name = bits.next().expect("Expected file name");
-                let tab_size: u64 = bits
-                    .next()
-                    .unwrap_or("4")
-                    .parse()
-                    .expect("Tab size should be an unsigned integer");
-                let insert_spaces: bool = bits
-                    .next()
-                    .unwrap_or("true")
-                    .parse()
-                    .expect("Insert spaces should be 'true' or 'false'");
-                format(file_name, tab_size, insert_spaces).to_string()
-            }
-            "range_format" => {
-                let file_name = bits.next().expect("Expected file name");
-                let start_row: u64 = bits
-                    .next()
-                    .expect("Expected start line")
-                    .parse()
-                    .expect("Bad start line");
-                let start_col: u64 = bits
-                    .next()
-                    .expect("Expected start column")
-                    .parse()
-                    .expect("Bad start column");
-                let end_row: u64 = bits
-                    .next()
-                    .expect("Expected end line")
-                    .parse()
-                    .expect("Bad end line");
-                let end_col: u64 = bits
-                    .next()
-                    .expect("Expected end column")
-                    .parse()
-                    .expect("Bad end column");
-                let tab_size: u64 = bits
-                    .next()
-                    .unwrap_or("4")
-                    .parse()
-                    .expect("Tab size should be an unsigned integer");
-                let insert_spaces: bool = bits
-                    .next()
-                    .unwrap_or("true")
-                    .parse()
-                    .expect("Insert spaces should be 'true' or 'false'");
-                range_format(
-                    file_name,
-                    start_row,
-                    start_col,
-                    end_row,
-                    end_col,
-                    tab_size,
-                    insert_spaces,
-                ).to_string()
-            }
-            "code_action" => {
-                let file_name = bits.next().expect("Expect file name");
-                let start_row: u64 = bits
-                    .next()
-                    .expect("Expect start line")
-                    .parse()
-                    .expect("Bad start line");
-                let start_col: u64 = bits
-                    .next()
-                    .expect("Expect start column")
-                    .parse()
-                    .expect("Bad start column");
-                let end_row: u64 = bits
-                    .next()
-                    .expect("Expect end line")
-                    .parse()
-                    .expect("Bad end line");
-                let end_col: u64 = bits
-                    .next()
-                    .expect("Expect end column")
-                    .parse()
-                    .expect("Bad end column");
-                code_action(file_name, start_row, start_col, end_row, end_col).to_string()
-            }
-            "resolve" => {
-                let label = bits.next().expect("Expect label");
-                let detail = bits.next().expect("Expect detail");
-                resolve_completion(label, detail).to_string()
-            }
-            "h" | "help" => {
+        match input.split_whitespace().next() {
+            Some("h") | Some("help") => {
                 help();
                 continue;
             }
-            "q" | "quit" => {
+            Some("q") | Some("quit") => {
                 sender
                     .send(shutdown().to_string())
                     .expect("Error sending on channel");
@@ -197,8 +107,14 @@ pub fn run() {
                 thread::sleep(Duration::from_millis(100));
                 return;
             }
-            _ => {
-                println!("Unknown action. Type 'help' to see available actions.");
+            _ => {}
+        }
+
+        let msg = match parse_command(&input) {
+            Ok(Some(msg)) => msg.text,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("{}", e);
                 continue;
             }
         };
@@ -211,47 +127,225 @@ pub fn run() {
     }
 }
 
-fn def(file_name: &str, row: &str, col: &str) -> Request<requests::Definition> {
-    let params = TextDocumentPositionParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
-        position: Position::new(
-            u64::from_str(row).expect("Bad line number"),
-            u64::from_str(col).expect("Bad column number"),
-        ),
+/// Run the RLS in batch mode: run the commands in the file at `path` (or read
+/// from stdin, if `path` is `-`) once the project has been built and indexed,
+/// printing the result of each as a line of JSON. Returns 0 if all commands
+/// succeeded.
+pub fn run_batch(path: &str) -> i32 {
+    let input: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(stdin()))
+    } else {
+        match fs::File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Could not open {}: {}", path, e);
+                return 101;
+            }
+        }
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
+
+    let mut server = BatchServer::init();
+    server.wait_for_diagnostics(None);
+
+    let mut failed = false;
+    for line in input.lines() {
+        let line = line.expect("Could not read commands");
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let result = match parse_command(&line) {
+            Ok(Some(msg)) => server.run(msg),
+            Ok(None) => continue,
+            Err(e) => Err(Value::String(e)),
+        };
+        let output = match result {
+            Ok(result) => json!({ "command": line, "result": result }),
+            Err(error) => {
+                failed = true;
+                json!({ "command": line, "error": error })
+            }
+        };
+        println!("{}", output);
+    }
+
+    let _ = server.run(Message::request(shutdown()));
+    server.send(&exit().to_string());
+
+    if failed {
+        1
+    } else {
+        0
     }
 }
 
-fn rename(file_name: &str, row: &str, col: &str, new_name: &str) -> Request<requests::Rename> {
-    let params = RenameParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
-        position: Position::new(
-            u64::from_str(row).expect("Bad line number"),
-            u64::from_str(col).expect("Bad column number"),
-        ),
-        new_name: new_name.to_owned(),
-    };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
+/// A message to the server, built from a command.
+struct Message {
+    text: String,
+    /// The id of the request, `None` for notifications.
+    id: Option<RequestId>,
+    /// Should batch mode wait for a build after sending this message?
+    builds: bool,
+}
+
+impl Message {
+    fn request<A>(request: Request<A>) -> Message
+    where
+        A: LSPRequest,
+        <A as LSPRequest>::Params: serde::Serialize,
+    {
+        Message {
+            id: Some(request.id.clone()),
+            text: request.to_string(),
+            builds: false,
+        }
+    }
+
+    fn notification<A>(notification: Notification<A>) -> Message
+    where
+        A: LSPNotification,
+        <A as LSPNotification>::Params: serde::Serialize,
+    {
+        Message {
+            id: None,
+            text: notification.to_string(),
+            builds: false,
+        }
     }
 }
 
-fn hover(file_name: &str, row: &str, col: &str) -> Request<requests::Hover> {
-    let params = TextDocumentPositionParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
-        position: Position::new(
-            u64::from_str(row).expect("Bad line number"),
-            u64::from_str(col).expect("Bad column number"),
-        ),
+// The arguments of a command.
+struct Args<'a>(SplitWhitespace<'a>);
+
+impl<'a> Args<'a> {
+    fn next(&mut self, what: &str) -> Result<&'a str, String> {
+        self.0.next().ok_or_else(|| format!("Expected {}", what))
+    }
+
+    fn parse<T: FromStr>(&mut self, what: &str) -> Result<T, String> {
+        self.next(what)?
+            .parse()
+            .map_err(|_| format!("Bad {}", what))
+    }
+
+    fn parse_or<T: FromStr>(&mut self, what: &str, default: T) -> Result<T, String> {
+        match self.0.next() {
+            Some(arg) => arg.parse().map_err(|_| format!("Bad {}", what)),
+            None => Ok(default),
+        }
+    }
+
+    fn file(&mut self) -> Result<Url, String> {
+        url(self.next("file name")?)
+    }
+
+    fn position(&mut self) -> Result<Position, String> {
+        Ok(Position::new(
+            self.parse("line number")?,
+            self.parse("column number")?,
+        ))
+    }
+
+    fn text_document_position(&mut self) -> Result<TextDocumentPositionParams, String> {
+        Ok(TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier::new(self.file()?),
+            position: self.position()?,
+        })
+    }
+
+    fn range(&mut self) -> Result<Range, String> {
+        Ok(Range {
+            start: Position::new(self.parse("start line")?, self.parse("start column")?),
+            end: Position::new(self.parse("end line")?, self.parse("end column")?),
+        })
+    }
+
+    fn formatting_options(&mut self) -> Result<FormattingOptions, String> {
+        Ok(FormattingOptions {
+            tab_size: self.parse_or("tab size, it should be an unsigned integer", 4)?,
+            insert_spaces: self
+                .parse_or("insert spaces, it should be 'true' or 'false'", true)?,
+            // no optional properties
+            properties: HashMap::default(),
+        })
+    }
+}
+
+// Builds the message for a command line, returns `None` for an empty line.
+fn parse_command(line: &str) -> Result<Option<Message>, String> {
+    // Split the input into an action command and args
+    let mut bits = line.split_whitespace();
+    let action = match bits.next() {
+        Some(a) => a,
+        None => return Ok(None),
     };
+    let mut args = Args(bits);
+
+    // Switch on the action and build an appropriate message.
+    let msg = match action {
+        "def" => Message::request(def(args.text_document_position()?)),
+        "rename" => {
+            let params = args.text_document_position()?;
+            let new_name = args.next("new name")?;
+            Message::request(rename(params, new_name))
+        }
+        "hover" => Message::request(hover(args.text_document_position()?)),
+        "symbol" => Message::request(workspace_symbol(args.next("a query")?)),
+        "document" => Message::request(document_symbol(args.file()?)),
+        "format" => {
+            let file = args.file()?;
+            Message::request(format(file, args.formatting_options()?))
+        }
+        "range_format" => {
+            let file = args.file()?;
+            let range = args.range()?;
+            Message::request(range_format(file, range, args.formatting_options()?))
+        }
+        "code_action" => {
+            let file = args.file()?;
+            Message::request(code_action(file, args.range()?))
+        }
+        "resolve" => {
+            let label = args.next("label")?;
+            Message::request(resolve_completion(label, args.next("detail")?))
+        }
+        "refs" => Message::request(references(args.text_document_position()?)),
+        "impls" => Message::request(request::<requests::Implementation>(
+            args.text_document_position()?,
+        )),
+        "highlight" => Message::request(request::<requests::DocumentHighlight>(
+            args.text_document_position()?,
+        )),
+        "complete" => Message::request(complete(args.text_document_position()?)),
+        "lens" => Message::request(request::<requests::CodeLensRequest>(CodeLensParams {
+            text_document: TextDocumentIdentifier::new(args.file()?),
+        })),
+        "type_def" => Message::request(request::<requests::TypeDefinition>(
+            args.text_document_position()?,
+        )),
+        "open" => {
+            let file_name = args.next("file name")?;
+            let contents = args.next("contents file").unwrap_or(file_name);
+            Message::notification(open(url(file_name)?, read_contents(contents)?))
+        }
+        "change" => {
+            let file = args.file()?;
+            let contents = read_contents(args.next("contents file")?)?;
+            Message {
+                builds: true,
+                ..Message::notification(change(file, contents))
+            }
+        }
+        _ => return Err("Unknown action. Type 'help' to see available actions.".to_owned()),
+    };
+    Ok(Some(msg))
+}
+
+fn read_contents(file_name: &str) -> Result<String, String> {
+    fs::read_to_string(file_name).map_err(|e| format!("Could not read {}: {}", file_name, e))
+}
+
+fn request<A: LSPRequest>(params: A::Params) -> Request<A> {
     Request {
         id: next_id(),
         params,
@@ -260,128 +354,131 @@ fn hover(file_name: &str, row: &str, col: &str) -> Request<requests::Hover> {
     }
 }
 
+fn def(params: TextDocumentPositionParams) -> Request<requests::Definition> {
+    request(params)
+}
+
+fn rename(params: TextDocumentPositionParams, new_name: &str) -> Request<requests::Rename> {
+    let params = RenameParams {
+        text_document: params.text_document,
+        position: params.position,
+        new_name: new_name.to_owned(),
+    };
+    request(params)
+}
+
+fn hover(params: TextDocumentPositionParams) -> Request<requests::Hover> {
+    request(params)
+}
+
 fn workspace_symbol(query: &str) -> Request<requests::WorkspaceSymbol> {
     let params = WorkspaceSymbolParams {
         query: query.to_owned(),
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
-    }
+    request(params)
 }
 
-fn format(file_name: &str, tab_size: u64, insert_spaces: bool) -> Request<requests::Formatting> {
-    // no optional properties
-    let properties = HashMap::default();
-
+fn format(file: Url, options: FormattingOptions) -> Request<requests::Formatting> {
     let params = DocumentFormattingParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
-        options: FormattingOptions {
-            tab_size,
-            insert_spaces,
-            properties,
-        },
+        text_document: TextDocumentIdentifier::new(file),
+        options,
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
-    }
+    request(params)
 }
 
-fn document_symbol(file_name: &str) -> Request<requests::Symbols> {
+fn document_symbol(file: Url) -> Request<requests::Symbols> {
     let params = DocumentSymbolParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
+        text_document: TextDocumentIdentifier::new(file),
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
-    }
+    request(params)
 }
 
 fn range_format(
-    file_name: &str,
-    start_row: u64,
-    start_col: u64,
-    end_row: u64,
-    end_col: u64,
-    tab_size: u64,
-    insert_spaces: bool,
+    file: Url,
+    range: Range,
+    options: FormattingOptions,
 ) -> Request<requests::RangeFormatting> {
-    // no optional properties
-    let properties = HashMap::default();
-
     let params = DocumentRangeFormattingParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
-        range: Range {
-            start: Position::new(start_row, start_col),
-            end: Position::new(end_row, end_col),
-        },
-        options: FormattingOptions {
-            tab_size,
-            insert_spaces,
-            properties,
-        },
+        text_document: TextDocumentIdentifier::new(file),
+        range,
+        options,
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
-    }
+    request(params)
 }
 
-fn code_action(
-    file_name: &str,
-    start_row: u64,
-    start_col: u64,
-    end_row: u64,
-    end_col: u64,
-) -> Request<requests::CodeAction> {
+fn code_action(file: Url, range: Range) -> Request<requests::CodeAction> {
     let params = CodeActionParams {
-        text_document: TextDocumentIdentifier::new(url(file_name)),
-        range: Range {
-            start: Position::new(start_row, start_col),
-            end: Position::new(end_row, end_col),
-        },
+        text_document: TextDocumentIdentifier::new(file),
+        range,
         context: CodeActionContext {
             diagnostics: Vec::new(),
             only: None,
         },
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
-    }
+    request(params)
 }
 
 fn resolve_completion(label: &str, detail: &str) -> Request<requests::ResolveCompletion> {
     let params = CompletionItem::new_simple(label.to_owned(), detail.to_owned());
+    request(params)
+}
 
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
+fn references(params: TextDocumentPositionParams) -> Request<requests::References> {
+    let params = ReferenceParams {
+        text_document: params.text_document,
+        position: params.position,
+        context: ReferenceContext {
+            include_declaration: true,
+        },
+    };
+    request(params)
+}
+
+fn complete(params: TextDocumentPositionParams) -> Request<requests::Completion> {
+    let params = CompletionParams {
+        text_document: params.text_document,
+        position: params.position,
+        context: None,
+    };
+    request(params)
+}
+
+fn open(file: Url, text: String) -> Notification<notifications::DidOpenTextDocument> {
+    Notification {
+        params: DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: file,
+                language_id: "rust".to_owned(),
+                version: next_version(),
+                text,
+            },
+        },
         _action: PhantomData,
     }
 }
 
-fn shutdown() -> Request<server::ShutdownRequest> {
-    Request {
-        id: next_id(),
-        params: (),
-        received: Instant::now(),
+// Replaces the whole contents of the file.
+fn change(file: Url, text: String) -> Notification<notifications::DidChangeTextDocument> {
+    Notification {
+        params: DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: file,
+                version: Some(next_version()),
+            },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text,
+            }],
+        },
         _action: PhantomData,
     }
 }
 
+fn shutdown() -> Request<server::ShutdownRequest> {
+    request(())
+}
+
 fn exit() -> Notification<server::ExitNotification> {
     Notification {
         params: (),
@@ -389,10 +486,10 @@ fn exit() -> Notification<server::ExitNotification> {
     }
 }
 
-fn initialize(root_path: String) -> Request<server::InitializeRequest> {
+fn initialize(root_path: String) -> Result<Request<server::InitializeRequest>, String> {
     let params = InitializeParams {
         process_id: None,
-        root_uri: Some(url(&root_path)),
+        root_uri: Some(url(&root_path)?),
         root_path: Some(root_path),
         initialization_options: None,
         capabilities: ClientCapabilities {
@@ -403,18 +500,13 @@ fn initialize(root_path: String) -> Request<server::InitializeRequest> {
         trace: Some(TraceOption::Off),
         workspace_folders: None,
     };
-    Request {
-        id: next_id(),
-        params,
-        received: Instant::now(),
-        _action: PhantomData,
-    }
+    Ok(request(params))
 }
 
-fn url(file_name: &str) -> Url {
+fn url(file_name: &str) -> Result<Url, String> {
     let canonical = Path::new(file_name)
         .canonicalize()
-        .expect("Could not canonicalize file name");
+        .map_err(|e| format!("Could not canonicalize file name {}: {}", file_name, e))?;
     let mut path = canonical.to_str().unwrap();
 
     // workaround for UNC path see https://github.com/rust-lang/rust/issues/42869
@@ -422,7 +514,7 @@ fn url(file_name: &str) -> Url {
         path = &path[r"\\?\".len()..];
     }
 
-    Url::parse(&format!("file://{}", path)).expect("Bad file name")
+    Url::parse(&format!("file://{}", path)).map_err(|_| format!("Bad file name {}", file_name))
 }
 
 fn next_id() -> RequestId {
@@ -430,6 +522,12 @@ fn next_id() -> RequestId {
     RequestId::Num(ID.fetch_add(1, Ordering::SeqCst))
 }
 
+// Versions only need to increase for each file, so share them between files.
+fn next_version() -> u64 {
+    static VERSION: AtomicU64 = AtomicU64::new(1);
+    VERSION.fetch_add(1, Ordering::SeqCst)
+}
+
 // Custom reader and output for the RLS server.
 #[derive(Clone)]
 struct PrintlnOutput;
@@ -471,32 +569,198 @@ impl server::MessageReader for ChannelMsgReader {
 // Initialize a server, returns the sender end of a channel for posting messages.
 // The initialized server will live on its own thread and look after the receiver.
 fn init() -> Sender<String> {
+    let (sender, receiver) = channel();
+    start_server(receiver, PrintlnOutput);
+
+    send_initialize(&sender);
+    println!("Initializing (look for `progress[done:true]` message)...");
+
+    sender
+}
+
+fn start_server<O: server::Output>(receiver: Receiver<String>, output: O) {
     let analysis = Arc::new(AnalysisHost::new(Target::Debug));
     let vfs = Arc::new(Vfs::new());
-    let (sender, receiver) = channel();
 
     let service = LsService::new(
         analysis,
         vfs,
         Arc::new(Mutex::new(Config::default())),
         Box::new(ChannelMsgReader::new(receiver)),
-        PrintlnOutput,
+        output,
     );
     thread::spawn(move || LsService::run(service));
+}
 
-    sender
-        .send(
-            initialize(
-                ::std::env::current_dir()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-            ).to_string(),
-        ).expect("Error sending init");
+// Connect to a running server, returns the sender end of a channel for posting messages.
+// Messages are forwarded to the server, and its responses printed, on their own threads.
+fn connect(address: &Address) -> io::Result<Sender<String>> {
+    let stream = Stream::connect(address)?;
+    let mut writer = stream.try_clone()?;
+    let (sender, receiver) = channel::<String>();
+
+    thread::spawn(move || {
+        for msg in receiver {
+            if let Err(e) = server::write_message(&mut writer, &msg) {
+                println!("Lost connection to the server: {}", e);
+                return;
+            }
+        }
+    });
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        while let Ok(msg) = server::read_message(&mut reader) {
+            // Pretty print, to match the output of an in-process server.
+            match serde_json::from_str::<serde_json::Value>(&msg) {
+                Ok(json) => println!("{}", serde_json::to_string_pretty(&json).unwrap()),
+                Err(_) => println!("{}", msg),
+            }
+        }
+        print_verb!("Server closed the connection");
+    });
+
+    send_initialize(&sender);
     println!("Initializing (look for `progress[done:true]` message)...");
+    Ok(sender)
+}
 
-    sender
+fn send_initialize(sender: &Sender<String>) {
+    let root_path = ::std::env::current_dir()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let init = initialize(root_path).expect("Could not initialize");
+    sender.send(init.to_string()).expect("Error sending init");
+}
+
+// Passes everything the server sends on to the batch mode driver.
+#[derive(Clone)]
+struct BatchOutput {
+    sender: Arc<Mutex<Sender<String>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl server::Output for BatchOutput {
+    fn response(&self, output: String) {
+        let _ = self.sender.lock().unwrap().send(output);
+    }
+
+    fn provide_id(&self) -> RequestId {
+        RequestId::Num(self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+}
+
+// An in-process server driven by batch mode.
+struct BatchServer {
+    sender: Sender<String>,
+    receiver: Receiver<String>,
+    // How many times the server finished publishing diagnostics after a build
+    // (i.e., the build results have been handled and the analysis reloaded),
+    // which has not been waited for yet.
+    diagnostics_ends: usize,
+}
+
+impl BatchServer {
+    fn init() -> BatchServer {
+        let (sender, server_receiver) = channel();
+        let (server_sender, receiver) = channel();
+        start_server(
+            server_receiver,
+            BatchOutput {
+                sender: Arc::new(Mutex::new(server_sender)),
+                next_id: Arc::new(AtomicU64::new(1)),
+            },
+        );
+        send_initialize(&sender);
+
+        BatchServer {
+            sender,
+            receiver,
+            diagnostics_ends: 0,
+        }
+    }
+
+    fn send(&self, msg: &str) {
+        print_verb!("message: {:?}", msg);
+        self.sender.send(msg.to_owned()).expect("Error sending on channel");
+    }
+
+    // Sends the message, returns the response for requests, `null` for
+    // notifications.
+    fn run(&mut self, msg: Message) -> Result<Value, Value> {
+        self.send(&msg.text);
+        if msg.builds {
+            self.wait_for_diagnostics(Some(BATCH_TIMEOUT));
+        }
+        let id: Value = match msg.id {
+            Some(RequestId::Num(n)) => n.into(),
+            Some(RequestId::Str(s)) => s.into(),
+            None => return Ok(Value::Null),
+        };
+
+        let deadline = Instant::now() + BATCH_TIMEOUT;
+        loop {
+            let response = match self.receive(deadline) {
+                Some(response) => response,
+                None => return Err(Value::String("Timed out waiting for a response".to_owned())),
+            };
+            if response.get("method").is_none() && response.get("id") == Some(&id) {
+                return match response.get("error") {
+                    Some(error) => Err(error.clone()),
+                    None => Ok(response.get("result").cloned().unwrap_or_default()),
+                };
+            }
+        }
+    }
+
+    // Waits until the server has finished publishing the diagnostics of a
+    // build. The server no longer sends `rustDocument/diagnosticsEnd`
+    // (`lsp_data::DiagnosticsEnd`) for this; instead it sends a done
+    // `Indexing` progress notification (see
+    // `BuildDiagnosticsNotifier::notify_end_diagnostics`), so that's what we
+    // wait for.
+    fn wait_for_diagnostics(&mut self, timeout: Option<Duration>) {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        while self.diagnostics_ends == 0 {
+            let received = match deadline {
+                Some(deadline) => self.receive(deadline),
+                None => self.receive_any(),
+            };
+            if received.is_none() {
+                break;
+            }
+        }
+        self.diagnostics_ends = self.diagnostics_ends.saturating_sub(1);
+    }
+
+    fn receive(&mut self, deadline: Instant) -> Option<Value> {
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        match self.receiver.recv_timeout(deadline - now) {
+            Ok(msg) => Some(self.handle(&msg)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    fn receive_any(&mut self) -> Option<Value> {
+        let msg = self.receiver.recv().ok()?;
+        Some(self.handle(&msg))
+    }
+
+    fn handle(&mut self, msg: &str) -> Value {
+        print_verb!("received: {}", msg);
+        let msg: Value = serde_json::from_str(msg).unwrap_or_default();
+        let is_diagnostics_end = msg["method"] == NOTIFICATION__Progress
+            && msg["params"]["title"] == "Indexing"
+            && msg["params"]["done"] == true;
+        if is_diagnostics_end {
+            self.diagnostics_ends += 1;
+        }
+        msg
+    }
 }
 
 // Display help message.
@@ -541,7 +805,38 @@ Supported commands:
                   textDocument/codeAction
 
     resolve       label detail
-                  completionItem/resolve"
+                  completionItem/resolve
+
+    refs          file_name line_number column_number
+                  textDocument/references
+
+    impls         file_name line_number column_number
+                  textDocument/implementation
+
+    highlight     file_name line_number column_number
+                  textDocument/documentHighlight
+
+    complete      file_name line_number column_number
+                  textDocument/completion
+
+    lens          file_name
+                  textDocument/codeLens
+
+    type_def      file_name line_number column_number
+                  textDocument/typeDefinition
+
+    open          file_name [contents_file_name]
+                  textDocument/didOpen
+                  contents default to those of file_name on disk
+
+    change        file_name contents_file_name
+                  textDocument/didChange
+                  replaces the contents of file_name (in the RLS only) with
+                  those of contents_file_name
+
+Batch mode (`rls --cli --batch FILE`, or `-` for stdin) runs the commands in
+FILE, one per line, after the project has been built and indexed. `change`
+waits for the resulting build. Each result is printed as a line of JSON."
     );
 }
 
@@ -550,7 +845,7 @@ Supported commands:
 #[test]
 fn url_workaround_unc_canonicals() {
     let current_dir = ::std::env::current_dir().unwrap();
-    let url = url(current_dir.to_str().unwrap());
+    let url = url(current_dir.to_str().unwrap()).unwrap();
 
     let url_str = format!("{}", url);
     assert!(
@@ -559,3 +854,29 @@ fn url_workaround_unc_canonicals() {
         url
     );
 }
+
+#[test]
+fn parse_commands() {
+    let cwd = ::std::env::current_dir().unwrap();
+    let file = cwd.join("Cargo.toml");
+    let file = file.to_str().unwrap();
+
+    assert!(parse_command("  ").unwrap().is_none());
+
+    let msg = parse_command(&format!("refs {} 1 2", file)).unwrap().unwrap();
+    assert!(msg.id.is_some());
+    let json: Value = serde_json::from_str(&msg.text).unwrap();
+    assert_eq!(json["method"], "textDocument/references");
+    assert_eq!(json["params"]["position"]["character"], 2);
+    assert_eq!(json["params"]["context"]["includeDeclaration"], true);
+
+    let msg = parse_command(&format!("change {0} {0}", file)).unwrap().unwrap();
+    assert!(msg.id.is_none());
+    assert!(msg.builds);
+    let json: Value = serde_json::from_str(&msg.text).unwrap();
+    assert_eq!(json["method"], "textDocument/didChange");
+
+    assert!(parse_command(&format!("hover {} one 2", file)).is_err());
+    assert!(parse_command(&format!("lens {}", cwd.join("missing.rs").display())).is_err());
+    assert!(parse_command("frobnicate").is_err());
+}
@@ -1,8 +1,14 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 
 use crossbeam_channel::select;
 use crossbeam_channel::{bounded, Receiver, Sender};
 
+use crate::server::RequestId;
+use log::trace;
+
 /// `ConcurrentJob` is a handle for some long-running computation
 /// off the main thread. It can be used, indirectly, to wait for
 /// the completion of the said computation.
@@ -95,3 +101,52 @@ fn is_closed(chan: &Receiver<Never>) -> bool {
         default => false,
     }
 }
+
+/// `CancellationToken` is shared between the main thread and the worker
+/// handling a request. The main thread signals it when the client cancels the
+/// request, long-running work can poll it to stop early.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A table of the requests which have been dispatched but not yet responded
+/// to, allowing them to be cancelled by the client.
+#[derive(Default)]
+pub struct InFlightRequests {
+    requests: HashMap<RequestId, CancellationToken>,
+}
+
+impl InFlightRequests {
+    /// Registers a request, returning the token signalled on its cancellation.
+    pub fn add(&mut self, id: RequestId) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.requests.insert(id, token.clone());
+        token
+    }
+
+    /// Cancels the given request, if it is still in flight.
+    pub fn cancel(&mut self, id: &RequestId) {
+        match self.requests.get(id) {
+            Some(token) => token.cancel(),
+            None => trace!("cannot cancel {}, no such request in flight", id),
+        }
+    }
+
+    /// Removes a request which has been responded to.
+    pub fn finish(&mut self, id: &RequestId) {
+        self.requests.remove(id);
+    }
+}
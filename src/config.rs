@@ -161,8 +161,19 @@ pub struct Config {
     /// EXPERIMENTAL (needs unstable features)
     /// If set, executes a given program responsible for rebuilding save-analysis
     /// to be loaded by the RLS. The program given should output a list of
-    /// resulting .json files on stdout.
+    /// resulting .json files on stdout. It can also output rustc JSON diagnostic
+    /// messages, or `diagnostics:<path>` lines pointing to files containing them.
     pub build_command: Option<String>,
+    /// EXPERIMENTAL (needs unstable features)
+    /// If set, builds the project by running the rustc invocations of a given
+    /// build plan (in the format of `cargo build --build-plan`) in-process,
+    /// rather than running Cargo. Other invocations, e.g. running build scripts,
+    /// are expected to have been run by the build system producing the plan.
+    pub build_plan_path: Option<PathBuf>,
+    /// EXPERIMENTAL (needs unstable features)
+    /// Like `build_plan_path`, but executes a given program which should output
+    /// the build plan on stdout.
+    pub build_plan_command: Option<String>,
 }
 
 impl Default for Config {
@@ -193,6 +204,8 @@ impl Default for Config {
             show_hover_context: true,
             rustfmt_path: None,
             build_command: None,
+            build_plan_path: None,
+            build_plan_command: None,
         };
         result.normalise();
         result
@@ -231,6 +244,8 @@ impl Config {
             self.cfg_test = false;
             self.rustfmt_path = None;
             self.build_command = None;
+            self.build_plan_path = None;
+            self.build_plan_command = None;
         }
     }
 
@@ -266,6 +266,12 @@ pub struct InitializationOptions {
     /// Should the build not be triggered immediately after receiving `initialize`
     pub omit_init_build: bool,
     pub cmd_run: bool,
+    /// Whether the client handles the `rls.debug` command, run by the "Debug"
+    /// code lenses.
+    pub cmd_debug: bool,
+    /// Whether the client handles the `rls.showReferences` command, run by
+    /// the reference and implementation code lenses.
+    pub cmd_show_references: bool,
 }
 
 impl Default for InitializationOptions {
@@ -273,6 +279,8 @@ impl Default for InitializationOptions {
         InitializationOptions {
             omit_init_build: false,
             cmd_run: false,
+            cmd_debug: false,
+            cmd_show_references: false,
         }
     }
 }
@@ -284,6 +292,14 @@ impl Default for InitializationOptions {
 pub struct ClientCapabilities {
     pub code_completion_has_snippet_support: bool,
     pub related_information_support: bool,
+    pub hierarchical_document_symbol_support: bool,
+    /// Whether workspace edits can rename files, as well as edit them.
+    pub rename_file_support: bool,
+    /// Whether the client sends `textDocument/prepareRename` before renaming.
+    pub rename_prepare_support: bool,
+    /// Whether code actions can be returned as `CodeAction`s, rather than only
+    /// as `Command`s.
+    pub code_action_literal_support: bool,
 }
 
 impl ClientCapabilities {
@@ -312,9 +328,51 @@ impl ClientCapabilities {
             .unwrap_or(&false)
             .to_owned();
 
+        let hierarchical_document_symbol_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.document_symbol.as_ref())
+            .and_then(|symbol| symbol.hierarchical_document_symbol_support.as_ref())
+            .unwrap_or(&false)
+            .to_owned();
+
+        let rename_file_support = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .map_or(false, |edit| {
+                edit.document_changes == Some(true)
+                    && edit
+                        .resource_operations
+                        .as_ref()
+                        .map_or(false, |ops| ops.contains(&ResourceOperationKind::Rename))
+            });
+
+        let rename_prepare_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.rename.as_ref())
+            .and_then(|rename| rename.prepare_support.as_ref())
+            .unwrap_or(&false)
+            .to_owned();
+
+        let code_action_literal_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.code_action.as_ref())
+            .map_or(false, |action| action.code_action_literal_support.is_some());
+
         ClientCapabilities {
             code_completion_has_snippet_support,
             related_information_support,
+            hierarchical_document_symbol_support,
+            rename_file_support,
+            rename_prepare_support,
+            code_action_literal_support,
         }
     }
 }
@@ -353,6 +411,20 @@ impl LSPNotification for BeginBuild {
     const METHOD: &'static str = "rustDocument/beginBuild";
 }
 
+/* ----------  Temporary LSP type until languageserver-types supports it --------- */
+
+/// Request from client to server, checking that the symbol at a position can
+/// be renamed before asking the user for a new name. Responds with the range
+/// of the identifier to rename.
+#[derive(Debug)]
+pub enum PrepareRename {}
+
+impl LSPRequest for PrepareRename {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Range>;
+    const METHOD: &'static str = "textDocument/prepareRename";
+}
+
 /* ----------  Temporary LSP type until window/progress proposal is done --------- */
 
 // Notification from server to client for build progress.
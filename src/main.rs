@@ -49,6 +49,7 @@ use rls_vfs::Vfs;
 
 pub mod actions;
 pub mod build;
+pub mod check;
 pub mod cmd;
 pub mod concurrency;
 pub mod config;
@@ -81,7 +82,8 @@ fn main_inner() -> i32 {
         return 0;
     }
 
-    if let Some(first_arg) = ::std::env::args().nth(1) {
+    let args: Vec<String> = ::std::env::args().collect();
+    if let Some(first_arg) = args.get(1) {
         return match first_arg.as_str() {
             "--version" | "-V" => {
                 println!("{}", version().replace("rls", "rls-preview"));
@@ -91,10 +93,51 @@ fn main_inner() -> i32 {
                 println!("{}", help());
                 0
             }
+            "--cli" if args.get(2).map(String::as_str) == Some("--connect") => {
+                match address_arg(&args[2], args.get(3)) {
+                    Ok(address) => cmd::run_connected(&address),
+                    Err(code) => code,
+                }
+            }
+            "--cli" if args.get(2).map(String::as_str) == Some("--batch") => match args.get(3) {
+                Some(path) => cmd::run_batch(path),
+                None => {
+                    println!("Expected a file after '--batch'. Supported arguments:\n{}", help());
+                    101
+                }
+            },
             "--cli" => {
                 cmd::run();
                 0
             }
+            "--listen" => match address_arg(first_arg, args.get(2)) {
+                Ok(ref address)
+                    if !address.is_local()
+                        && args.get(3).map(String::as_str) != Some("--allow-remote") =>
+                {
+                    println!(
+                        "Refusing to listen on {}, which other machines can connect to; \
+                         pass --allow-remote to do so anyway",
+                        address
+                    );
+                    101
+                }
+                Ok(address) => {
+                    let analysis = Arc::new(AnalysisHost::new(Target::Debug));
+                    server::run_server_on(analysis, &address)
+                }
+                Err(code) => code,
+            },
+            "check" => check::run(&args[2..]),
+            "lsif" => actions::lsif::run(&args[2..]),
+            "tags" => actions::tags::run(&args[2..]),
+            "--replay" => match args.get(2) {
+                Some(path) => server::replay(std::path::Path::new(path)),
+                None => {
+                    println!("Expected a file after '--replay'. Supported arguments:\n{}", help());
+                    101
+                }
+            },
             unknown => {
                 println!(
                     "Unknown argument '{}'. Supported arguments:\n{}",
@@ -117,11 +160,45 @@ fn version() -> String {
     version.to_string()
 }
 
+// Parses the address given after `flag`, printing an error if it's missing or invalid.
+fn address_arg(flag: &str, arg: Option<&String>) -> Result<server::Address, i32> {
+    let arg = match arg {
+        Some(arg) => arg,
+        None => {
+            println!("Expected an address after '{}'. Supported arguments:\n{}", flag, help());
+            return Err(101);
+        }
+    };
+    arg.parse().map_err(|e| {
+        println!("{}", e);
+        101
+    })
+}
+
 fn help() -> &'static str {
     r#"
     --version or -V to print the version and commit info
     --help or -h for this message
     --cli starts the RLS in command line mode
+    --cli --batch FILE runs the command line mode commands in FILE (or stdin,
+        if FILE is -) and prints the result of each as JSON
+    --cli --connect ADDRESS starts the command line mode, talking to a running RLS
+    --listen ADDRESS [--allow-remote] starts the RLS as a language server,
+        accepting clients on ADDRESS rather than using stdio. Clients aren't
+        authenticated, so ADDRESS must be local unless --allow-remote is given
+    check [PROJECT_DIR] [OPTIONS] builds the project once and reports the
+        diagnostics as JSON, SARIF or JUnit XML (see `rls check --help`)
+    lsif [PROJECT_DIR] [--output FILE] builds the project and writes its index
+        in the Language Server Index Format
+    tags [PROJECT_DIR] [OPTIONS] builds the project and writes a ctags (or etags)
+        file for its definitions (see `rls tags --help`)
+    --replay FILE replays a session recorded with RLS_RECORD=FILE and reports
+        how the responses differ from the recorded ones
     No input starts the RLS as a language server
+
+    Setting RLS_RECORD=FILE records the session to FILE (to FILE.N for the Nth
+    client when using --listen)
+
+    ADDRESS is one of tcp:PORT (localhost only), tcp:HOST:PORT or unix:PATH
     "#
 }
@@ -14,7 +14,7 @@ use log::warn;
 use racer;
 use rls_vfs::{FileContents, Vfs};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -23,6 +23,8 @@ use std::{
 pub struct ProjectModel {
     manifest_to_id: HashMap<PathBuf, Package>,
     packages: Vec<PackageData>,
+    /// Where Cargo puts the workspace's build artifacts.
+    target_dir: PathBuf,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -30,11 +32,38 @@ pub struct Package(usize);
 
 #[derive(Debug)]
 struct PackageData {
+    name: String,
+    /// The directory containing the package's manifest.
+    root: PathBuf,
+    /// Whether the package is a member of the workspace, rather than a
+    /// dependency.
+    member: bool,
     lib: Option<(PathBuf, String)>,
+    targets: Vec<Target>,
     deps: Vec<Dep>,
     edition: racer::Edition,
 }
 
+/// A library, binary, test, etc. of a package.
+#[derive(Debug)]
+pub struct Target {
+    pub name: String,
+    pub kind: TargetKind,
+    /// The root source file of the target's crate.
+    pub src_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetKind {
+    Lib,
+    Bin,
+    Test,
+    Bench,
+    Example,
+    /// Build scripts.
+    Other,
+}
+
 #[derive(Debug)]
 pub struct Dep {
     pub crate_name: String,
@@ -68,6 +97,7 @@ impl ProjectModel {
             let ids: Vec<PackageId> = resolve.iter().cloned().collect();
             registry.get(&ids)?
         };
+        let members: HashSet<&PackageId> = ws.members().map(|pkg| pkg.package_id()).collect();
         let mut pkg_id_to_pkg = HashMap::new();
         let mut manifest_to_id = HashMap::new();
         let mut packages = Vec::new();
@@ -77,12 +107,32 @@ impl ProjectModel {
             let cargo_pkg = cargo_packages.get_one(pkg_id)?;
             let manifest = cargo_pkg.manifest_path().to_owned();
             packages.push(PackageData {
+                name: cargo_pkg.name().to_string(),
+                root: cargo_pkg.root().to_owned(),
+                member: members.contains(pkg_id),
                 lib: cargo_pkg
                     .targets()
                     .iter()
                     .find(|t| t.is_lib())
                     // racer expect name 'underscored'(crate) name
                     .map(|t| (t.src_path().path().to_owned(), t.name().replace('-', "_"))),
+                targets: cargo_pkg
+                    .targets()
+                    .iter()
+                    .map(|t| Target {
+                        name: t.name().to_owned(),
+                        kind: match *t.kind() {
+                            cargo::core::TargetKind::Lib(_) => TargetKind::Lib,
+                            cargo::core::TargetKind::Bin => TargetKind::Bin,
+                            cargo::core::TargetKind::Test => TargetKind::Test,
+                            cargo::core::TargetKind::Bench => TargetKind::Bench,
+                            cargo::core::TargetKind::ExampleLib(_)
+                            | cargo::core::TargetKind::ExampleBin => TargetKind::Example,
+                            cargo::core::TargetKind::CustomBuild => TargetKind::Other,
+                        },
+                        src_path: t.src_path().path().to_owned(),
+                    })
+                    .collect(),
                 deps: Vec::new(),
                 edition: match cargo_pkg.manifest().edition() {
                     cargo::core::Edition::Edition2015 => racer::Edition::Ed2015,
@@ -107,6 +157,7 @@ impl ProjectModel {
         Ok(ProjectModel {
             manifest_to_id,
             packages,
+            target_dir: ws.target_dir().into_path_unlocked(),
         })
     }
 
@@ -114,6 +165,31 @@ impl ProjectModel {
         self.manifest_to_id.get(manifest_path).cloned()
     }
 
+    /// The directory Cargo builds the workspace in, unless told otherwise
+    /// (e.g., with `--target-dir`).
+    pub fn target_dir(&self) -> &Path {
+        &self.target_dir
+    }
+
+    /// Returns the package whose sources contain `file`, i.e. the one with the
+    /// innermost root containing it.
+    pub fn package_for_file(&self, file: &Path) -> Option<Package> {
+        (0..self.packages.len())
+            .filter(|&i| file.starts_with(&self.packages[i].root))
+            .max_by_key(|&i| self.packages[i].root.components().count())
+            .map(Package)
+    }
+
+    /// Returns the target whose crate root is `src_path`, and its package.
+    pub fn target_for_root(&self, src_path: &Path) -> Option<(Package, &Target)> {
+        self.packages.iter().enumerate().find_map(|(i, pkg)| {
+            pkg.targets
+                .iter()
+                .find(|target| target.src_path == src_path)
+                .map(|target| (Package(i), target))
+        })
+    }
+
     fn get(&self, pkg: Package) -> &PackageData {
         &self.packages[pkg.0]
     }
@@ -124,12 +200,18 @@ impl ProjectModel {
 }
 
 impl Package {
+    pub fn name(self, project: &ProjectModel) -> &str {
+        &project.get(self).name
+    }
     pub fn deps(self, project: &ProjectModel) -> &[Dep] {
         &project.get(self).deps
     }
     pub fn lib_root(self, project: &ProjectModel) -> Option<&Path> {
         project.get(self).lib.as_ref().map(|p| p.0.as_path())
     }
+    pub fn is_workspace_member(self, project: &ProjectModel) -> bool {
+        project.get(self).member
+    }
 }
 
 // We use the following wrappers to teach Racer about the structure
@@ -17,12 +17,14 @@ use crate::lsp_data::LSPRequest;
 use crate::server;
 use crate::server::io::Output;
 use crate::server::message::ResponseError;
-use crate::server::{Request, Response};
+use crate::server::{Request, RequestId, Response};
 use jsonrpc_core::types::ErrorCode;
 use log::debug;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use url::Url;
 
 /// Timeout time for request responses. By default a LSP client request not
 /// responded to after this duration will return a fallback response.
@@ -33,6 +35,13 @@ pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_millis(1500);
 #[cfg(test)]
 pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_millis(3_600_000);
 
+/// Error code for a request cancelled by the client, as defined by the LSP spec.
+pub const REQUEST_CANCELLED_CODE: ErrorCode = ErrorCode::ServerError(-32800);
+
+/// How often a request waiting on the work pool checks whether it has been
+/// cancelled.
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 /// Macro enum `DispatchRequest` packing in various similar `Request` types
 macro_rules! define_dispatch_request_enum {
     ($($request_type:ident),*$(,)*) => {
@@ -52,33 +61,85 @@ macro_rules! define_dispatch_request_enum {
         )*
 
         impl DispatchRequest {
+            fn id(&self) -> &RequestId {
+                match self {
+                $(
+                    DispatchRequest::$request_type(req) => &req.id,
+                )*
+                }
+            }
+
+            /// Returns the path of the text document this request refers to, if any.
+            fn document(&self) -> Option<PathBuf> {
+                let uri = match self {
+                $(
+                    DispatchRequest::$request_type(req) => $request_type::document(&req.params),
+                )*
+                };
+                uri?.to_file_path().ok()
+            }
+
             fn handle<O: Output>(self, ctx: InitActionContext, out: &O) {
                 match self {
                 $(
                     DispatchRequest::$request_type(req) => {
                         let Request { id, params, received, .. } = req;
                         let timeout = $request_type::timeout();
+                        let cancel_token = ctx.cancel_token();
+
+                        // drop requests which were cancelled while queued, without
+                        // taking up a slot on the work pool
+                        if cancel_token.is_cancelled() {
+                            debug!("{} request {} cancelled before starting", $request_type::METHOD, id);
+                            ctx.finish_request(&id);
+                            out.failure_message(id, REQUEST_CANCELLED_CODE, "Request cancelled");
+                            return;
+                        }
 
+                        let work_ctx = ctx.clone();
+                        let work_token = cancel_token.clone();
                         let receiver = work_pool::receive_from_thread(move || {
                             // checking timeout here can prevent starting expensive work that has
                             // already timed out due to previous long running requests
                             // Note: done here on the threadpool as pool scheduling may incur
                             // a further delay
-                            if received.elapsed() >= timeout {
+                            if received.elapsed() >= timeout || work_token.is_cancelled() {
                                 $request_type::fallback_response()
                             }
                             else {
-                                $request_type::handle(ctx, params)
+                                $request_type::handle(work_ctx, params)
                             }
                         }, WorkDescription($request_type::METHOD));
 
-                        match receiver.recv_timeout(timeout)
-                            .unwrap_or_else(|_| $request_type::fallback_response()) {
-                            Ok(response) => response.send(id, out),
-                            Err(ResponseError::Empty) => {
+                        let started = Instant::now();
+                        let response = loop {
+                            if cancel_token.is_cancelled() {
+                                break None;
+                            }
+                            let elapsed = started.elapsed();
+                            if elapsed >= timeout {
+                                break Some($request_type::fallback_response());
+                            }
+                            match receiver.recv_timeout(CANCELLATION_POLL_INTERVAL.min(timeout - elapsed)) {
+                                Ok(response) => break Some(response),
+                                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                                    break Some($request_type::fallback_response())
+                                }
+                            }
+                        };
+                        ctx.finish_request(&id);
+
+                        match response {
+                            None => {
+                                debug!("{} request {} cancelled", $request_type::METHOD, id);
+                                out.failure_message(id, REQUEST_CANCELLED_CODE, "Request cancelled")
+                            }
+                            Some(Ok(response)) => response.send(id, out),
+                            Some(Err(ResponseError::Empty)) => {
                                 out.failure_message(id, ErrorCode::InternalError, "An unknown error occurred")
                             }
-                            Err(ResponseError::Message(code, msg)) => {
+                            Some(Err(ResponseError::Message(code, msg))) => {
                                 out.failure_message(id, code, msg)
                             }
                         }
@@ -93,10 +154,12 @@ macro_rules! define_dispatch_request_enum {
 define_dispatch_request_enum!(
     Completion,
     Definition,
+    TypeDefinition,
     References,
     WorkspaceSymbol,
     Symbols,
     Hover,
+    SignatureHelp,
     Implementation,
     DocumentHighlight,
     Rename,
@@ -104,8 +167,11 @@ define_dispatch_request_enum!(
     ResolveCompletion,
     Formatting,
     RangeFormatting,
+    FoldingRangeRequest,
     ExecuteCommand,
     CodeLensRequest,
+    PrepareRename,
+    CodeLensResolve,
 );
 
 /// Provides ability to dispatch requests to a worker thread that will
@@ -133,11 +199,18 @@ impl Dispatcher {
         Self { sender }
     }
 
-    /// Sends a request to the dispatch-worker thread, does not block
-    crate fn dispatch<R: Into<DispatchRequest>>(&mut self, request: R, ctx: InitActionContext) {
+    /// Sends a request to the dispatch-worker thread, does not block.
+    /// The request is tracked as in flight until responded to, so it can be
+    /// cancelled by a `$/cancelRequest` notification in the meantime.
+    crate fn dispatch<R: Into<DispatchRequest>>(&mut self, request: R, mut ctx: InitActionContext) {
+        let request = request.into();
+        ctx.add_request(request.id().clone());
+        // Route the request to the project owning its document
+        ctx.select_project(request.document().as_ref().map(PathBuf::as_path));
+
         let (job, token) = ConcurrentJob::new();
         ctx.add_job(job);
-        if let Err(err) = self.sender.send((request.into(), ctx, token)) {
+        if let Err(err) = self.sender.send((request, ctx, token)) {
             debug!("Failed to dispatch request: {:?}", err);
         }
     }
@@ -154,6 +227,13 @@ pub trait RequestAction: LSPRequest {
         DEFAULT_REQUEST_TIMEOUT
     }
 
+    /// Returns the URI of the document the request refers to, if any. The
+    /// request is handled by the project that document belongs to, or else by
+    /// the first one.
+    fn document(_params: &Self::Params) -> Option<Url> {
+        None
+    }
+
     /// Returns a response used in timeout scenarios
     fn fallback_response() -> Result<Self::Response, ResponseError>;
 
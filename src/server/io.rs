@@ -16,9 +16,9 @@ use super::{Notification, Request, RequestId};
 use crate::lsp_data::{LSPNotification, LSPRequest};
 
 use std::fmt;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use jsonrpc_core::{self as jsonrpc, response, version, Id};
 
@@ -45,11 +45,38 @@ impl MessageReader for StdioMsgReader {
     }
 }
 
+/// A message reader that gets messages from a byte stream, e.g., a socket.
+pub struct StreamMsgReader<R> {
+    input: Mutex<BufReader<R>>,
+}
+
+impl<R: Read> StreamMsgReader<R> {
+    /// Construct a new reader over the given stream.
+    pub fn new(input: R) -> StreamMsgReader<R> {
+        StreamMsgReader {
+            input: Mutex::new(BufReader::new(input)),
+        }
+    }
+}
+
+impl<R: Read> MessageReader for StreamMsgReader<R> {
+    fn read_message(&self) -> Option<String> {
+        let mut input = self.input.lock().unwrap();
+        match read_message(&mut *input) {
+            Ok(message) => Some(message),
+            Err(err) => {
+                debug!("{:?}", err);
+                None
+            }
+        }
+    }
+}
+
 // Reads the content of the next message from given input.
 //
 // The input is expected to provide a message as described by "Base Protocol" of Language Server
 // Protocol.
-fn read_message<R: BufRead>(input: &mut R) -> Result<String, io::Error> {
+crate fn read_message<R: BufRead>(input: &mut R) -> Result<String, io::Error> {
     // Read in the "Content-Length: xx" part
     let mut size: Option<usize> = None;
     loop {
@@ -116,6 +143,17 @@ fn read_message<R: BufRead>(input: &mut R) -> Result<String, io::Error> {
     String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
 
+// Writes the message to the given output, framed as described by "Base Protocol" of Language
+// Server Protocol.
+crate fn write_message<W: Write>(output: &mut W, message: &str) -> Result<(), io::Error> {
+    let o = format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+
+    trace!("response: {:?}", o);
+
+    output.write_all(o.as_bytes())?;
+    output.flush()
+}
+
 /// Anything that can send notifications and responses to a language server
 /// client.
 pub trait Output: Sync + Send + Clone + 'static {
@@ -204,14 +242,52 @@ impl StdioOutput {
 
 impl Output for StdioOutput {
     fn response(&self, output: String) {
-        let o = format!("Content-Length: {}\r\n\r\n{}", output.len(), output);
-
-        trace!("response: {:?}", o);
-
         let stdout = io::stdout();
         let mut stdout_lock = stdout.lock();
-        write!(stdout_lock, "{}", o).unwrap();
-        stdout_lock.flush().unwrap();
+        write_message(&mut stdout_lock, &output).unwrap();
+    }
+
+    fn provide_id(&self) -> RequestId {
+        RequestId::Num(self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+}
+
+/// An output that sends notifications and responses over a byte stream, e.g.,
+/// a socket.
+pub struct StreamOutput<W> {
+    output: Arc<Mutex<W>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl<W: Write> StreamOutput<W> {
+    /// Construct a new output writing to the given stream.
+    pub fn new(output: W) -> StreamOutput<W> {
+        StreamOutput {
+            output: Arc::new(Mutex::new(output)),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+// Implemented by hand, since deriving would needlessly require `W: Clone`.
+impl<W> Clone for StreamOutput<W> {
+    fn clone(&self) -> StreamOutput<W> {
+        StreamOutput {
+            output: self.output.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Output for StreamOutput<W> {
+    fn response(&self, output: String) {
+        let mut stream = self.output.lock().unwrap();
+        // Unlike stdout, the other end of a stream may go away while we are
+        // still busy (e.g., a build finishing after the client disconnected),
+        // so don't bring the whole server down when that happens.
+        if let Err(e) = write_message(&mut *stream, &output) {
+            debug!("Could not write response: {:?}", e);
+        }
     }
 
     fn provide_id(&self) -> RequestId {
@@ -316,4 +392,18 @@ mod tests {
 
         read_message(&mut input).expect_err("Reading should fail when input ends after header.");
     }
+
+    #[test]
+    fn stream_reader_reads_what_stream_output_wrote() {
+        let output = StreamOutput::new(Vec::new());
+        output.response("First".to_owned());
+        output.response("Second message".to_owned());
+
+        let written = output.output.lock().unwrap().clone();
+        let reader = StreamMsgReader::new(io::Cursor::new(written));
+
+        assert_eq!(reader.read_message(), Some("First".to_owned()));
+        assert_eq!(reader.read_message(), Some("Second message".to_owned()));
+        assert_eq!(reader.read_message(), None);
+    }
 }
@@ -18,13 +18,18 @@ use crate::lsp_data;
 use crate::lsp_data::{InitializationOptions, LSPNotification, LSPRequest};
 use crate::server::dispatch::Dispatcher;
 pub use crate::server::dispatch::{RequestAction, DEFAULT_REQUEST_TIMEOUT};
-pub use crate::server::io::{MessageReader, Output};
+pub use crate::server::io::{MessageReader, Output, StreamMsgReader, StreamOutput};
+pub(crate) use crate::server::io::{read_message, write_message};
 use crate::server::io::{StdioMsgReader, StdioOutput};
 use crate::server::message::RawMessage;
 pub use crate::server::message::{
     Ack, BlockingNotificationAction, BlockingRequestAction, NoResponse, Notification, Request,
     RequestId, Response, ResponseError, ResponseWithMessage,
 };
+use crate::server::record::{Direction, Recorder, RecordingOutput};
+pub use crate::server::record::replay;
+use crate::server::socket::Listener;
+pub use crate::server::socket::{Address, Stream};
 use crate::version;
 use jsonrpc_core::{self as jsonrpc, types::error::ErrorCode, Id};
 pub use languageserver_types::notification::Exit as ExitNotification;
@@ -32,10 +37,13 @@ pub use languageserver_types::request::Initialize as InitializeRequest;
 pub use languageserver_types::request::Shutdown as ShutdownRequest;
 use languageserver_types::{
     CodeActionProviderCapability, CodeLensOptions, CompletionOptions, ExecuteCommandOptions,
-    ImplementationProviderCapability, InitializeParams, InitializeResult, RenameProviderCapability,
-    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    FoldingRangeProviderCapability, ImplementationProviderCapability, InitializeParams,
+    InitializeResult, RenameOptions, RenameProviderCapability, ServerCapabilities,
+    SignatureHelpOptions, TextDocumentSyncCapability, TextDocumentSyncKind,
+    TypeDefinitionProviderCapability, WorkspaceCapability, WorkspaceFolderCapability,
+    WorkspaceFolderCapabilityChangeNotifications,
 };
-use log::{debug, error, trace, warn};
+use log::{debug, error, info, trace, warn};
 use rls_analysis::AnalysisHost;
 use rls_vfs::Vfs;
 use serde_json;
@@ -46,24 +54,86 @@ use std::sync::{Arc, Mutex};
 mod dispatch;
 mod io;
 mod message;
+mod record;
+mod socket;
 
 const NOT_INITIALIZED_CODE: ErrorCode = ErrorCode::ServerError(-32002);
 
 /// Run the Rust Language Server.
 pub fn run_server(analysis: Arc<AnalysisHost>, vfs: Arc<Vfs>) -> i32 {
     debug!("Language Server starting up. Version: {}", version());
-    let service = LsService::new(
+    let exit_code = run_service(
         analysis,
         vfs,
-        Arc::new(Mutex::new(Config::default())),
         Box::new(StdioMsgReader),
         StdioOutput::new(),
+        None,
     );
-    let exit_code = LsService::run(service);
     debug!("Server shutting down");
     exit_code
 }
 
+/// Run the Rust Language Server, serving clients which connect to `address`.
+///
+/// Clients are served one at a time and the server keeps running after a
+/// client exits, waiting for the next one. Analysis data is kept between
+/// clients, but every client gets a fresh VFS and configuration, as if it had
+/// started its own server. Only returns if the server can't listen on `address`.
+pub fn run_server_on(analysis: Arc<AnalysisHost>, address: &Address) -> i32 {
+    debug!("Language Server starting up. Version: {}", version());
+    let listener = match Listener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not listen on {}: {}", address, e);
+            return 101;
+        }
+    };
+    info!("Listening on {}", address);
+
+    let mut client = 0;
+    loop {
+        client += 1;
+        let (reader, writer) = match listener.accept().and_then(|s| Ok((s.try_clone()?, s))) {
+            Ok(streams) => streams,
+            Err(e) => {
+                warn!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+        debug!("Client {} connected", client);
+
+        let exit_code = run_service(
+            analysis.clone(),
+            Arc::new(Vfs::new()),
+            Box::new(StreamMsgReader::new(reader)),
+            StreamOutput::new(writer),
+            Some(client),
+        );
+        debug!("Client {} disconnected, exit code: {}", client, exit_code);
+    }
+}
+
+// Serves a single client until it exits, recording the session if requested
+// (see the `record` module).
+fn run_service<O: Output>(
+    analysis: Arc<AnalysisHost>,
+    vfs: Arc<Vfs>,
+    reader: Box<dyn MessageReader + Send + Sync>,
+    output: O,
+    client: Option<usize>,
+) -> i32 {
+    let config = Arc::new(Mutex::new(Config::default()));
+    match Recorder::from_env(client) {
+        Some(recorder) => {
+            let output = RecordingOutput::new(output, recorder.clone());
+            let mut service = LsService::new(analysis, vfs, config, reader, output);
+            service.recorder = Some(recorder);
+            LsService::run(service)
+        }
+        None => LsService::run(LsService::new(analysis, vfs, config, reader, output)),
+    }
+}
+
 impl BlockingRequestAction for ShutdownRequest {
     type Response = Ack;
 
@@ -111,16 +181,16 @@ impl BlockingRequestAction for InitializeRequest {
             ));
         }
 
+        let capabilities = lsp_data::ClientCapabilities::new(&params);
         let result = InitializeResult {
-            capabilities: server_caps(ctx),
+            capabilities: server_caps(ctx, &capabilities),
         };
 
         // send response early before `ctx.init` to enforce
         // initialize-response-before-all-other-messages constraint
         result.send(id, &out);
 
-        let capabilities = lsp_data::ClientCapabilities::new(&params);
-        ctx.init(get_root_path(&params), &init_options, capabilities, &out)
+        ctx.init(get_project_roots(&params), &init_options, capabilities, &out)
             .unwrap();
 
         Ok(NoResponse)
@@ -133,6 +203,7 @@ pub struct LsService<O: Output> {
     output: O,
     ctx: ActionContext,
     dispatcher: Dispatcher,
+    recorder: Option<Recorder>,
 }
 
 impl<O: Output> LsService<O> {
@@ -151,6 +222,7 @@ impl<O: Output> LsService<O> {
             output,
             ctx: ActionContext::new(analysis, vfs, config),
             dispatcher,
+            recorder: None,
         }
     }
 
@@ -259,6 +331,7 @@ impl<O: Output> LsService<O> {
                 notifications::DidSaveTextDocument,
                 notifications::DidChangeConfiguration,
                 notifications::DidChangeWatchedFiles,
+                notifications::DidChangeWorkspaceFolders,
                 notifications::Cancel;
             blocking_requests:
                 ShutdownRequest,
@@ -267,18 +340,23 @@ impl<O: Output> LsService<O> {
                 requests::ExecuteCommand,
                 requests::Formatting,
                 requests::RangeFormatting,
+                requests::FoldingRangeRequest,
                 requests::ResolveCompletion,
                 requests::Rename,
+                requests::PrepareRename,
                 requests::CodeAction,
                 requests::DocumentHighlight,
                 requests::Implementation,
                 requests::Symbols,
                 requests::Hover,
+                requests::SignatureHelp,
                 requests::WorkspaceSymbol,
                 requests::Definition,
+                requests::TypeDefinition,
                 requests::References,
                 requests::Completion,
-                requests::CodeLensRequest;
+                requests::CodeLensRequest,
+                requests::CodeLensResolve;
         );
         Ok(())
     }
@@ -297,6 +375,9 @@ impl<O: Output> LsService<O> {
         };
 
         trace!("Read message `{}`", msg_string);
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(Direction::In, &msg_string);
+        }
 
         let raw_message = match RawMessage::try_parse(&msg_string) {
             Ok(Some(rm)) => rm,
@@ -355,7 +436,10 @@ pub enum ServerStateChange {
     Break { exit_code: i32 },
 }
 
-fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
+fn server_caps(
+    ctx: &ActionContext,
+    client: &lsp_data::ClientCapabilities,
+) -> ServerCapabilities {
     ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Kind(
             TextDocumentSyncKind::Incremental,
@@ -366,7 +450,7 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
             trigger_characters: Some(vec![".".to_string(), ":".to_string()]),
         }),
         definition_provider: Some(true),
-        type_definition_provider: None,
+        type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
         implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
         references_provider: Some(true),
         document_highlight_provider: Some(true),
@@ -383,7 +467,13 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
                 format!("rls.deglobImports-{}", ctx.pid()),
             ],
         }),
-        rename_provider: Some(RenameProviderCapability::Simple(true)),
+        // Clients which don't send `textDocument/prepareRename` may not expect
+        // anything but a boolean here.
+        rename_provider: Some(if client.rename_prepare_support {
+            RenameProviderCapability::Options(RenameOptions { prepare_provider: Some(true) })
+        } else {
+            RenameProviderCapability::Simple(true)
+        }),
         color_provider: None,
 
         // These are supported if the `unstable_features` option is set.
@@ -392,13 +482,37 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
         document_range_formatting_provider: Some(false),
 
         code_lens_provider: Some(CodeLensOptions {
-            resolve_provider: Some(false),
+            resolve_provider: Some(true),
         }),
         document_on_type_formatting_provider: None,
-        signature_help_provider: None,
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+        }),
+
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        workspace: Some(WorkspaceCapability {
+            workspace_folders: Some(WorkspaceFolderCapability {
+                supported: Some(true),
+                change_notifications: Some(WorkspaceFolderCapabilityChangeNotifications::Bool(true)),
+            }),
+        }),
+    }
+}
 
-        folding_range_provider: None,
-        workspace: None,
+/// Returns the roots of the projects to open, one per workspace folder, or
+/// just the root path if the client doesn't support workspace folders.
+fn get_project_roots(params: &InitializeParams) -> Vec<PathBuf> {
+    let folders: Vec<_> = params
+        .workspace_folders
+        .iter()
+        .flatten()
+        .filter_map(|folder| folder.uri.to_file_path().ok())
+        .collect();
+
+    if folders.is_empty() {
+        vec![get_root_path(params)]
+    } else {
+        folders
     }
 }
 
@@ -471,6 +585,54 @@ mod test {
         assert_eq!(get_root_path(&params), root_path);
     }
 
+    #[test]
+    fn test_use_workspace_folders() {
+        let mut params = get_default_params();
+
+        let root_path = make_platform_path("path/a");
+        params.root_uri = Some(Url::from_directory_path(&root_path).unwrap());
+        assert_eq!(get_project_roots(&params), vec![root_path.clone()]);
+
+        let folder_a = make_platform_path("path/a");
+        let folder_b = make_platform_path("path/b");
+        params.workspace_folders = Some(
+            [&folder_a, &folder_b]
+                .iter()
+                .map(|path| languageserver_types::WorkspaceFolder {
+                    uri: Url::from_directory_path(path).unwrap(),
+                    name: path.file_name().unwrap().to_str().unwrap().to_owned(),
+                })
+                .collect(),
+        );
+        assert_eq!(get_project_roots(&params), vec![folder_a, folder_b]);
+    }
+
+    #[test]
+    fn test_rename_provider_prepare_support() {
+        let rename_provider = |text_document| {
+            let mut params = get_default_params();
+            params.capabilities.text_document =
+                Some(serde_json::from_value(text_document).unwrap());
+            let ctx = ActionContext::new(
+                Arc::new(AnalysisHost::new(rls_analysis::Target::Debug)),
+                Arc::new(Vfs::new()),
+                Arc::new(Mutex::new(Config::default())),
+            );
+            let capabilities = lsp_data::ClientCapabilities::new(&params);
+            serde_json::to_value(server_caps(&ctx, &capabilities).rename_provider).unwrap()
+        };
+
+        assert_eq!(rename_provider(serde_json::json!({})), serde_json::json!(true));
+        assert_eq!(
+            rename_provider(serde_json::json!({ "rename": { "prepareSupport": false } })),
+            serde_json::json!(true)
+        );
+        assert_eq!(
+            rename_provider(serde_json::json!({ "rename": { "prepareSupport": true } })),
+            serde_json::json!({ "prepareProvider": true })
+        );
+    }
+
     /// Some clients send empty object params for void params requests (see #1038)
     #[test]
     fn parse_shutdown_object_params() {
@@ -0,0 +1,446 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recording of LSP sessions, and replaying them against the current RLS.
+//!
+//! If the `RLS_RECORD` environment variable is set, every message the server
+//! reads or writes is appended, with a timestamp, to the JSONL file it names.
+//! `rls --replay FILE` then feeds the recorded client messages to a fresh
+//! server and reports how its responses differ from the recorded ones.
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{debug, error};
+use rls_analysis::{AnalysisHost, Target};
+use rls_vfs::Vfs;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use super::{LsService, MessageReader, Output, RequestId};
+use crate::config::Config;
+
+/// Name of the environment variable holding the file to record sessions to.
+crate const RECORD_ENV_VAR_NAME: &str = "RLS_RECORD";
+
+/// How long a replay waits for the server to produce the responses it produced
+/// when recording, before moving on without them.
+const REPLAY_QUIET_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+crate enum Direction {
+    /// Sent by the client to the server.
+    In,
+    /// Sent by the server to the client.
+    Out,
+}
+
+/// A single line of a recording.
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedMessage {
+    /// Milliseconds since the Unix epoch.
+    time: u64,
+    direction: Direction,
+    message: String,
+}
+
+/// Appends messages to a recording.
+#[derive(Clone)]
+crate struct Recorder {
+    file: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+    /// Create a recorder if `RLS_RECORD` is set. When serving several clients,
+    /// `client` tells them apart, so that each gets its own recording.
+    crate fn from_env(client: Option<usize>) -> Option<Recorder> {
+        let mut path = PathBuf::from(env::var_os(RECORD_ENV_VAR_NAME)?);
+        if let Some(client) = client {
+            let mut file_name = path.file_name()?.to_owned();
+            file_name.push(format!(".{}", client));
+            path.set_file_name(file_name);
+        }
+
+        match Recorder::create(&path) {
+            Ok(recorder) => {
+                debug!("Recording session to {}", path.display());
+                Some(recorder)
+            }
+            Err(e) => {
+                error!("Could not record session to {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn create(path: &Path) -> io::Result<Recorder> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Recorder {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    crate fn record(&self, direction: Direction, message: &str) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()))
+            .unwrap_or(0);
+        let line = serde_json::to_string(&RecordedMessage {
+            time,
+            direction,
+            message: message.to_owned(),
+        }).unwrap();
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            debug!("Could not record message: {}", e);
+        }
+    }
+}
+
+/// An output which records everything it sends before passing it on.
+#[derive(Clone)]
+crate struct RecordingOutput<O> {
+    output: O,
+    recorder: Recorder,
+}
+
+impl<O: Output> RecordingOutput<O> {
+    crate fn new(output: O, recorder: Recorder) -> RecordingOutput<O> {
+        RecordingOutput { output, recorder }
+    }
+}
+
+impl<O: Output> Output for RecordingOutput<O> {
+    fn response(&self, output: String) {
+        self.recorder.record(Direction::Out, &output);
+        self.output.response(output);
+    }
+
+    fn provide_id(&self) -> RequestId {
+        self.output.provide_id()
+    }
+}
+
+fn read_recording(path: &Path) -> Result<Vec<RecordedMessage>, String> {
+    let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    let mut messages = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: invalid recorded message: {}", path.display(), i + 1, e))?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+/// Responses produced by the replayed server so far.
+#[derive(Default)]
+struct Responses {
+    messages: Mutex<Vec<String>>,
+    changed: Condvar,
+    /// Set once the replay is over, anything sent afterwards is ignored.
+    finished: AtomicBool,
+}
+
+impl Responses {
+    /// Block until there are at least `count` responses, or until the server
+    /// has been quiet for too long.
+    fn wait_for(&self, count: usize) {
+        let mut messages = self.messages.lock().unwrap();
+        let mut last_change = Instant::now();
+        while messages.len() < count {
+            let seen = messages.len();
+            let timeout = match REPLAY_QUIET_TIMEOUT.checked_sub(last_change.elapsed()) {
+                Some(timeout) => timeout,
+                None => {
+                    debug!("Gave up waiting for {} responses, got {}", count, seen);
+                    return;
+                }
+            };
+            messages = self.changed.wait_timeout(messages, timeout).unwrap().0;
+            if messages.len() > seen {
+                last_change = Instant::now();
+            }
+        }
+    }
+
+    fn finish(&self) -> Vec<String> {
+        self.finished.store(true, Ordering::SeqCst);
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[derive(Clone)]
+struct ReplayOutput {
+    responses: Arc<Responses>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Output for ReplayOutput {
+    fn response(&self, output: String) {
+        if self.responses.finished.load(Ordering::SeqCst) {
+            return;
+        }
+        self.responses.messages.lock().unwrap().push(output);
+        self.responses.changed.notify_all();
+    }
+
+    fn provide_id(&self) -> RequestId {
+        RequestId::Num(self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+}
+
+/// Feeds recorded client messages to the server. Before each message, waits
+/// until the server has sent as many messages as it had at that point of the
+/// recording, so that, e.g., requests are not sent before a build finishes.
+struct ReplayMsgReader {
+    /// Recorded client messages, with the number of server messages sent before each.
+    messages: Mutex<VecDeque<(usize, String)>>,
+    total_responses: usize,
+    responses: Arc<Responses>,
+}
+
+impl MessageReader for ReplayMsgReader {
+    fn read_message(&self) -> Option<String> {
+        let next = self.messages.lock().unwrap().pop_front();
+        match next {
+            Some((responses_before, message)) => {
+                self.responses.wait_for(responses_before);
+                Some(message)
+            }
+            None => {
+                // The recording ended without an `exit`, stop once the server
+                // has caught up.
+                self.responses.wait_for(self.total_responses);
+                self.responses.finish();
+                None
+            }
+        }
+    }
+}
+
+/// Replay the session recorded in `path`, printing the differences between the
+/// recorded and the replayed responses. Returns 0 if there were none.
+pub fn replay(path: &Path) -> i32 {
+    let recording = match read_recording(path) {
+        Ok(recording) => recording,
+        Err(e) => {
+            println!("{}", e);
+            return 101;
+        }
+    };
+
+    let mut inbound = VecDeque::new();
+    let mut recorded = vec![];
+    for msg in recording {
+        match msg.direction {
+            Direction::In => inbound.push_back((recorded.len(), msg.message)),
+            Direction::Out => recorded.push(msg.message),
+        }
+    }
+    let inbound_count = inbound.len();
+
+    let responses = Arc::new(Responses::default());
+    let reader = ReplayMsgReader {
+        messages: Mutex::new(inbound),
+        total_responses: recorded.len(),
+        responses: responses.clone(),
+    };
+    let output = ReplayOutput {
+        responses: responses.clone(),
+        next_id: Arc::new(AtomicU64::new(1)),
+    };
+    let service = LsService::new(
+        Arc::new(AnalysisHost::new(Target::Debug)),
+        Arc::new(Vfs::new()),
+        Arc::new(Mutex::new(Config::default())),
+        Box::new(reader),
+        output,
+    );
+    LsService::run(service);
+    let replayed = responses.finish();
+
+    println!(
+        "Replayed {} messages from {}, recorded {} responses, got {}",
+        inbound_count,
+        path.display(),
+        recorded.len(),
+        replayed.len()
+    );
+    let differences = diff(&recorded, &replayed);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if differences.is_empty() {
+        println!("No differences");
+        0
+    } else {
+        println!("{} differences", differences.len());
+        1
+    }
+}
+
+/// A message sent by the server, in a form where unimportant differences (such
+/// as the ids of requests made by the server) are ignored.
+#[derive(Debug, PartialEq)]
+enum ServerMessage {
+    /// A response to the client request with the given id.
+    Response(String, Value),
+    /// A notification, or a request made by the server.
+    Notification(String, Value),
+    Invalid(String),
+}
+
+impl ServerMessage {
+    fn parse(message: &str) -> ServerMessage {
+        let mut json: Value = match serde_json::from_str(message) {
+            Ok(json) => json,
+            Err(_) => return ServerMessage::Invalid(message.to_owned()),
+        };
+        match json.get("method").and_then(Value::as_str).map(str::to_owned) {
+            Some(method) => {
+                ServerMessage::Notification(method, json.get("params").cloned().unwrap_or(Value::Null))
+            }
+            None => {
+                let id = json.get("id").map(Value::to_string).unwrap_or_default();
+                if let Some(obj) = json.as_object_mut() {
+                    obj.remove("id");
+                }
+                ServerMessage::Response(id, json)
+            }
+        }
+    }
+}
+
+/// Describe how the `replayed` server messages differ from the `recorded` ones.
+/// Responses are matched up by request id, other messages are compared
+/// regardless of their order.
+fn diff(recorded: &[String], replayed: &[String]) -> Vec<String> {
+    let mut differences = vec![];
+
+    let mut replayed_responses = HashMap::new();
+    let mut replayed_others = vec![];
+    for msg in replayed {
+        match ServerMessage::parse(msg) {
+            ServerMessage::Response(id, result) => {
+                replayed_responses.insert(id, result);
+            }
+            other => replayed_others.push((other, msg)),
+        }
+    }
+
+    for msg in recorded {
+        match ServerMessage::parse(msg) {
+            ServerMessage::Response(id, result) => match replayed_responses.remove(&id) {
+                Some(ref replayed) if *replayed == result => {}
+                Some(replayed) => differences.push(format!(
+                    "Response to request {} differs:\n  recorded: {}\n  replayed: {}",
+                    id, result, replayed
+                )),
+                None => differences.push(format!("Missing response to request {}: {}", id, result)),
+            },
+            other => match replayed_others.iter().position(|(m, _)| *m == other) {
+                Some(i) => {
+                    replayed_others.remove(i);
+                }
+                None => differences.push(format!("Missing message: {}", msg)),
+            },
+        }
+    }
+
+    let mut unexpected: Vec<_> = replayed_responses
+        .into_iter()
+        .map(|(id, result)| format!("Unexpected response to request {}: {}", id, result))
+        .collect();
+    unexpected.sort();
+    differences.extend(unexpected);
+    differences.extend(
+        replayed_others
+            .into_iter()
+            .map(|(_, msg)| format!("Unexpected message: {}", msg)),
+    );
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(messages: &[&str]) -> Vec<String> {
+        messages.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_ignores_order_of_notifications_and_server_request_ids() {
+        let recorded = strings(&[
+            r#"{"jsonrpc":"2.0","method":"window/progress","params":{"done":true}}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"client/registerCapability","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"result":null}"#,
+        ]);
+        let replayed = strings(&[
+            r#"{"jsonrpc":"2.0","id":3,"result":null}"#,
+            r#"{"jsonrpc":"2.0","id":7,"method":"client/registerCapability","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"window/progress","params":{"done":true}}"#,
+        ]);
+
+        assert_eq!(diff(&recorded, &replayed), Vec::<String>::new());
+    }
+
+    #[test]
+    fn diff_reports_changed_missing_and_unexpected_responses() {
+        let recorded = strings(&[
+            r#"{"jsonrpc":"2.0","id":1,"result":[]}"#,
+            r#"{"jsonrpc":"2.0","id":2,"result":null}"#,
+        ]);
+        let replayed = strings(&[
+            r#"{"jsonrpc":"2.0","id":1,"result":[1]}"#,
+            r#"{"jsonrpc":"2.0","id":3,"result":null}"#,
+        ]);
+
+        let differences = diff(&recorded, &replayed);
+        assert_eq!(differences.len(), 3);
+        assert!(differences[0].starts_with("Response to request 1 differs"));
+        assert!(differences[1].starts_with("Missing response to request 2"));
+        assert!(differences[2].starts_with("Unexpected response to request 3"));
+    }
+
+    #[test]
+    fn recorded_message_round_trip() {
+        let line = serde_json::to_string(&RecordedMessage {
+            time: 42,
+            direction: Direction::In,
+            message: r#"{"jsonrpc":"2.0"}"#.to_owned(),
+        }).unwrap();
+        assert_eq!(
+            line,
+            r#"{"time":42,"direction":"in","message":"{\"jsonrpc\":\"2.0\"}"}"#
+        );
+
+        let msg: RecordedMessage = serde_json::from_str(&line).unwrap();
+        assert_eq!(msg.direction, Direction::In);
+        assert_eq!(msg.message, r#"{"jsonrpc":"2.0"}"#);
+    }
+}
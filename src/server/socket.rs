@@ -0,0 +1,231 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Socket transports for the language server, so that a client can talk to an
+//! RLS that outlives it (or runs on another machine) rather than one it spawned
+//! and talks to over stdio.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// An address the RLS can listen on, or connect to.
+///
+/// Written as `tcp:PORT` (bound to localhost), `tcp:HOST:PORT` or `unix:PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Address, String> {
+        if s.starts_with("tcp:") {
+            let addr = &s["tcp:".len()..];
+            if let Ok(port) = addr.parse::<u16>() {
+                return Ok(Address::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port))));
+            }
+            // Resolve the host, so that names like `localhost` work as well as
+            // IP addresses.
+            return addr
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .map(Address::Tcp)
+                .ok_or_else(|| {
+                    format!("Invalid TCP address '{}', expected PORT or HOST:PORT", addr)
+                });
+        }
+
+        if s.starts_with("unix:") {
+            let path = &s["unix:".len()..];
+            if path.is_empty() {
+                return Err("Missing path for Unix domain socket".to_owned());
+            }
+            #[cfg(unix)]
+            return Ok(Address::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err("Unix domain sockets are not supported on this platform".to_owned());
+        }
+
+        Err(format!(
+            "Invalid address '{}', expected 'tcp:PORT', 'tcp:HOST:PORT' or 'unix:PATH'",
+            s
+        ))
+    }
+}
+
+impl Address {
+    /// Whether only clients on this machine can connect to the address.
+    ///
+    /// The server doesn't authenticate its clients, and a client can make it
+    /// edit files (through workspace edits), so it shouldn't be reachable from
+    /// other machines unless that's explicitly asked for.
+    pub fn is_local(&self) -> bool {
+        match *self {
+            Address::Tcp(ref addr) => addr.ip().is_loopback(),
+            #[cfg(unix)]
+            Address::Unix(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Address::Tcp(ref addr) => write!(f, "tcp:{}", addr),
+            #[cfg(unix)]
+            Address::Unix(ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Accepts client connections on an `Address`.
+pub(super) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub(super) fn bind(address: &Address) -> io::Result<Listener> {
+        match *address {
+            Address::Tcp(ref addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            #[cfg(unix)]
+            Address::Unix(ref path) => {
+                // A previous server which was killed leaves its socket file
+                // behind, which would make binding fail. Only remove it if
+                // nobody is listening on it any more.
+                if path.exists() && UnixStream::connect(path).is_err() {
+                    std::fs::remove_file(path)?;
+                }
+                UnixListener::bind(path).map(Listener::Unix)
+            }
+        }
+    }
+
+    /// Blocks until the next client connects.
+    pub(super) fn accept(&self) -> io::Result<Stream> {
+        match *self {
+            Listener::Tcp(ref listener) => listener.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(ref listener) => listener.accept().map(|(s, _)| Stream::Unix(s)),
+        }
+    }
+}
+
+/// A connection between a client and the RLS.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Connect to an RLS listening on `address`.
+    pub fn connect(address: &Address) -> io::Result<Stream> {
+        match *address {
+            Address::Tcp(ref addr) => TcpStream::connect(addr).map(Stream::Tcp),
+            #[cfg(unix)]
+            Address::Unix(ref path) => UnixStream::connect(path).map(Stream::Unix),
+        }
+    }
+
+    /// Create an independent handle to the same connection, so that it can be
+    /// read from and written to on different threads.
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match *self {
+            Stream::Tcp(ref s) => s.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(ref s) => s.try_clone().map(Stream::Unix),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_address() {
+        assert_eq!(
+            "tcp:9000".parse(),
+            Ok(Address::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, 9000))))
+        );
+        assert_eq!(
+            "tcp:0.0.0.0:9000".parse(),
+            Ok(Address::Tcp(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 9000))))
+        );
+        assert!(is_local("tcp:localhost:9000"));
+        assert!("tcp:".parse::<Address>().is_err());
+        assert!("tcp:localhost".parse::<Address>().is_err());
+        assert!("unix:".parse::<Address>().is_err());
+        assert!("9000".parse::<Address>().is_err());
+    }
+
+    fn is_local(address: &str) -> bool {
+        address.parse::<Address>().unwrap().is_local()
+    }
+
+    #[test]
+    fn local_address() {
+        assert!(is_local("tcp:9000"));
+        assert!(is_local("tcp:127.0.0.1:9000"));
+        assert!(is_local("tcp:[::1]:9000"));
+        assert!(!is_local("tcp:0.0.0.0:9000"));
+        assert!(!is_local("tcp:192.168.0.1:9000"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix_address() {
+        assert_eq!(
+            "unix:/tmp/rls.sock".parse(),
+            Ok(Address::Unix(PathBuf::from("/tmp/rls.sock")))
+        );
+    }
+}
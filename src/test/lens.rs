@@ -1,6 +1,6 @@
 use std::path::Path;
 
-use languageserver_types::{CodeLensParams, TextDocumentIdentifier};
+use languageserver_types::{CodeLens, CodeLensParams, Position, Range, TextDocumentIdentifier};
 use serde_json;
 use url::Url;
 
@@ -32,6 +32,8 @@ fn test_lens_run() {
             Some(InitializationOptions {
                 omit_init_build: false,
                 cmd_run: true,
+                cmd_debug: false,
+                cmd_show_references: false,
             }),
         ).to_string(),
         request::<requests::CodeLensRequest>(
@@ -52,7 +54,7 @@ fn test_lens_run() {
         &mut server,
         results.clone(),
         &ExpectedMessage::new(Some(0))
-            .expect_contains(r#""codeLensProvider":{"resolveProvider":false}"#),
+            .expect_contains(r#""codeLensProvider":{"resolveProvider":true}"#),
     );
 
     expect_series(&mut server, results.clone(), vec!["progress"]);
@@ -64,14 +66,38 @@ fn test_lens_run() {
     server.wait_for_concurrent_jobs();
     let result: serde_json::Value =
         serde_json::from_str(&results.lock().unwrap().remove(0)).unwrap();
+    // Reference lenses are resolved later, so don't have a command yet.
+    let run_lenses: Vec<_> = result["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|lens| !lens["command"].is_null())
+        .cloned()
+        .collect();
     compare_json(
-        result.get("result").unwrap(),
+        &serde_json::Value::Array(run_lenses),
         r#"[{
+            "command": {
+              "command": "rls.run",
+              "title": "Run",
+              "arguments": [{
+                  "kind": "run",
+                  "args": [ "run", "-p", "run", "--bin", "run" ],
+                  "binary": "cargo",
+                  "env": { "RUST_BACKTRACE": "short" }
+              }]
+            },
+            "range": {
+              "start": { "character": 7, "line": 10 },
+              "end": { "character": 11, "line": 10 }
+            }
+        }, {
             "command": {
               "command": "rls.run",
               "title": "Run test",
               "arguments": [{
-                  "args": [ "test", "--", "--nocapture", "test_foo" ],
+                  "kind": "run",
+                  "args": [ "test", "-p", "run", "--bin", "run", "--", "--nocapture", "--exact", "test_foo" ],
                   "binary": "cargo",
                   "env": { "RUST_BACKTRACE": "short" }
               }]
@@ -83,3 +109,86 @@ fn test_lens_run() {
         }]"#,
     )
 }
+
+#[test]
+fn test_lens_references() {
+    let mut env = Environment::new("lens_run");
+
+    let source_file_path = Path::new("src").join("main.rs");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+    let root_path = root_path.as_os_str().to_str().map(|x| x.to_owned());
+    let url = Url::from_file_path(env.cache.abs_path(&source_file_path))
+        .expect("couldn't convert file path to URL");
+    let main = Range::new(Position::new(10, 7), Position::new(10, 11));
+    let messages = vec![
+        initialize_with_opts(
+            0,
+            root_path,
+            Some(InitializationOptions {
+                omit_init_build: false,
+                cmd_run: true,
+                cmd_debug: false,
+                cmd_show_references: true,
+            }),
+        ).to_string(),
+        request::<requests::CodeLensRequest>(
+            100,
+            CodeLensParams {
+                text_document: TextDocumentIdentifier::new(url.clone()),
+            },
+        ).to_string(),
+        request::<requests::CodeLensResolve>(
+            200,
+            CodeLens {
+                range: main,
+                command: None,
+                data: Some(serde_json::json!({
+                    "kind": "references",
+                    "uri": url,
+                    "position": main.start,
+                })),
+            },
+        ).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    expect_message(&mut server, results.clone(), &ExpectedMessage::new(Some(0)));
+    expect_series(&mut server, results.clone(), vec!["progress"]);
+
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    server.wait_for_concurrent_jobs();
+    let result: serde_json::Value =
+        serde_json::from_str(&results.lock().unwrap().remove(0)).unwrap();
+    let lens = result["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|lens| {
+            lens["range"] == serde_json::to_value(main).unwrap()
+                && lens["data"]["kind"] == "references"
+        })
+        .expect("no reference lens for `main`")
+        .clone();
+    assert!(lens["command"].is_null());
+
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    server.wait_for_concurrent_jobs();
+    let result: serde_json::Value =
+        serde_json::from_str(&results.lock().unwrap().remove(0)).unwrap();
+    // `main` is never referenced, so there's nothing to show when clicked.
+    let command = &result["result"]["command"];
+    assert_eq!(command["title"], "0 references");
+    assert_eq!(command["command"], "");
+}
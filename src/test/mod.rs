@@ -126,6 +126,83 @@ fn test_shutdown() {
     expect_message(&mut server, results, &ExpectedMessage::new(Some(1)));
 }
 
+#[test]
+fn test_cancel_queued_request() {
+    let mut env = Environment::new("common");
+
+    let source_file_path = Path::new("src").join("main.rs");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+    let url = Url::from_file_path(env.cache.abs_path(&source_file_path))
+        .expect("couldn't convert file path to URL");
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        request::<requests::WorkspaceSymbol>(
+            100,
+            WorkspaceSymbolParams {
+                query: "foo".to_owned(),
+            },
+        ).to_string(),
+        // Would send a `workspace/applyEdit` request to the client if it ran
+        request::<requests::ExecuteCommand>(
+            200,
+            ExecuteCommandParams {
+                command: format!("rls.applySuggestion-{}", ::std::process::id()),
+                arguments: vec![
+                    serde_json::to_value(&Location {
+                        uri: url,
+                        range: Range::new(Position::new(12, 0), Position::new(12, 0)),
+                    }).unwrap(),
+                    serde_json::to_value("// applied").unwrap(),
+                ],
+            },
+        ).to_string(),
+        notification::<notifications::Cancel>(CancelParams {
+            id: NumberOrString::Number(200),
+        }).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    expect_message(
+        &mut server,
+        results.clone(),
+        ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+    );
+    expect_series(&mut server, results.clone(), vec!["progress"]);
+
+    {
+        // Hold on to the output, so that the dispatch worker blocks responding
+        // to the first request and the second one is still queued when it's
+        // cancelled.
+        let _output = results.lock().unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                ls_server::LsService::handle_message(&mut server),
+                ls_server::ServerStateChange::Continue
+            );
+        }
+    }
+    server.wait_for_concurrent_jobs();
+
+    let responses: Vec<Value> = results
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|msg| serde_json::from_str(msg).unwrap())
+        .collect();
+    let response = |id: u64| responses.iter().find(|msg| msg["id"] == id).unwrap();
+    assert!(response(100)["result"].is_array());
+    assert_eq!(response(200)["error"]["code"], -32800);
+    assert!(responses
+        .iter()
+        .all(|msg| msg["method"] != "workspace/applyEdit"));
+}
+
 #[test]
 fn test_goto_def() {
     let mut env = Environment::new("common");
@@ -176,6 +253,55 @@ fn test_goto_def() {
     );
 }
 
+#[test]
+fn test_goto_type_def() {
+    let mut env = Environment::new("common");
+
+    let source_file_path = Path::new("src").join("main.rs");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+    let url = Url::from_file_path(env.cache.abs_path(&source_file_path))
+        .expect("couldn't convert file path to URL");
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        request::<requests::TypeDefinition>(
+            11,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier::new(url),
+                position: env
+                    .cache
+                    .mk_ls_position(src(&source_file_path, 25, "bar2")),
+            },
+        ).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+
+    expect_message(
+        &mut server,
+        results.clone(),
+        ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+    );
+
+    expect_series(&mut server, results.clone(), vec!["progress"]);
+
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    expect_message(
+        &mut server,
+        results,
+        ExpectedMessage::new(Some(11)).expect_contains(r#""start":{"line":9,"character":7}"#),
+    );
+}
+
 #[test]
 fn test_hover() {
     let mut env = Environment::new("common");
@@ -1095,6 +1221,8 @@ fn test_omit_init_build() {
     let init_options = Some(InitializationOptions {
         omit_init_build: true,
         cmd_run: true,
+        cmd_debug: false,
+        cmd_show_references: false,
     });
     let initialize = initialize_with_opts(0, root_path, init_options);
 
@@ -1593,6 +1721,112 @@ fn test_deglob() {
     );
 }
 
+#[test]
+fn test_deglob_code_action_literal() {
+    let mut env = Environment::new("deglob");
+
+    let source_file_path = Path::new("src").join("main.rs");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+    let url = Url::from_file_path(env.cache.abs_path(&source_file_path))
+        .expect("couldn't convert file path to URL");
+    let mut init = initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned()));
+    init.params.capabilities.text_document = Some(
+        serde_json::from_value(serde_json::json!({
+            "codeAction": {
+                "codeActionLiteralSupport": {
+                    "codeActionKind": { "valueSet": ["quickfix", "refactor", "source"] }
+                }
+            }
+        })).unwrap(),
+    );
+    let messages = vec![
+        init.to_string(),
+        request::<requests::CodeAction>(
+            100,
+            CodeActionParams {
+                text_document: TextDocumentIdentifier::new(url.clone()),
+                range: env.cache.mk_ls_range_from_line(12),
+                context: CodeActionContext {
+                    diagnostics: vec![],
+                    only: None,
+                },
+            },
+        ).to_string(),
+        // Deglobbing is a refactoring, not a source action
+        request::<requests::CodeAction>(
+            200,
+            CodeActionParams {
+                text_document: TextDocumentIdentifier::new(url),
+                range: env.cache.mk_ls_range_from_line(12),
+                context: CodeActionContext {
+                    diagnostics: vec![],
+                    only: Some(vec!["source".to_owned()]),
+                },
+            },
+        ).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    expect_message(
+        &mut server,
+        results.clone(),
+        ExpectedMessage::new(Some(0)).expect_contains("rls.deglobImports-"),
+    );
+
+    expect_series(&mut server, results.clone(), vec!["progress"]);
+
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    {
+        server.wait_for_concurrent_jobs();
+        let response: Value = serde_json::from_str(&results.lock().unwrap().remove(0)).unwrap();
+        assert_eq!(response["id"], 100);
+        let action = &response["result"][0];
+        assert_eq!(action["title"], "Deglob import");
+        assert_eq!(action["kind"], "refactor.rewrite");
+        assert!(action["command"].is_null());
+        let (key, changes) = action["edit"]["changes"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap();
+        assert!(key.ends_with("deglob/src/main.rs"));
+        let change = &changes[0];
+        assert_eq!(change["range"]["start"]["line"], 12);
+        assert_eq!(change["range"]["start"]["character"], 13);
+        assert_eq!(change["range"]["end"]["line"], 12);
+        assert_eq!(change["range"]["end"]["character"], 14);
+        let mut imports: Vec<_> = change["newText"]
+            .as_str()
+            .expect("newText missing")
+            .trim_matches('{')
+            .trim_matches('}')
+            .split(", ")
+            .collect();
+        imports.sort();
+        assert_eq!(imports, vec!["Stdin", "Stdout"]);
+    }
+
+    assert_eq!(
+        ls_server::LsService::handle_message(&mut server),
+        ls_server::ServerStateChange::Continue
+    );
+    expect_message(
+        &mut server,
+        results,
+        ExpectedMessage::new(Some(200)).expect_contains(r#""result":[]"#),
+    );
+}
+
 #[test]
 fn test_all_targets() {
     let mut env = Environment::new("bin_lib");